@@ -0,0 +1,103 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+    util::{ListUid, UserUid},
+    webhooks::ChangeKind,
+};
+
+// How many events are kept for each list before the oldest are dropped
+const EVENTS_PER_LIST: usize = 100;
+
+/// Something a user did to a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Activity {
+    Created,
+    Renamed,
+    TaskSchemaChanged,
+    TemplateChanged,
+    TaskAdded,
+    TaskUpdated,
+    TasksCompleted,
+    TaskDeleted,
+    Shared,
+    Unshared,
+    Merged,
+    Transferred,
+    Deleted,
+}
+
+impl Activity {
+    /// How this activity is reported to webhooks
+    pub fn change_kind(self) -> ChangeKind {
+        match self {
+            Activity::Created => ChangeKind::Insert,
+            Activity::Deleted => ChangeKind::Delete,
+            _ => ChangeKind::Update,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityEvent {
+    pub activity: Activity,
+    pub by: UserUid,
+    /// Milliseconds since the Unix epoch
+    pub at: u128,
+}
+
+/// The recent activity on each list. Like presence, this lives only in the server's memory
+/// and is never written out with the entities.
+#[derive(Debug, Default)]
+pub struct ActivityLog {
+    lists: HashMap<ListUid, VecDeque<ActivityEvent>>,
+}
+
+impl ActivityLog {
+    pub fn record(&mut self, list: ListUid, activity: Activity, by: UserUid) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let events = self.lists.entry(list).or_default();
+        if events.len() == EVENTS_PER_LIST {
+            events.pop_front();
+        }
+        events.push_back(ActivityEvent { activity, by, at });
+    }
+
+    /// The `limit` most recent events on `list`, newest first
+    pub fn recent(&self, list: &ListUid, limit: usize) -> Vec<ActivityEvent> {
+        self.lists
+            .get(list)
+            .into_iter()
+            .flat_map(|events| events.iter().rev())
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Drops the activity on `list`, e.g. once it has been deleted
+    pub fn forget(&mut self, list: &ListUid) {
+        self.lists.remove(list);
+    }
+}
@@ -14,18 +14,35 @@
  * limitations under the License.
  */
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
 
 use serde::{Deserialize, Serialize, Serializer};
 use tokio::sync::{mpsc, oneshot};
 use warp::Filter;
 
 use crate::{
-    context::{AppQuery, AppQueryKind, AppResponse, Error},
-    objects::{List, TaskState},
-    util::{EntityUid, ListUid, Lists, UserOrTeamUid, UserUid},
+    activity::ActivityEvent,
+    context::{
+        AdminStats, AppQuery, AppQueryKind, AppResponse, AuthorizationInfo, Capabilities,
+        ContextBuilder, ContextRequirement, DeletionImpact, DeniedAction, Error, ListLookup,
+        ReproCase,
+    },
+    entitystore::{
+        AclSnapshot, EntityType, GrantingTeams, ListsByOwner, Memberships, OffboardReport,
+        StoreEntity, UserDataExport,
+    },
+    objects::{List, ListSummary, TaskSchema, TaskState, TasksPage},
+    tokens::TokenCapability,
+    util::{EntityUid, Fingerprint, ListUid, Lists, TeamUid, UserOrTeamUid, UserUid},
+    versionvector::VersionVector,
 };
 
+#[cfg(feature = "partial-eval")]
+use crate::context::PartialDecision;
+
 type AppChannel = mpsc::Sender<AppQuery>;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -40,10 +57,79 @@ impl From<GetList> for AppQueryKind {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetManyLists {
+    pub uid: UserUid,
+    pub lists: Vec<ListUid>,
+}
+
+impl From<GetManyLists> for AppQueryKind {
+    fn from(v: GetManyLists) -> AppQueryKind {
+        AppQueryKind::GetManyLists(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IssueListToken {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub capabilities: HashSet<TokenCapability>,
+    // How long the token lasts, an hour by default
+    pub ttl_secs: Option<u64>,
+}
+
+impl From<IssueListToken> for AppQueryKind {
+    fn from(v: IssueListToken) -> AppQueryKind {
+        AppQueryKind::IssueListToken(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetListWithToken {
+    pub token: String,
+}
+
+impl From<GetListWithToken> for AppQueryKind {
+    fn from(v: GetListWithToken) -> AppQueryKind {
+        AppQueryKind::GetListWithToken(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateTaskWithToken {
+    pub token: String,
+    pub name: String,
+}
+
+impl From<CreateTaskWithToken> for AppQueryKind {
+    fn from(v: CreateTaskWithToken) -> AppQueryKind {
+        AppQueryKind::CreateTaskWithToken(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetTasksPage {
+    pub uid: UserUid,
+    pub list: ListUid,
+    // The `next` cursor of the previous page, if any
+    pub after: Option<i64>,
+    // How many tasks to return, 50 by default
+    pub limit: Option<usize>,
+}
+
+impl From<GetTasksPage> for AppQueryKind {
+    fn from(v: GetTasksPage) -> AppQueryKind {
+        AppQueryKind::GetTasksPage(v)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CreateList {
     pub uid: UserUid,
     pub name: String,
+    // Whether to share the new list with each of the creator's teams that has a default share
+    #[serde(default)]
+    pub apply_team_defaults: bool,
 }
 
 impl From<CreateList> for AppQueryKind {
@@ -57,6 +143,10 @@ pub struct UpdateList {
     pub uid: UserUid,
     pub list: ListUid,
     pub name: String,
+    // The version of the list the previous update gave, if the update should fail when someone
+    // else has updated the list since
+    #[serde(default)]
+    pub version: Option<VersionVector>,
 }
 
 impl From<UpdateList> for AppQueryKind {
@@ -65,6 +155,71 @@ impl From<UpdateList> for AppQueryKind {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeLists {
+    pub uid: UserUid,
+    pub into: ListUid,
+    pub from: ListUid,
+}
+
+impl From<MergeLists> for AppQueryKind {
+    fn from(v: MergeLists) -> AppQueryKind {
+        AppQueryKind::MergeLists(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferList {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub new_owner: UserUid,
+}
+
+impl From<TransferList> for AppQueryKind {
+    fn from(v: TransferList) -> AppQueryKind {
+        AppQueryKind::TransferList(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetTaskSchema {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub schema: Option<TaskSchema>,
+}
+
+impl From<SetTaskSchema> for AppQueryKind {
+    fn from(v: SetTaskSchema) -> AppQueryKind {
+        AppQueryKind::SetTaskSchema(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetTemplate {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub is_template: bool,
+}
+
+impl From<SetTemplate> for AppQueryKind {
+    fn from(v: SetTemplate) -> AppQueryKind {
+        AppQueryKind::SetTemplate(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegisterWebhook {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub url: String,
+}
+
+impl From<RegisterWebhook> for AppQueryKind {
+    fn from(v: RegisterWebhook) -> AppQueryKind {
+        AppQueryKind::RegisterWebhook(v)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AddShare {
     pub uid: UserUid,
@@ -90,6 +245,9 @@ pub struct DeleteShare {
     pub uid: UserUid,
     pub list: ListUid,
     pub unshare_with: UserOrTeamUid,
+    // Each role is a separate linked policy. Without templates, `unshare_with` loses both roles
+    // at once, so clients may leave this out.
+    #[cfg(feature = "use-templates")]
     pub role: ShareRole,
 }
 
@@ -114,6 +272,9 @@ impl From<DeleteList> for AppQueryKind {
 #[derive(Debug, Clone, Deserialize)]
 pub struct GetLists {
     pub uid: UserUid,
+    // Gives the template lists instead of the others
+    #[serde(default)]
+    pub templates: bool,
 }
 
 impl From<GetLists> for AppQueryKind {
@@ -122,6 +283,235 @@ impl From<GetLists> for AppQueryKind {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReorderLists {
+    pub uid: UserUid,
+    pub order: Vec<ListUid>,
+}
+
+impl From<ReorderLists> for AppQueryKind {
+    fn from(v: ReorderLists) -> AppQueryKind {
+        AppQueryKind::ReorderLists(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetActivity {
+    pub uid: UserUid,
+    pub list: ListUid,
+    // How many events to return, 20 by default
+    pub limit: Option<usize>,
+}
+
+impl From<GetActivity> for AppQueryKind {
+    fn from(v: GetActivity) -> AppQueryKind {
+        AppQueryKind::GetActivity(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetDuplicateLists {
+    pub uid: UserUid,
+}
+
+impl From<GetDuplicateLists> for AppQueryKind {
+    fn from(v: GetDuplicateLists) -> AppQueryKind {
+        AppQueryKind::GetDuplicateLists(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetListsByOwner {
+    pub uid: UserUid,
+}
+
+impl From<GetListsByOwner> for AppQueryKind {
+    fn from(v: GetListsByOwner) -> AppQueryKind {
+        AppQueryKind::GetListsByOwner(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetMemberships {
+    pub uid: UserUid,
+}
+
+impl From<GetMemberships> for AppQueryKind {
+    fn from(v: GetMemberships) -> AppQueryKind {
+        AppQueryKind::GetMemberships(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetGrantingTeams {
+    pub uid: UserUid,
+    pub list: ListUid,
+}
+
+impl From<GetGrantingTeams> for AppQueryKind {
+    fn from(v: GetGrantingTeams) -> AppQueryKind {
+        AppQueryKind::GetGrantingTeams(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportUserData {
+    pub uid: UserUid,
+    // The user whose data to export, who must be `uid`
+    pub user: UserUid,
+}
+
+impl From<ExportUserData> for AppQueryKind {
+    fn from(v: ExportUserData) -> AppQueryKind {
+        AppQueryKind::ExportUserData(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetCapabilities {
+    pub uid: UserUid,
+}
+
+impl From<GetCapabilities> for AppQueryKind {
+    fn from(v: GetCapabilities) -> AppQueryKind {
+        AppQueryKind::GetCapabilities(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetListActions {
+    pub uid: UserUid,
+    pub list: ListUid,
+}
+
+impl From<GetListActions> for AppQueryKind {
+    fn from(v: GetListActions) -> AppQueryKind {
+        AppQueryKind::GetListActions(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetTrace {
+    pub uid: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+}
+
+impl From<GetTrace> for AppQueryKind {
+    fn from(v: GetTrace) -> AppQueryKind {
+        AppQueryKind::GetTrace(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckAccess {
+    pub uid: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+    // Teams `uid` is a member of for this check only, e.g. from a token's groups claim
+    #[serde(default)]
+    pub groups: Vec<TeamUid>,
+}
+
+impl From<CheckAccess> for AppQueryKind {
+    fn from(v: CheckAccess) -> AppQueryKind {
+        AppQueryKind::CheckAccess(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetRequirements {
+    pub uid: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+}
+
+impl From<GetRequirements> for AppQueryKind {
+    fn from(v: GetRequirements) -> AppQueryKind {
+        AppQueryKind::GetRequirements(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetGrantingPolicies {
+    pub uid: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+}
+
+impl From<GetGrantingPolicies> for AppQueryKind {
+    fn from(v: GetGrantingPolicies) -> AppQueryKind {
+        AppQueryKind::GetGrantingPolicies(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetDeniedActions {
+    pub uid: UserUid,
+    pub resource: EntityUid,
+}
+
+impl From<GetDeniedActions> for AppQueryKind {
+    fn from(v: GetDeniedActions) -> AppQueryKind {
+        AppQueryKind::GetDeniedActions(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetListSummaries {
+    pub uid: UserUid,
+}
+
+impl From<GetListSummaries> for AppQueryKind {
+    fn from(v: GetListSummaries) -> AppQueryKind {
+        AppQueryKind::GetListSummaries(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TouchPresence {
+    pub uid: UserUid,
+    pub list: ListUid,
+}
+
+impl From<TouchPresence> for AppQueryKind {
+    fn from(v: TouchPresence) -> AppQueryKind {
+        AppQueryKind::TouchPresence(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetPresence {
+    pub uid: UserUid,
+    pub list: ListUid,
+}
+
+impl From<GetPresence> for AppQueryKind {
+    fn from(v: GetPresence) -> AppQueryKind {
+        AppQueryKind::GetPresence(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompleteTasks {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub completed: bool,
+    // Only tasks whose name contains this are affected; all tasks are if it's absent
+    #[serde(default)]
+    pub name_contains: Option<String>,
+}
+
+impl From<CompleteTasks> for AppQueryKind {
+    fn from(v: CompleteTasks) -> AppQueryKind {
+        AppQueryKind::CompleteTasks(v)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct UpdateTask {
     pub uid: UserUid,
@@ -142,6 +532,8 @@ pub struct CreateTask {
     pub uid: UserUid,
     pub list: ListUid,
     pub name: String,
+    #[serde(default)]
+    pub fields: HashMap<String, serde_json::Value>,
 }
 
 impl From<CreateTask> for AppQueryKind {
@@ -163,6 +555,280 @@ impl From<DeleteTask> for AppQueryKind {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateTeam {
+    pub uid: UserUid,
+    pub members: Vec<UserOrTeamUid>,
+}
+
+impl From<CreateTeam> for AppQueryKind {
+    fn from(v: CreateTeam) -> AppQueryKind {
+        AppQueryKind::CreateTeam(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OffboardUser {
+    pub uid: UserUid,
+    pub user: UserUid,
+    pub successor: UserUid,
+}
+
+impl From<OffboardUser> for AppQueryKind {
+    fn from(v: OffboardUser) -> AppQueryKind {
+        AppQueryKind::OffboardUser(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncGroup {
+    pub uid: UserUid,
+    // The name of the external group, which the team mirroring it takes
+    pub name: String,
+}
+
+impl From<SyncGroup> for AppQueryKind {
+    fn from(v: SyncGroup) -> AppQueryKind {
+        AppQueryKind::SyncGroup(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CloneTeam {
+    pub uid: UserUid,
+    pub team: TeamUid,
+}
+
+impl From<CloneTeam> for AppQueryKind {
+    fn from(v: CloneTeam) -> AppQueryKind {
+        AppQueryKind::CloneTeam(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetTeamAncestors {
+    pub uid: UserUid,
+    pub team: TeamUid,
+}
+
+impl From<GetTeamAncestors> for AppQueryKind {
+    fn from(v: GetTeamAncestors) -> AppQueryKind {
+        AppQueryKind::GetTeamAncestors(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeedEntities {
+    pub uid: UserUid,
+    pub entities: Vec<StoreEntity>,
+}
+
+impl From<SeedEntities> for AppQueryKind {
+    fn from(v: SeedEntities) -> AppQueryKind {
+        AppQueryKind::SeedEntities(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApp {
+    pub uid: UserUid,
+    pub app: EntityUid,
+}
+
+impl From<CreateApp> for AppQueryKind {
+    fn from(v: CreateApp) -> AppQueryKind {
+        AppQueryKind::CreateApp(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetAppLists {
+    pub uid: UserUid,
+    pub app: EntityUid,
+}
+
+impl From<GetAppLists> for AppQueryKind {
+    fn from(v: GetAppLists) -> AppQueryKind {
+        AppQueryKind::GetAppLists(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FindEntities {
+    pub uid: UserUid,
+    #[serde(rename = "type")]
+    pub ty: EntityType,
+    pub attr: String,
+    pub value: serde_json::Value,
+}
+
+impl From<FindEntities> for AppQueryKind {
+    fn from(v: FindEntities) -> AppQueryKind {
+        AppQueryKind::FindEntities(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetDeletionImpact {
+    pub uid: UserUid,
+    pub entity: EntityUid,
+}
+
+impl From<GetDeletionImpact> for AppQueryKind {
+    fn from(v: GetDeletionImpact) -> AppQueryKind {
+        AppQueryKind::GetDeletionImpact(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetFingerprint {
+    pub uid: UserUid,
+}
+
+impl From<GetFingerprint> for AppQueryKind {
+    fn from(v: GetFingerprint) -> AppQueryKind {
+        AppQueryKind::GetFingerprint(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerifySync {
+    pub uid: UserUid,
+    pub fingerprint: Fingerprint,
+}
+
+impl From<VerifySync> for AppQueryKind {
+    fn from(v: VerifySync) -> AppQueryKind {
+        AppQueryKind::VerifySync(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportEntities {
+    pub uid: UserUid,
+}
+
+impl From<ExportEntities> for AppQueryKind {
+    fn from(v: ExportEntities) -> AppQueryKind {
+        AppQueryKind::ExportEntities(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveEntity {
+    pub uid: UserUid,
+    pub entity: EntityUid,
+}
+
+impl From<ArchiveEntity> for AppQueryKind {
+    fn from(v: ArchiveEntity) -> AppQueryKind {
+        AppQueryKind::ArchiveEntity(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestoreEntity {
+    pub uid: UserUid,
+    pub entity: EntityUid,
+}
+
+impl From<RestoreEntity> for AppQueryKind {
+    fn from(v: RestoreEntity) -> AppQueryKind {
+        AppQueryKind::RestoreEntity(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetReproCase {
+    pub uid: UserUid,
+    pub principal: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+}
+
+impl From<GetReproCase> for AppQueryKind {
+    fn from(v: GetReproCase) -> AppQueryKind {
+        AppQueryKind::GetReproCase(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetStats {
+    pub uid: UserUid,
+}
+
+impl From<GetStats> for AppQueryKind {
+    fn from(v: GetStats) -> AppQueryKind {
+        AppQueryKind::GetStats(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportAcls {
+    pub uid: UserUid,
+}
+
+impl From<ExportAcls> for AppQueryKind {
+    fn from(v: ExportAcls) -> AppQueryKind {
+        AppQueryKind::ExportAcls(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportAcls {
+    pub uid: UserUid,
+    pub snapshot: AclSnapshot,
+}
+
+impl From<ImportAcls> for AppQueryKind {
+    fn from(v: ImportAcls) -> AppQueryKind {
+        AppQueryKind::ImportAcls(v)
+    }
+}
+
+#[cfg(feature = "partial-eval")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetPartialDecision {
+    pub uid: UserUid,
+    // The name of the action, e.g. `GetList`
+    pub action: String,
+    pub resource: EntityUid,
+}
+
+#[cfg(feature = "partial-eval")]
+impl From<GetPartialDecision> for AppQueryKind {
+    fn from(v: GetPartialDecision) -> AppQueryKind {
+        AppQueryKind::GetPartialDecision(v)
+    }
+}
+
+#[cfg(feature = "sealed-fields")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportSealed {
+    pub uid: UserUid,
+}
+
+#[cfg(feature = "sealed-fields")]
+impl From<ExportSealed> for AppQueryKind {
+    fn from(v: ExportSealed) -> AppQueryKind {
+        AppQueryKind::ExportSealed(v)
+    }
+}
+
+#[cfg(feature = "undo")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Undo {
+    pub uid: UserUid,
+}
+
+#[cfg(feature = "undo")]
+impl From<Undo> for AppQueryKind {
+    fn from(v: Undo) -> AppQueryKind {
+        AppQueryKind::Undo(v)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Empty {
     message: &'static str,
@@ -183,6 +849,11 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                 .and(with_app(chan.clone()))
                 .and(warp::query::query::<GetList>())
                 .and_then(simple_query::<GetList, List>))
+            .or(warp::path("tasks")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetTasksPage>())
+                .and_then(simple_query::<GetTasksPage, TasksPage>))
             .or(warp::path("create")
                 .and(warp::post())
                 .and(with_app(chan.clone()))
@@ -192,13 +863,83 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                 .and(warp::post())
                 .and(with_app(chan.clone()))
                 .and(warp::body::json())
-                .and_then(simple_query::<UpdateList, Empty>))
+                .and_then(simple_query::<UpdateList, VersionVector>))
             .or(warp::path("delete")
                 .and(warp::delete())
                 .and(with_app(chan.clone()))
                 .and(warp::body::json())
-                .and_then(simple_query::<DeleteList, Empty>)),
+                .and_then(simple_query::<DeleteList, Empty>))
+            .or(warp::path("actions")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetListActions>())
+                .and_then(simple_query::<GetListActions, Vec<&'static str>>))
+            .or(warp::path("merge")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<MergeLists, Empty>))
+            .or(warp::path("transfer")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<TransferList, Empty>))
+            .or(warp::path("task_schema")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<SetTaskSchema, Empty>))
+            .or(warp::path("template")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<SetTemplate, Empty>))
+            .or(warp::path("webhook")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<RegisterWebhook, Empty>))
+            .or(warp::path("presence").and(
+                (warp::post()
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<TouchPresence, Empty>))
+                .or(warp::get()
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<GetPresence>())
+                    .and_then(simple_query::<GetPresence, Vec<UserUid>>)),
+            ))
+            .or(warp::path("activity")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetActivity>())
+                .and_then(simple_query::<GetActivity, Vec<ActivityEvent>>))
+            .or(warp::path("granting_teams")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetGrantingTeams>())
+                .and_then(simple_query::<GetGrantingTeams, GrantingTeams>))
+            .or(warp::path("token")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<IssueListToken, String>)),
         ))
+        .or(
+            // Requests made with a list-scoped token rather than as a user
+            warp::path("token").and(
+                (warp::path("list")
+                    .and(warp::get())
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<GetListWithToken>())
+                    .and_then(simple_query::<GetListWithToken, List>))
+                .or(warp::path("task")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<CreateTaskWithToken, i64>)),
+            ),
+        )
         .or(
             // Task CRUD
             warp::path("task").and(
@@ -212,6 +953,11 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                     .and(with_app(chan.clone()))
                     .and(warp::body::json())
                     .and_then(simple_query::<UpdateTask, Empty>))
+                .or(warp::path("complete")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<CompleteTasks, usize>))
                 .or(warp::path("delete")
                     .and(warp::delete())
                     .and(with_app(chan.clone()))
@@ -219,11 +965,76 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                     .and_then(simple_query::<DeleteTask, Empty>)),
             ),
         )
-        .or(warp::path("lists")
-            .and(warp::path("get"))
+        .or(warp::path("lists").and(
+            (warp::path("get")
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetLists>())
+                .and_then(simple_query::<GetLists, Lists>))
+            .or(warp::path("order")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<ReorderLists, Empty>))
+            .or(warp::path("many")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<GetManyLists, Vec<ListLookup>>))
+            .or(warp::path("summaries")
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetListSummaries>())
+                .and_then(simple_query::<GetListSummaries, Vec<ListSummary>>))
+            .or(warp::path("duplicates")
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetDuplicateLists>())
+                .and_then(simple_query::<GetDuplicateLists, Vec<Vec<ListUid>>>))
+            .or(warp::path("by_owner")
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetListsByOwner>())
+                .and_then(simple_query::<GetListsByOwner, ListsByOwner>)),
+        ))
+        .or(warp::path("memberships")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetMemberships>())
+            .and_then(simple_query::<GetMemberships, Memberships>))
+        .or(warp::path("user").and(
+            warp::path("export")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<ExportUserData>())
+                .and_then(simple_query::<ExportUserData, UserDataExport>),
+        ))
+        .or(warp::path("capabilities")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetCapabilities>())
+            .and_then(simple_query::<GetCapabilities, Vec<Capabilities>>))
+        .or(warp::path("trace")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetTrace>())
+            .and_then(simple_query::<GetTrace, serde_json::Value>))
+        .or(warp::path("authorize")
+            .and(warp::post())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(simple_query::<CheckAccess, Empty>))
+        .or(warp::path("requirements")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetRequirements>())
+            .and_then(simple_query::<GetRequirements, Vec<ContextRequirement>>))
+        .or(warp::path("granted")
+            .and(warp::get())
             .and(with_app(chan.clone()))
-            .and(warp::query::query::<GetLists>())
-            .and_then(simple_query::<GetLists, Lists>))
+            .and(warp::query::query::<GetGrantingPolicies>())
+            .and_then(simple_query::<GetGrantingPolicies, AuthorizationInfo>))
+        .or(warp::path("denied")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetDeniedActions>())
+            .and_then(simple_query::<GetDeniedActions, Vec<DeniedAction>>))
         .or(warp::path("share").and(
             (warp::post()
                 .and(with_app(chan.clone()))
@@ -233,8 +1044,139 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                 .and(with_app(chan.clone()))
                 .and(warp::body::json())
                 .and_then(simple_query::<DeleteShare, Empty>)),
-        )),
+        ))
+        .or(
+            // Requests only admins may make
+            warp::path("admin").and(
+                (warp::path("team").and(
+                    (warp::path("create")
+                        .and(warp::post())
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<CreateTeam, EntityUid>))
+                    .or(warp::path("group")
+                        .and(warp::post())
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<SyncGroup, EntityUid>))
+                    .or(warp::path("clone")
+                        .and(warp::post())
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<CloneTeam, EntityUid>))
+                    .or(warp::path("ancestors")
+                        .and(warp::get())
+                        .and(with_app(chan.clone()))
+                        .and(warp::query::query::<GetTeamAncestors>())
+                        .and_then(simple_query::<GetTeamAncestors, Vec<EntityUid>>)),
+                ))
+                .or(warp::path("offboard")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<OffboardUser, OffboardReport>))
+                .or(warp::path("seed")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<SeedEntities, Vec<EntityUid>>))
+                .or(warp::path("app").and(
+                    (warp::path("create")
+                        .and(warp::post())
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<CreateApp, EntityUid>))
+                    .or(warp::path("lists")
+                        .and(warp::get())
+                        .and(with_app(chan.clone()))
+                        .and(warp::query::query::<GetAppLists>())
+                        .and_then(simple_query::<GetAppLists, Lists>)),
+                ))
+                .or(warp::path("acls").and(
+                    (warp::get()
+                        .and(with_app(chan.clone()))
+                        .and(warp::query::query::<ExportAcls>())
+                        .and_then(simple_query::<ExportAcls, AclSnapshot>))
+                    .or(warp::post()
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<ImportAcls, Empty>)),
+                ))
+                .or(warp::path("find")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<FindEntities, Vec<EntityUid>>))
+                .or(warp::path("impact")
+                    .and(warp::get())
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<GetDeletionImpact>())
+                    .and_then(simple_query::<GetDeletionImpact, DeletionImpact>))
+                .or(warp::path("sync").and(
+                    (warp::get()
+                        .and(with_app(chan.clone()))
+                        .and(warp::query::query::<GetFingerprint>())
+                        .and_then(simple_query::<GetFingerprint, Fingerprint>))
+                    .or(warp::post()
+                        .and(with_app(chan.clone()))
+                        .and(warp::body::json())
+                        .and_then(simple_query::<VerifySync, Empty>)),
+                ))
+                .or(warp::path("entities")
+                    .and(warp::get())
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<ExportEntities>())
+                    .and_then(simple_query::<ExportEntities, serde_json::Value>))
+                .or(warp::path("archive")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<ArchiveEntity, Empty>))
+                .or(warp::path("restore")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<RestoreEntity, Empty>))
+                .or(warp::path("repro")
+                    .and(warp::get())
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<GetReproCase>())
+                    .and_then(simple_query::<GetReproCase, ReproCase>))
+                .or(warp::path("stats")
+                    .and(warp::get())
+                    .and(with_app(chan.clone()))
+                    .and(warp::query::query::<GetStats>())
+                    .and_then(simple_query::<GetStats, AdminStats>)),
+            ),
+        ),
     );
+    // Only stores that keep an undo history can revert changes
+    #[cfg(feature = "undo")]
+    let filter = filter.or(warp::path("api").and(warp::path("admin")).and(
+        warp::path("undo")
+            .and(warp::post())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(simple_query::<Undo, Empty>),
+    ));
+    // Only builds with partial evaluation can decide a request without its context
+    #[cfg(feature = "partial-eval")]
+    let filter = filter.or(warp::path("api").and(
+        warp::path("partial")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetPartialDecision>())
+            .and_then(simple_query::<GetPartialDecision, PartialDecision>),
+    ));
+    // Only stores loaded with an entities key can be sealed
+    #[cfg(feature = "sealed-fields")]
+    let filter = filter.or(warp::path("api").and(warp::path("admin")).and(
+        warp::path("sealed")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<ExportSealed>())
+            .and_then(simple_query::<ExportSealed, serde_json::Value>),
+    ));
 
     let s = warp::serve(filter);
     let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
@@ -243,8 +1185,21 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
 
 pub fn with_app(
     chan: AppChannel,
-) -> impl Filter<Extract = (AppChannel,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || chan.clone())
+) -> impl Filter<Extract = (AppChannel, ContextBuilder), Error = warp::Rejection> + Clone {
+    warp::any().map(move || chan.clone()).and(request_context())
+}
+
+// The context a request is authorized with, from its headers. `X-TinyTodo-MFA` is meant to be set
+// by a proxy in front of the server that checked the caller's second factor; like `uid`, it's
+// taken on trust.
+fn request_context() -> impl Filter<Extract = (ContextBuilder,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<bool>("x-tinytodo-mfa").map(|mfa: Option<bool>| {
+        let context = ContextBuilder::default();
+        match mfa {
+            Some(mfa) => context.bool("mfa", mfa),
+            None => context,
+        }
+    })
 }
 
 #[derive(Serialize)]
@@ -269,6 +1224,7 @@ fn respond(msg: Result<impl Serialize, Error>) -> impl warp::Reply {
 
 pub async fn simple_query<I, R>(
     app: mpsc::Sender<AppQuery>,
+    context: ContextBuilder,
     q: I,
 ) -> Result<impl warp::Reply, warp::Rejection>
 where
@@ -276,12 +1232,13 @@ where
     AppResponse: TryInto<R, Error = Error>,
     R: Serialize,
 {
-    let result = simple_query_inner::<R>(app, q).await;
+    let result = simple_query_inner::<R>(app, context, q).await;
     Ok(respond(result))
 }
 
 pub async fn simple_query_inner<R>(
     app: mpsc::Sender<AppQuery>,
+    context: ContextBuilder,
     q: impl Into<AppQueryKind>,
 ) -> Result<R, Error>
 where
@@ -290,7 +1247,7 @@ where
 {
     let (send, recv) = oneshot::channel();
     let kind = q.into();
-    let q = AppQuery::new(kind, send);
+    let q = AppQuery::new(kind, send).with_context(context);
     app.send(q).await?;
     let resp = recv.await??;
     let resp = resp.try_into()?;
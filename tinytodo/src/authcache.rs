@@ -0,0 +1,156 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+
+use cedar_policy::Response;
+use serde::Serialize;
+
+use crate::util::EntityUid;
+
+// (principal, action, resource)
+type Key = (EntityUid, EntityUid, EntityUid);
+
+/// Counters for tuning an `AuthCache`'s capacity against its hit rate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+}
+
+/// Authorization responses by request, evicting the least recently used once `capacity` are held.
+/// A response may depend on any entity or policy, so the owner must `clear` the cache whenever
+/// either changes; nothing here can tell that an entry has gone stale.
+#[derive(Debug)]
+pub struct AuthCache {
+    capacity: usize,
+    entries: HashMap<Key, (Response, u64)>,
+    // The keys in `entries` by when they were last used, oldest first
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl AuthCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+    ) -> Option<Response> {
+        let key = (principal.clone(), action.clone(), resource.clone());
+        match self.entries.get_mut(&key) {
+            Some((response, used)) => {
+                self.recency.remove(used);
+                self.tick += 1;
+                *used = self.tick;
+                self.recency.insert(self.tick, key);
+                self.stats.hits += 1;
+                Some(response.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        response: Response,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (principal.clone(), action.clone(), resource.clone());
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (response, self.tick)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, key);
+        while self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    /// Drops every entry, keeping the counters
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cedar_policy::{Authorizer, Context, Entities, PolicySet, Request};
+
+    // A deny, as every request gets from an empty policy set
+    fn response() -> Response {
+        let q = Request::new(None, None, None, Context::empty(), None).unwrap();
+        Authorizer::new().is_authorized(&q, &PolicySet::new(), &Entities::empty())
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let mut cache = AuthCache::new(2);
+        let action: EntityUid = r#"Action::"GetList""#.parse().unwrap();
+        let list: EntityUid = r#"List::"0""#.parse().unwrap();
+        let [andrew, emina, kesha] = ["andrew", "emina", "kesha"]
+            .map(|name| format!(r#"User::"{name}""#).parse::<EntityUid>().unwrap());
+        cache.insert(&andrew, &action, &list, response());
+        cache.insert(&emina, &action, &list, response());
+        // Used after emina's, so emina's is the least recently used
+        assert!(cache.get(&andrew, &action, &list).is_some());
+
+        cache.insert(&kesha, &action, &list, response());
+        assert!(cache.get(&emina, &action, &list).is_none());
+        assert!(cache.get(&andrew, &action, &list).is_some());
+        assert!(cache.get(&kesha, &action, &list).is_some());
+        let stats = CacheStats {
+            hits: 3,
+            misses: 1,
+            evictions: 1,
+            entries: 2,
+        };
+        assert_eq!(cache.stats(), stats);
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::sync::mpsc::Sender;
+use tower::{Layer, Service};
+use tracing::error;
+
+use crate::{
+    api::{simple_query_inner, CheckAccess, Empty},
+    context::{AppQuery, ContextBuilder, Error},
+};
+
+/// Authorizes each request before it reaches the wrapped service, for embedding TinyTodo's
+/// authorization in a `tower`-based server. `extract` names the principal, action, and
+/// resource a request is for, or `None` if it names none, which is answered `400`.
+/// Denied requests are answered `403` without reaching the service; allowed ones reach it
+/// with their `CheckAccess` attached as an extension.
+///
+/// The check is a `CheckAccess` query to the app task, so Cedar's evaluation happens there,
+/// as it does for every other request, and never blocks the thread the service runs on.
+#[derive(Debug, Clone)]
+pub struct AuthorizeLayer<F> {
+    app: Sender<AppQuery>,
+    extract: F,
+}
+
+impl<F> AuthorizeLayer<F> {
+    pub fn new(app: Sender<AppQuery>, extract: F) -> Self {
+        Self { app, extract }
+    }
+}
+
+impl<S, F: Clone> Layer<S> for AuthorizeLayer<F> {
+    type Service = Authorize<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authorize {
+            inner,
+            app: self.app.clone(),
+            extract: self.extract.clone(),
+        }
+    }
+}
+
+/// The service an `AuthorizeLayer` wraps another in
+#[derive(Debug, Clone)]
+pub struct Authorize<S, F> {
+    inner: S,
+    app: Sender<AppQuery>,
+    extract: F,
+}
+
+impl<S, F, B> Service<Request<B>> for Authorize<S, F>
+where
+    S: Service<Request<B>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send,
+    F: Fn(&Request<B>) -> Option<CheckAccess>,
+    B: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // `self.inner` was the one readied by `poll_ready`, so it's the one to call; the clone
+        // left in its place is readied before the next call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let check = (self.extract)(&req);
+        let app = self.app.clone();
+        Box::pin(async move {
+            let Some(check) = check else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            match simple_query_inner::<Empty>(app, ContextBuilder::default(), check.clone()).await {
+                Ok(_) => {
+                    req.extensions_mut().insert(check);
+                    inner.call(req).await
+                }
+                Err(Error::AuthDenied { .. }) => Ok(status(StatusCode::FORBIDDEN)),
+                Err(Error::NoSuchEntity(_)) => Ok(status(StatusCode::NOT_FOUND)),
+                Err(e) => {
+                    error!("Authorization check failed: {e}");
+                    Ok(status(StatusCode::INTERNAL_SERVER_ERROR))
+                }
+            }
+        })
+    }
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::future::{poll_fn, Ready};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::context::{AppConfig, AppContext};
+
+    // Answers every request `200`, counting the requests that reach it
+    #[derive(Clone)]
+    struct Counting(Arc<AtomicUsize>);
+
+    impl Service<Request<Body>> for Counting {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<Body>) -> Self::Future {
+            assert!(req.extensions().get::<CheckAccess>().is_some());
+            self.0.fetch_add(1, Ordering::SeqCst);
+            std::future::ready(Ok(Response::new(Body::empty())))
+        }
+    }
+
+    // Asks whether the user named by the `x-user` header may administer the application
+    fn administer(req: &Request<Body>) -> Option<CheckAccess> {
+        let uid = req.headers().get("x-user")?.to_str().ok()?.parse().ok()?;
+        Some(CheckAccess {
+            uid,
+            action: "Administer".to_string(),
+            resource: r#"Application::"TinyTodo""#.parse().unwrap(),
+            groups: vec![],
+        })
+    }
+
+    async fn send<S: Service<Request<Body>>>(
+        service: &mut S,
+        user: Option<&str>,
+    ) -> Result<S::Response, S::Error> {
+        let mut req = Request::builder();
+        if let Some(user) = user {
+            req = req.header("x-user", user);
+        }
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        service.call(req.body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn only_allowed_requests_reach_the_service() {
+        #[cfg(not(feature = "use-templates"))]
+        let (schema, policies) = ("./tinytodo.cedarschema", "./policies.cedar");
+        #[cfg(feature = "use-templates")]
+        let (schema, policies) = (
+            "./tinytodo-templates.cedarschema",
+            "./policies-templates.cedar",
+        );
+        let app = AppContext::spawn("./entities.json", schema, policies, AppConfig::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service =
+            AuthorizeLayer::new(app.unwrap(), administer).layer(Counting(calls.clone()));
+
+        let denied = send(&mut service, Some(r#"User::"kesha""#)).await.unwrap();
+        assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let unnamed = send(&mut service, None).await.unwrap();
+        assert_eq!(unnamed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let allowed = send(&mut service, Some(r#"User::"andrew""#)).await.unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -16,12 +16,22 @@
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use std::path::PathBuf;
-use tracing::{error, info, trace};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, trace, warn};
 
 use cedar_policy::{
-    Authorizer, Context, Decision, Diagnostics, EntityTypeName, HumanSchemaError, ParseErrors,
-    PolicySet, PolicySetError, Request, Schema, SchemaError, ValidationMode, Validator,
+    Authorizer, Context, Decision, Diagnostics, Effect, Entities, HumanSchemaError, ParseErrors,
+    Policy, PolicyId, PolicySet, PolicySetError, Request, Response, Schema, SchemaError,
+    ValidationMode, Validator,
 };
 
 use thiserror::Error;
@@ -31,31 +41,92 @@ use tokio::sync::{
 };
 
 use crate::{
+    activity::{Activity, ActivityEvent, ActivityLog},
     api::{
-        AddShare, CreateList, CreateTask, DeleteList, DeleteShare, DeleteTask, Empty, GetList,
-        GetLists, UpdateList, UpdateTask,
+        AddShare, ArchiveEntity, CheckAccess, CloneTeam, CompleteTasks, CreateApp, CreateList,
+        CreateTask, CreateTaskWithToken, CreateTeam, DeleteList, DeleteShare, DeleteTask, Empty,
+        ExportAcls, ExportEntities, ExportUserData, FindEntities, GetActivity, GetAppLists,
+        GetCapabilities, GetDeletionImpact, GetDeniedActions, GetDuplicateLists, GetFingerprint,
+        GetGrantingPolicies, GetGrantingTeams, GetList, GetListActions, GetListSummaries,
+        GetListWithToken, GetLists, GetListsByOwner, GetManyLists, GetMemberships, GetPresence,
+        GetReproCase, GetRequirements, GetStats, GetTasksPage, GetTeamAncestors, GetTrace,
+        ImportAcls, IssueListToken, MergeLists, OffboardUser, RegisterWebhook, ReorderLists,
+        RestoreEntity, SeedEntities, SetTaskSchema, SetTemplate, SyncGroup, TouchPresence,
+        TransferList, UpdateList, UpdateTask, VerifySync,
+    },
+    authcache::{AuthCache, CacheStats},
+    decisionlog::{DecisionLog, DecisionLogTarget, DecisionLogger, FileLog, TracingLog},
+    entitystore::{
+        AclSnapshot, EntitiesCache, EntityDecodeError, EntityRef, EntityStore, EntityType,
+        GrantingTeams, JsonEntities, ListsByOwner, Memberships, OffboardReport, StoreStats,
+        UserDataExport, UuidIds, MAX_LIST_NAME_LEN,
     },
-    entitystore::{EntityDecodeError, EntityStore},
-    objects::List,
+    objects::{Application, List, ListSummary, TaskFieldError, TasksDiff, TasksPage},
     policy_store,
-    util::{EntityUid, ListUid, Lists, TYPE_LIST},
+    presence::Presence,
+    ratelimit::{RateLimit, RateLimiter},
+    schemagen,
+    slicing::PolicySlices,
+    tokens::{ScopedToken, TokenCapability, TokenRegistry},
+    util::{EntityTypeError, EntityUid, Fingerprint, ListUid, Lists, UserUid, TYPE_APPLICATION},
+    versionvector::VersionVector,
+    webhooks::{ListChange, WebhookError, WebhookRegistry},
 };
 
+#[cfg(feature = "sealed-fields")]
+use crate::api::ExportSealed;
+#[cfg(feature = "partial-eval")]
+use crate::api::GetPartialDecision;
+use crate::api::ShareRole;
+#[cfg(feature = "undo")]
+use crate::api::Undo;
 #[cfg(feature = "use-templates")]
-use crate::{api::ShareRole, util::UserOrTeamUid};
+use crate::entitystore::Grant;
+#[cfg(feature = "sealed-fields")]
+use crate::sealing::{FieldKey, SealError};
+use crate::util::{TeamUid, UserOrTeamUid};
+#[cfg(feature = "partial-eval")]
+use cedar_policy::RequestBuilder;
 #[cfg(feature = "use-templates")]
-use cedar_policy::{PolicyId, SlotId};
-#[cfg(feature = "use-templates")]
-use std::{collections::HashMap, str::FromStr};
+use cedar_policy::SlotId;
 
 // There's almost certainly a nicer way to do this than having separate `sender` fields
 
 #[derive(Debug)]
 pub enum AppResponse {
     GetList(Box<List>),
+    TasksPage(TasksPage),
     Euid(EntityUid),
     Lists(Lists),
+    ListSummaries(Vec<ListSummary>),
+    ListLookups(Vec<ListLookup>),
+    ListGroups(Vec<Vec<ListUid>>),
+    ListsByOwner(ListsByOwner),
+    Activity(Vec<ActivityEvent>),
+    Capabilities(Vec<Capabilities>),
+    Actions(Vec<&'static str>),
+    Trace(serde_json::Value),
+    Snapshot(serde_json::Value),
+    Requirements(Vec<ContextRequirement>),
+    DeniedActions(Vec<DeniedAction>),
+    Granted(AuthorizationInfo),
+    #[cfg(feature = "partial-eval")]
+    PartialDecision(PartialDecision),
+    GrantingTeams(GrantingTeams),
+    Memberships(Memberships),
+    Offboard(OffboardReport),
+    UserData(Box<UserDataExport>),
+    Repro(Box<ReproCase>),
+    Acls(AclSnapshot),
+    Stats(AdminStats),
+    Users(Vec<UserUid>),
+    Euids(Vec<EntityUid>),
+    DeletionImpact(DeletionImpact),
+    Fingerprint(Fingerprint),
+    Version(VersionVector),
     TaskId(i64),
+    Token(String),
+    Count(usize),
     Unit(()),
 }
 
@@ -65,6 +136,17 @@ impl AppResponse {
     }
 }
 
+impl TryInto<usize> for AppResponse {
+    type Error = Error;
+
+    fn try_into(self) -> std::result::Result<usize, Self::Error> {
+        match self {
+            AppResponse::Count(n) => Ok(n),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
 impl TryInto<i64> for AppResponse {
     type Error = Error;
 
@@ -87,6 +169,17 @@ impl TryInto<List> for AppResponse {
     }
 }
 
+impl TryInto<String> for AppResponse {
+    type Error = Error;
+
+    fn try_into(self) -> std::result::Result<String, Self::Error> {
+        match self {
+            AppResponse::Token(t) => Ok(t),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
 impl TryInto<EntityUid> for AppResponse {
     type Error = Error;
     fn try_into(self) -> std::result::Result<EntityUid, Self::Error> {
@@ -118,39 +211,527 @@ impl TryInto<Lists> for AppResponse {
     }
 }
 
+impl TryInto<Vec<UserUid>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<UserUid>, Self::Error> {
+        match self {
+            AppResponse::Users(u) => Ok(u),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<ReproCase> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<ReproCase, Self::Error> {
+        match self {
+            AppResponse::Repro(r) => Ok(*r),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<VersionVector> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<VersionVector, Self::Error> {
+        match self {
+            AppResponse::Version(v) => Ok(v),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Fingerprint> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Fingerprint, Self::Error> {
+        match self {
+            AppResponse::Fingerprint(f) => Ok(f),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<DeletionImpact> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<DeletionImpact, Self::Error> {
+        match self {
+            AppResponse::DeletionImpact(i) => Ok(i),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<EntityUid>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<EntityUid>, Self::Error> {
+        match self {
+            AppResponse::Euids(e) => Ok(e),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<serde_json::Value> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<serde_json::Value, Self::Error> {
+        match self {
+            AppResponse::Trace(v) | AppResponse::Snapshot(v) => Ok(v),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<GrantingTeams> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<GrantingTeams, Self::Error> {
+        match self {
+            AppResponse::GrantingTeams(g) => Ok(g),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+#[cfg(feature = "partial-eval")]
+impl TryInto<PartialDecision> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<PartialDecision, Self::Error> {
+        match self {
+            AppResponse::PartialDecision(d) => Ok(d),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<AuthorizationInfo> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<AuthorizationInfo, Self::Error> {
+        match self {
+            AppResponse::Granted(g) => Ok(g),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<&'static str>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<&'static str>, Self::Error> {
+        match self {
+            AppResponse::Actions(a) => Ok(a),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<DeniedAction>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<DeniedAction>, Self::Error> {
+        match self {
+            AppResponse::DeniedActions(d) => Ok(d),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<ContextRequirement>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<ContextRequirement>, Self::Error> {
+        match self {
+            AppResponse::Requirements(r) => Ok(r),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<Capabilities>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<Capabilities>, Self::Error> {
+        match self {
+            AppResponse::Capabilities(c) => Ok(c),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<TasksPage> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<TasksPage, Self::Error> {
+        match self {
+            AppResponse::TasksPage(p) => Ok(p),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<ActivityEvent>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<ActivityEvent>, Self::Error> {
+        match self {
+            AppResponse::Activity(a) => Ok(a),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<OffboardReport> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<OffboardReport, Self::Error> {
+        match self {
+            AppResponse::Offboard(r) => Ok(r),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Memberships> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Memberships, Self::Error> {
+        match self {
+            AppResponse::Memberships(m) => Ok(m),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<UserDataExport> for AppResponse {
+    type Error = Error;
+
+    fn try_into(self) -> std::result::Result<UserDataExport, Self::Error> {
+        match self {
+            AppResponse::UserData(export) => Ok(*export),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<AclSnapshot> for AppResponse {
+    type Error = Error;
+
+    fn try_into(self) -> std::result::Result<AclSnapshot, Self::Error> {
+        match self {
+            AppResponse::Acls(snapshot) => Ok(snapshot),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<Vec<ListUid>>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<Vec<ListUid>>, Self::Error> {
+        match self {
+            AppResponse::ListGroups(g) => Ok(g),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<ListsByOwner> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<ListsByOwner, Self::Error> {
+        match self {
+            AppResponse::ListsByOwner(lists) => Ok(lists),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<ListSummary>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<ListSummary>, Self::Error> {
+        match self {
+            AppResponse::ListSummaries(l) => Ok(l),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<AdminStats> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<AdminStats, Self::Error> {
+        match self {
+            AppResponse::Stats(s) => Ok(s),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Vec<ListLookup>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Vec<ListLookup>, Self::Error> {
+        match self {
+            AppResponse::ListLookups(l) => Ok(l),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+/// What refers to an entity, and so would be left dangling if it were deleted,
+/// see `AppContext::deletion_impact`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeletionImpact {
+    /// Stored entities referring to it, each with the referring field
+    pub entities: Vec<(EntityUid, &'static str)>,
+    /// Policies naming it, in their scope, their conditions, or their template links
+    pub policies: Vec<PolicyId>,
+}
+
+/// The actions a principal may perform on one resource
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub resource: EntityUid,
+    pub actions: Vec<&'static str>,
+}
+
+/// Counters for tuning the server, for admins
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminStats {
+    /// `None` when the authorization cache is off
+    pub cache: Option<CacheStats>,
+    pub store: StoreStats,
+}
+
+/// One list looked up by `get_many_lists`: the list, or why it couldn't be given
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListLookup {
+    List(Box<List>),
+    Error(String),
+}
+
+/// One authorization decision with just the entities and policies needed to reproduce it, see
+/// `AppContext::repro_case`. It serializes to a file that `replay` decides on its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReproCase {
+    pub principal: EntityUid,
+    pub action: EntityUid,
+    pub resource: EntityUid,
+    /// The request's context, as a JSON object of its attributes
+    pub context: serde_json::Value,
+    /// The entities in Cedar's JSON entity format
+    pub entities: serde_json::Value,
+    /// The policies that determined the decision in Cedar's JSON policy format, by id
+    pub policies: BTreeMap<String, serde_json::Value>,
+    pub allowed: bool,
+}
+
+impl ReproCase {
+    /// Decides the request against only the case's entities and policies, without a schema.
+    /// Returns whether it was allowed, which should match `allowed`.
+    pub fn replay(&self) -> std::result::Result<bool, String> {
+        let entities =
+            Entities::from_json_value(self.entities.clone(), None).map_err(|e| e.to_string())?;
+        let mut policies = PolicySet::new();
+        for (id, est) in &self.policies {
+            let id = PolicyId::from_str(id).map_err(|e| e.to_string())?;
+            let policy = Policy::from_json(Some(id), est.clone()).map_err(|e| e.to_string())?;
+            policies.add(policy).map_err(|e| e.to_string())?;
+        }
+        let q = Request::new(
+            Some(self.principal.clone().into()),
+            Some(self.action.clone().into()),
+            Some(self.resource.clone().into()),
+            Context::from_json_value(self.context.clone(), None).map_err(|e| e.to_string())?,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+        let response = Authorizer::new().is_authorized(&q, &policies, &entities);
+        Ok(response.decision() == Decision::Allow)
+    }
+}
+
+/// A context attribute read by a permit policy that could allow a denied request,
+/// see `AppContext::authorization_requirements`
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextRequirement {
+    pub attribute: String,
+    pub policy: String,
+}
+
+/// An action a principal may not perform on a resource, see `AppContext::denied_actions`
+#[derive(Debug, Clone, Serialize)]
+pub struct DeniedAction {
+    pub action: &'static str,
+    /// The forbid policies that matched, or none if no permit policy matched instead
+    pub forbidden_by: Vec<PolicyId>,
+}
+
+/// Builds a request `Context` one typed attribute at a time. Nothing is checked until `build`,
+/// which checks the whole context against the one the schema declares for the action.
+#[derive(Debug, Clone, Default)]
+pub struct ContextBuilder(serde_json::Map<String, serde_json::Value>);
+
+impl ContextBuilder {
+    pub fn string(mut self, key: impl Into<String>, val: impl Into<String>) -> Self {
+        self.0.insert(key.into(), val.into().into());
+        self
+    }
+
+    pub fn long(mut self, key: impl Into<String>, val: i64) -> Self {
+        self.0.insert(key.into(), val.into());
+        self
+    }
+
+    pub fn bool(mut self, key: impl Into<String>, val: bool) -> Self {
+        self.0.insert(key.into(), val.into());
+        self
+    }
+
+    pub fn entity(mut self, key: impl Into<String>, euid: &EntityUid) -> Self {
+        let id: &str = euid.id().as_ref();
+        let escape = json!({ "__entity": { "type": euid.type_name().to_string(), "id": id } });
+        self.0.insert(key.into(), escape);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The context for a request to perform `action`. An attribute the schema doesn't declare
+    /// for `action`, a declared one that's missing, or one of the wrong type is an
+    /// `Error::Context` naming it, rather than a context that fails at authorization time.
+    pub fn build(self, schema: &Schema, action: &EntityUid) -> Result<Context> {
+        Context::from_json_value(serde_json::Value::Object(self.0), Some((schema, &**action)))
+            .map_err(|e| Error::Context(e.to_string()))
+    }
+}
+
 #[derive(Debug)]
 pub enum AppQueryKind {
     // List CRUD
     CreateList(CreateList),
     GetList(GetList),
+    GetManyLists(GetManyLists),
+    GetTasksPage(GetTasksPage),
     UpdateList(UpdateList),
     DeleteList(DeleteList),
+    MergeLists(MergeLists),
+    TransferList(TransferList),
+    SetTaskSchema(SetTaskSchema),
+    SetTemplate(SetTemplate),
+    RegisterWebhook(RegisterWebhook),
+    TouchPresence(TouchPresence),
+    GetPresence(GetPresence),
+    GetActivity(GetActivity),
+    GetGrantingTeams(GetGrantingTeams),
+    IssueListToken(IssueListToken),
+    GetListWithToken(GetListWithToken),
 
     // Task CRUD
     CreateTask(CreateTask),
     UpdateTask(UpdateTask),
+    CompleteTasks(CompleteTasks),
     DeleteTask(DeleteTask),
+    CreateTaskWithToken(CreateTaskWithToken),
 
     // Lists
     GetLists(GetLists),
+    ReorderLists(ReorderLists),
+    GetListSummaries(GetListSummaries),
+    GetDuplicateLists(GetDuplicateLists),
+    GetListsByOwner(GetListsByOwner),
+    GetCapabilities(GetCapabilities),
+    GetListActions(GetListActions),
+    GetTrace(GetTrace),
+    GetRequirements(GetRequirements),
+    GetDeniedActions(GetDeniedActions),
+    GetGrantingPolicies(GetGrantingPolicies),
+    CheckAccess(CheckAccess),
+    GetMemberships(GetMemberships),
+    ExportUserData(ExportUserData),
 
     // Shares
     AddShare(AddShare),
     DeleteShare(DeleteShare),
 
+    // Administration
+    CreateTeam(CreateTeam),
+    SyncGroup(SyncGroup),
+    OffboardUser(OffboardUser),
+    CloneTeam(CloneTeam),
+    GetTeamAncestors(GetTeamAncestors),
+    SeedEntities(SeedEntities),
+    CreateApp(CreateApp),
+    GetAppLists(GetAppLists),
+    ExportAcls(ExportAcls),
+    ImportAcls(ImportAcls),
+    GetStats(GetStats),
+    FindEntities(FindEntities),
+    GetDeletionImpact(GetDeletionImpact),
+    GetFingerprint(GetFingerprint),
+    VerifySync(VerifySync),
+    GetReproCase(GetReproCase),
+    ExportEntities(ExportEntities),
+    ArchiveEntity(ArchiveEntity),
+    RestoreEntity(RestoreEntity),
+    #[cfg(feature = "undo")]
+    Undo(Undo),
+    #[cfg(feature = "sealed-fields")]
+    ExportSealed(ExportSealed),
+    #[cfg(feature = "partial-eval")]
+    GetPartialDecision(GetPartialDecision),
+
     // Policy Set Updates
     UpdatePolicySet(PolicySet),
 }
 
+impl AppQueryKind {
+    /// Whether handling this query leaves the entities and policies as they were
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            AppQueryKind::GetList(_)
+                | AppQueryKind::GetManyLists(_)
+                | AppQueryKind::GetTasksPage(_)
+                | AppQueryKind::GetPresence(_)
+                | AppQueryKind::GetActivity(_)
+                | AppQueryKind::GetGrantingTeams(_)
+                | AppQueryKind::GetLists(_)
+                | AppQueryKind::GetListSummaries(_)
+                | AppQueryKind::GetDuplicateLists(_)
+                | AppQueryKind::GetListsByOwner(_)
+                | AppQueryKind::GetCapabilities(_)
+                | AppQueryKind::GetListActions(_)
+                | AppQueryKind::GetTrace(_)
+                | AppQueryKind::GetRequirements(_)
+                | AppQueryKind::GetDeniedActions(_)
+                | AppQueryKind::GetGrantingPolicies(_)
+                | AppQueryKind::CheckAccess(_)
+                | AppQueryKind::GetMemberships(_)
+                | AppQueryKind::ExportUserData(_)
+                | AppQueryKind::ExportAcls(_)
+                | AppQueryKind::GetStats(_)
+                | AppQueryKind::FindEntities(_)
+                | AppQueryKind::GetAppLists(_)
+                | AppQueryKind::GetTeamAncestors(_)
+                | AppQueryKind::GetDeletionImpact(_)
+                | AppQueryKind::GetFingerprint(_)
+                | AppQueryKind::VerifySync(_)
+                | AppQueryKind::GetReproCase(_)
+                | AppQueryKind::ExportEntities(_)
+                | AppQueryKind::GetListWithToken(_)
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct AppQuery {
     kind: AppQueryKind,
     sender: oneshot::Sender<Result<AppResponse>>,
+    context: ContextBuilder,
 }
 
 impl AppQuery {
     pub fn new(kind: AppQueryKind, sender: oneshot::Sender<Result<AppResponse>>) -> Self {
-        Self { kind, sender }
+        Self {
+            kind,
+            sender,
+            context: ContextBuilder::default(),
+        }
+    }
+
+    /// Authorizes every request the query makes with `context`, rather than an empty one
+    pub fn with_context(self, context: ContextBuilder) -> Self {
+        Self { context, ..self }
     }
 }
 
@@ -172,6 +753,13 @@ pub enum ContextError {
     Validation(String),
     #[error("Error Deserializing Json: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("Invalid Configuration: {0}")]
+    Config(String),
+    #[error("Error Loading Entities: {0}")]
+    Entities(#[from] EntityDecodeError),
+    #[cfg(feature = "sealed-fields")]
+    #[error("Error Unsealing Entities: {0}")]
+    Sealing(#[from] SealError),
 }
 
 #[derive(Debug, Error)]
@@ -180,15 +768,27 @@ pub enum Error {
     NoSuchEntity(EntityUid),
     #[error("Entity Decode Error: {0}")]
     EntityDecode(#[from] EntityDecodeError),
-    #[error("Authorization Denied")]
-    AuthDenied(Diagnostics),
+    #[error("Authorization Denied{}", denial_detail(.detail))]
+    AuthDenied {
+        request: AuthRequest,
+        diagnostics: Diagnostics,
+        reason: DenialReason,
+        // Only populated at `DenialVerbosity::Detailed`, as it reveals policy structure
+        detail: Option<String>,
+    },
+    #[error("Authorization Denied on {}", .0.iter().join(", "))]
+    AuthDeniedOn(Vec<EntityUid>),
     #[error("The list {0} does not contain a task with id {1}")]
     InvalidTaskId(EntityUid, i64),
+    #[error("Invalid task fields: {0}")]
+    TaskFields(#[from] TaskFieldError),
     #[error("Internal Error")]
     TokioSend(#[from] tokio::sync::mpsc::error::SendError<AppQuery>),
     #[error("Internal Error")]
     TokioRecv(#[from] tokio::sync::oneshot::error::RecvError),
     #[error("Internal Error")]
+    TokioJoin(#[from] tokio::task::JoinError),
+    #[error("Internal Error")]
     Type,
     #[error("Internal Error")]
     IO(#[from] std::io::Error),
@@ -198,6 +798,69 @@ pub enum Error {
     PolicySet(#[from] PolicySetError),
     #[error("Error constructing authorization request: {0}")]
     Request(String),
+    #[error("Resource {0} does not exist")]
+    ResourceMissing(EntityUid),
+    #[error("Action {action} does not apply to {resource}")]
+    ActionNotApplicable {
+        action: EntityUid,
+        resource: EntityUid,
+    },
+    #[error("{0}")]
+    EntityType(#[from] EntityTypeError),
+    #[error("Internal Error")]
+    IdSpaceExhausted,
+    #[error("{entity} is still referred to by {by}")]
+    StillReferenced { entity: EntityUid, by: EntityUid },
+    #[error("List {0} appears more than once in the order")]
+    DuplicateInOrder(EntityUid),
+    #[error("Entity {0} already exists")]
+    AlreadyExists(EntityUid),
+    #[error("{entity} refers to {missing} through `{field}`, which does not exist")]
+    DanglingReference {
+        entity: EntityUid,
+        field: &'static str,
+        missing: EntityUid,
+    },
+    #[error("User {0} may not own more than {1} lists")]
+    QuotaExceeded(EntityUid, usize),
+    #[error("List names must be between 1 and {} characters", MAX_LIST_NAME_LEN)]
+    InvalidListName,
+    #[error("Operation {index} of the batch failed, so none were applied: {source}")]
+    BatchFailed { index: usize, source: Box<Error> },
+    #[error("Write to {entity} conflicts with a concurrent write")]
+    WriteConflict {
+        entity: EntityUid,
+        base: VersionVector,
+        current: VersionVector,
+    },
+    #[error("Token is invalid or has expired")]
+    InvalidToken,
+    #[error("Token does not allow {0:?} on its list")]
+    TokenNotAllowed(TokenCapability),
+    #[error("Token lifetime of {0} seconds is too long")]
+    TokenTtl(u64),
+    #[error("Internal Error")]
+    Panicked,
+    #[error("Replica has diverged from the primary and needs a full resync")]
+    ReplicaDiverged,
+    #[error("Rate limited, try again later")]
+    RateLimited,
+    #[error("Invalid webhook: {0}")]
+    Webhook(#[from] WebhookError),
+    #[error("Authorization diagnostics are only available with detailed denial verbosity")]
+    DiagnosticsDisabled,
+    #[error("Error building authorization trace: {0}")]
+    Trace(String),
+    #[error("Error building reproduction case: {0}")]
+    Repro(String),
+    #[error("Invalid request context: {0}")]
+    Context(String),
+    #[cfg(feature = "undo")]
+    #[error("There is nothing to undo")]
+    NothingToUndo,
+    #[cfg(feature = "sealed-fields")]
+    #[error("No entities key is set, so there is nothing to seal with")]
+    NoEntitiesKey,
 }
 
 impl Error {
@@ -206,24 +869,362 @@ impl Error {
     }
 }
 
-lazy_static! {
-    pub static ref APPLICATION_TINY_TODO: EntityUid = r#"Application::"TinyTodo""#.parse().unwrap();
-    static ref ACTION_EDIT_SHARE: EntityUid = r#"Action::"EditShare""#.parse().unwrap();
-    static ref ACTION_UPDATE_TASK: EntityUid = r#"Action::"UpdateTask""#.parse().unwrap();
-    static ref ACTION_CREATE_TASK: EntityUid = r#"Action::"CreateTask""#.parse().unwrap();
-    static ref ACTION_DELETE_TASK: EntityUid = r#"Action::"DeleteTask""#.parse().unwrap();
-    static ref ACTION_GET_LISTS: EntityUid = r#"Action::"GetLists""#.parse().unwrap();
-    static ref ACTION_GET_LIST: EntityUid = r#"Action::"GetList""#.parse().unwrap();
-    static ref ACTION_CREATE_LIST: EntityUid = r#"Action::"CreateList""#.parse().unwrap();
-    static ref ACTION_UPDATE_LIST: EntityUid = r#"Action::"UpdateList""#.parse().unwrap();
+// Collects every attribute that is read or tested with `has` in a policy's JSON conditions,
+// along with the expression it is an attribute of
+fn attribute_accesses<'a>(
+    est: &'a serde_json::Value,
+    found: &mut Vec<(&'a serde_json::Value, &'a str)>,
+) {
+    match est {
+        serde_json::Value::Object(fields) => {
+            for (op, arg) in fields {
+                if op == "." || op == "has" {
+                    if let Some(attr) = arg["attr"].as_str() {
+                        found.push((&arg["left"], attr));
+                    }
+                }
+                attribute_accesses(arg, found);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                attribute_accesses(item, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+// The attributes read from `context` (when `of_context`), or from anything else, in a policy's
+// JSON conditions
+fn accessed_attributes(est: &serde_json::Value, of_context: bool) -> Vec<String> {
+    let mut found = vec![];
+    attribute_accesses(&est["conditions"], &mut found);
+    found
+        .into_iter()
+        .filter(|(left, _)| (**left == json!({ "Var": "context" })) == of_context)
+        .map(|(_, attr)| attr.to_string())
+        .unique()
+        .collect()
+}
+
+// Whether a policy's JSON mentions `euid` anywhere, e.g. in its scope or as a literal
+fn mentions_entity(est: &serde_json::Value, euid: &cedar_policy::EntityUid) -> bool {
+    match est {
+        serde_json::Value::Object(fields) => {
+            let is_euid = fields.get("type").and_then(|ty| ty.as_str())
+                == Some(euid.type_name().to_string().as_str())
+                && fields.get("id").and_then(|id| id.as_str()) == Some(euid.id().as_ref());
+            is_euid || fields.values().any(|v| mentions_entity(v, euid))
+        }
+        serde_json::Value::Array(items) => items.iter().any(|v| mentions_entity(v, euid)),
+        _ => false,
+    }
+}
+
+// Collects the entities referred to by `__entity` escapes in Cedar's entity JSON
+fn referenced_entities(json: &serde_json::Value, found: &mut Vec<cedar_policy::EntityUid>) {
+    match json {
+        serde_json::Value::Object(fields) => {
+            if fields.contains_key("__entity") {
+                if let Ok(euid) = cedar_policy::EntityUid::from_json(json.clone()) {
+                    found.push(euid);
+                }
+            }
+            for v in fields.values() {
+                referenced_entities(v, found);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                referenced_entities(item, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Parses the name of an action, e.g. `GetList`, into its euid
+fn parse_action(name: &str) -> Result<EntityUid> {
+    format!(r#"Action::"{name}""#)
+        .parse()
+        .map_err(|e: ParseErrors| Error::Request(e.to_string()))
+}
+
+fn denial_detail(detail: &Option<String>) -> String {
+    match detail {
+        Some(detail) => format!(": {detail}"),
+        None => String::new(),
+    }
+}
+
+/// How much of the reason for an authorization denial is shown to the caller.
+/// Full diagnostics are always logged server-side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DenialVerbosity {
+    /// Callers only learn that they were denied
+    #[default]
+    Generic,
+    /// Callers are also told which policies and errors produced the denial
+    Detailed,
+}
+
+impl FromStr for DenialVerbosity {
+    type Err = ContextError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Self::Generic),
+            "detailed" => Ok(Self::Detailed),
+            _ => Err(ContextError::Config(format!(
+                "Unknown denial verbosity `{s}`, expected `generic` or `detailed`"
+            ))),
+        }
+    }
+}
+
+/// What a caller is told when they read a list that is missing or that they may not read.
+/// Unless the policy is `Distinct`, the two cases look the same, so the response doesn't reveal
+/// whether the list exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotFoundPolicy {
+    /// Both are reported as an authorization denial
+    #[default]
+    Denied,
+    /// Both are reported as `NoSuchEntity`
+    NotFound,
+    /// A request on any resource that isn't in the store is reported as `ResourceMissing`,
+    /// before any policy is evaluated, so clients can tell "gone" from "forbidden"
+    Distinct,
+}
+
+impl FromStr for NotFoundPolicy {
+    type Err = ContextError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "denied" => Ok(Self::Denied),
+            "not-found" => Ok(Self::NotFound),
+            "distinct" => Ok(Self::Distinct),
+            _ => Err(ContextError::Config(format!(
+                "Unknown not-found policy `{s}`, expected `denied`, `not-found`, or `distinct`"
+            ))),
+        }
+    }
+}
+
+/// Per-deployment settings for the application server
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub denial_verbosity: DenialVerbosity,
+    pub not_found_policy: NotFoundPolicy,
+    /// Webhook URLs notified whenever any list changes
+    pub webhooks: Vec<String>,
+    /// The hosts users may register webhooks on their lists for; none unless configured
+    pub webhook_hosts: Vec<String>,
+    /// How long a user counts as present on a list after they last touched it
+    pub presence_ttl: Duration,
+    /// How often each principal may perform each mutating action, if limited at all
+    pub rate_limit: Option<RateLimit>,
+    /// Whether to quarantine entities that fail to load instead of refusing to start
+    pub lenient_load: bool,
+    /// Whether new entities get random UUIDs as ids instead of the next number
+    pub uuid_ids: bool,
+    /// A Cedar entity JSON file of entities of types the schema declares beyond the store's own
+    pub extra_entities: Option<PathBuf>,
+    /// How many authorization responses to cache, if any are cached at all
+    pub auth_cache_capacity: Option<usize>,
+    /// Whether to evaluate each request against only the policies whose scopes could apply to it
+    pub policy_slicing: bool,
+    /// Where to send every authorization decision, if decisions are logged at all
+    pub decision_log: Option<DecisionLogTarget>,
+    /// The key the entities file's sensitive fields are sealed with, if any are sealed
+    #[cfg(feature = "sealed-fields")]
+    pub entities_key: Option<FieldKey>,
+    /// How many changes to the entities can be undone, if not the store's default
+    #[cfg(feature = "undo")]
+    pub undo_depth: Option<usize>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            denial_verbosity: DenialVerbosity::default(),
+            not_found_policy: NotFoundPolicy::default(),
+            webhooks: vec![],
+            webhook_hosts: vec![],
+            presence_ttl: Duration::from_secs(30),
+            rate_limit: None,
+            lenient_load: false,
+            uuid_ids: false,
+            extra_entities: None,
+            auth_cache_capacity: None,
+            policy_slicing: false,
+            decision_log: None,
+            #[cfg(feature = "sealed-fields")]
+            entities_key: None,
+            #[cfg(feature = "undo")]
+            undo_depth: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads the configuration from `TINYTODO_*` environment variables, using the defaults
+    /// for any that are unset
+    pub fn from_env() -> std::result::Result<Self, ContextError> {
+        let mut config = Self::default();
+        if let Ok(v) = std::env::var("TINYTODO_DENIAL_VERBOSITY") {
+            config.denial_verbosity = v.parse()?;
+        }
+        if let Ok(v) = std::env::var("TINYTODO_NOT_FOUND_POLICY") {
+            config.not_found_policy = v.parse()?;
+        }
+        if let Ok(v) = std::env::var("TINYTODO_WEBHOOKS") {
+            config.webhooks = v.split(',').map(str::to_string).collect();
+        }
+        if let Ok(v) = std::env::var("TINYTODO_WEBHOOK_HOSTS") {
+            config.webhook_hosts = v.split(',').map(|h| h.trim().to_string()).collect();
+        }
+        if let Ok(v) = std::env::var("TINYTODO_PRESENCE_TTL_SECS") {
+            let secs = v
+                .parse()
+                .map_err(|_| ContextError::Config(format!("invalid presence ttl `{v}`")))?;
+            config.presence_ttl = Duration::from_secs(secs);
+        }
+        if let Ok(v) = std::env::var("TINYTODO_LENIENT_LOAD") {
+            config.lenient_load = v.parse().map_err(|_| {
+                ContextError::Config(format!(
+                    "invalid lenient load `{v}`, expected `true` or `false`"
+                ))
+            })?;
+        }
+        if let Ok(v) = std::env::var("TINYTODO_UUID_IDS") {
+            config.uuid_ids = v.parse().map_err(|_| {
+                ContextError::Config(format!(
+                    "invalid uuid ids `{v}`, expected `true` or `false`"
+                ))
+            })?;
+        }
+        if let Ok(v) = std::env::var("TINYTODO_EXTRA_ENTITIES") {
+            config.extra_entities = Some(v.into());
+        }
+        if let Ok(v) = std::env::var("TINYTODO_RATE_LIMIT") {
+            config.rate_limit = Some(parse_rate_limit(&v)?);
+        }
+        if let Ok(v) = std::env::var("TINYTODO_AUTH_CACHE") {
+            let capacity = v
+                .parse()
+                .map_err(|_| ContextError::Config(format!("invalid auth cache size `{v}`")))?;
+            config.auth_cache_capacity = Some(capacity);
+        }
+        if let Ok(v) = std::env::var("TINYTODO_POLICY_SLICING") {
+            config.policy_slicing = v.parse().map_err(|_| {
+                ContextError::Config(format!(
+                    "invalid policy slicing `{v}`, expected `true` or `false`"
+                ))
+            })?;
+        }
+        if let Ok(v) = std::env::var("TINYTODO_DECISION_LOG") {
+            config.decision_log = Some(v.into());
+        }
+        #[cfg(feature = "sealed-fields")]
+        if let Ok(v) = std::env::var("TINYTODO_ENTITIES_KEY") {
+            config.entities_key = Some(v.parse()?);
+        }
+        #[cfg(feature = "undo")]
+        if let Ok(v) = std::env::var("TINYTODO_UNDO_DEPTH") {
+            let depth = v
+                .parse()
+                .map_err(|_| ContextError::Config(format!("invalid undo depth `{v}`")))?;
+            config.undo_depth = Some(depth);
+        }
+        Ok(config)
+    }
+}
+
+// Parses `<capacity>/<refill per second>`, e.g. `10/0.5`
+fn parse_rate_limit(s: &str) -> std::result::Result<RateLimit, ContextError> {
+    let invalid = || {
+        ContextError::Config(format!(
+            "invalid rate limit `{s}`, expected `<capacity>/<refill per second>`"
+        ))
+    };
+    // Both must be finite and positive, else every request, or none, would be limited
+    let parse = |v: &str| match v.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0.0 => Ok(v),
+        _ => Err(invalid()),
+    };
+    let (capacity, refill) = s.split_once('/').ok_or_else(invalid)?;
+    Ok(RateLimit {
+        capacity: parse(capacity)?,
+        refill_per_sec: parse(refill)?,
+    })
+}
+
+lazy_static! {
+    pub static ref APPLICATION_TINY_TODO: EntityUid = r#"Application::"TinyTodo""#.parse().unwrap();
+    static ref ACTION_EDIT_SHARE: EntityUid = r#"Action::"EditShare""#.parse().unwrap();
+    static ref ACTION_UPDATE_TASK: EntityUid = r#"Action::"UpdateTask""#.parse().unwrap();
+    static ref ACTION_CREATE_TASK: EntityUid = r#"Action::"CreateTask""#.parse().unwrap();
+    static ref ACTION_DELETE_TASK: EntityUid = r#"Action::"DeleteTask""#.parse().unwrap();
+    static ref ACTION_GET_LISTS: EntityUid = r#"Action::"GetLists""#.parse().unwrap();
+    static ref ACTION_GET_LIST: EntityUid = r#"Action::"GetList""#.parse().unwrap();
+    static ref ACTION_CREATE_LIST: EntityUid = r#"Action::"CreateList""#.parse().unwrap();
+    static ref ACTION_UPDATE_LIST: EntityUid = r#"Action::"UpdateList""#.parse().unwrap();
     static ref ACTION_DELETE_LIST: EntityUid = r#"Action::"DeleteList""#.parse().unwrap();
+    static ref ACTION_TRANSFER_LIST: EntityUid = r#"Action::"TransferList""#.parse().unwrap();
+    static ref ACTION_ADMINISTER: EntityUid = r#"Action::"Administer""#.parse().unwrap();
+    // Every action, by name, for `capability_summary` and `EntityStore::bootstrap`
+    pub(crate) static ref ACTIONS: [(&'static str, &'static EntityUid); 11] = [
+        ("EditShare", &*ACTION_EDIT_SHARE),
+        ("UpdateTask", &*ACTION_UPDATE_TASK),
+        ("CreateTask", &*ACTION_CREATE_TASK),
+        ("DeleteTask", &*ACTION_DELETE_TASK),
+        ("GetLists", &*ACTION_GET_LISTS),
+        ("GetList", &*ACTION_GET_LIST),
+        ("CreateList", &*ACTION_CREATE_LIST),
+        ("UpdateList", &*ACTION_UPDATE_LIST),
+        ("DeleteList", &*ACTION_DELETE_LIST),
+        ("TransferList", &*ACTION_TRANSFER_LIST),
+        ("Administer", &*ACTION_ADMINISTER),
+    ];
+    // The actions that apply to a list, for `get_list_actions`
+    static ref LIST_ACTIONS: [&'static EntityUid; 8] = [
+        &*ACTION_GET_LIST,
+        &*ACTION_UPDATE_LIST,
+        &*ACTION_DELETE_LIST,
+        &*ACTION_CREATE_TASK,
+        &*ACTION_UPDATE_TASK,
+        &*ACTION_DELETE_TASK,
+        &*ACTION_TRANSFER_LIST,
+        &*ACTION_EDIT_SHARE,
+    ];
+    // The templates in `policies-templates.cedar` that shares are linked against
+    #[cfg(feature = "use-templates")]
+    static ref READER_TEMPLATE: PolicyId = "reader-template".parse().unwrap();
+    #[cfg(feature = "use-templates")]
+    static ref EDITOR_TEMPLATE: PolicyId = "editor-template".parse().unwrap();
 }
 
 pub struct AppContext {
     entities: EntityStore,
+    entities_cache: EntitiesCache,
     authorizer: Authorizer,
     policies: PolicySet,
     schema: Schema,
+    config: AppConfig,
+    webhooks: WebhookRegistry,
+    presence: Presence,
+    activity: ActivityLog,
+    tokens: TokenRegistry,
+    // Behind a lock so that handlers which only read the store can still spend tokens
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    auth_cache: Option<Mutex<AuthCache>>,
+    policy_slices: Option<PolicySlices>,
+    decisions: Option<DecisionLogger>,
+    // The context of the query being handled, which `is_authorized` authorizes with
+    request_context: ContextBuilder,
     recv: Receiver<AppQuery>,
 }
 
@@ -258,6 +1259,78 @@ impl From<ReadError> for Error {
         }
     }
 }
+
+/// Loads the entities file at `path`, in the store's own format or in Cedar's, or starts from
+/// `EntityStore::bootstrap` if there's no file there yet
+fn load_entities(
+    path: &Path,
+    schema: &Schema,
+    config: &AppConfig,
+) -> std::result::Result<EntityStore, ContextError> {
+    if !path.exists() {
+        info!(
+            "No entities file at {}, starting with just the application",
+            path.display()
+        );
+        return Ok(EntityStore::bootstrap(schema)?);
+    }
+    let snapshot: serde_json::Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+    let store = if snapshot.is_array() {
+        // Cedar's entity format, e.g. as `api/admin/entities` exports it
+        let json = snapshot.to_string();
+        EntityStore::import_entities_json(&json, schema)?
+    } else if config.lenient_load {
+        #[cfg(feature = "sealed-fields")]
+        let snapshot = match &config.entities_key {
+            Some(key) => crate::sealing::unseal(snapshot, key)?,
+            None => snapshot,
+        };
+        let store = EntityStore::load_lenient(snapshot, schema)?;
+        for q in store.quarantined() {
+            error!("Quarantined entity {}: {}", q.key, q.reason);
+        }
+        store
+    } else {
+        #[cfg(feature = "sealed-fields")]
+        let store = match &config.entities_key {
+            Some(key) => EntityStore::from_sealed(snapshot, key)?,
+            None => serde_json::from_value(snapshot)?,
+        };
+        #[cfg(not(feature = "sealed-fields"))]
+        let store: EntityStore = serde_json::from_value(snapshot)?;
+        store.validate(schema)?;
+        store
+    };
+    // These don't stop the load, but they'll fail any request that needs the missing entity
+    for d in store.validate_references() {
+        warn!(
+            "{} refers to {} through `{}`, which does not exist",
+            d.entity, d.missing, d.field
+        );
+    }
+    Ok(store)
+}
+
+/// Parses `src` as a policy set and validates it against `schema`, failing with the
+/// validator's errors if there are any, so that a mistaken policy never goes live.
+/// Validation warnings are logged but don't fail the load.
+pub fn load_policies(src: &str, schema: &Schema) -> std::result::Result<PolicySet, ContextError> {
+    let policies = rename_from_id_annotation(src.parse()?)?;
+    let validator = Validator::new(schema.clone());
+    let output = validator.validate(&policies, ValidationMode::default());
+    for warning in output.validation_warnings() {
+        warn!("Policy validation warning: {warning}");
+    }
+    if !output.validation_passed() {
+        let error_string = output
+            .validation_errors()
+            .map(|err| format!("{err}"))
+            .join("\n");
+        return Err(ContextError::Validation(error_string));
+    }
+    Ok(policies)
+}
+
 /// Renames policies and templates based on (@id("new_id") annotation.
 /// If no such annotation exists, it keeps the current id.
 ///
@@ -297,45 +1370,94 @@ impl AppContext {
         entities_path: impl Into<PathBuf>,
         schema_path: impl Into<PathBuf>,
         policies_path: impl Into<PathBuf>,
+        config: AppConfig,
     ) -> std::result::Result<Sender<AppQuery>, ContextError> {
         let schema_path = schema_path.into();
         let policies_path = policies_path.into();
-        let schema_file = std::fs::File::open(&schema_path)?;
-        let (schema, _) = Schema::from_file_natural(schema_file)?;
+        let schema_src = std::fs::read_to_string(&schema_path)?;
+        let (schema, _) = Schema::from_str_natural(&schema_src)?;
+        if !schemagen::matches_schema_source(&schema_src) {
+            warn!(
+                "{} has drifted from the object model, see `schemagen::schema_source`",
+                schema_path.display()
+            );
+        }
+
+        let mut entities = load_entities(&entities_path.into(), &schema, &config)?;
+        if config.uuid_ids {
+            entities = entities.with_id_source(UuidIds);
+        }
+        if let Some(path) = &config.extra_entities {
+            let json = std::fs::read_to_string(path)?;
+            entities.register_extra(JsonEntities::from_json_str(&json, &schema)?);
+        }
+        #[cfg(feature = "undo")]
+        if let Some(depth) = config.undo_depth {
+            entities.set_undo_depth(depth);
+        }
 
-        let entities_file = std::fs::File::open(entities_path.into())?;
-        let entities = serde_json::from_reader(entities_file)?;
+        let decision_log: Option<Box<dyn DecisionLog>> = match &config.decision_log {
+            None => None,
+            Some(DecisionLogTarget::File(path)) => Some(Box::new(FileLog::open(path)?)),
+            Some(DecisionLogTarget::Tracing) => Some(Box::new(TracingLog)),
+        };
 
         let policy_src = std::fs::read_to_string(&policies_path)?;
-        let policies0 = policy_src.parse()?;
-        let policies = rename_from_id_annotation(policies0)?;
-        let validator = Validator::new(schema.clone());
-        let output = validator.validate(&policies, ValidationMode::default());
-        if output.validation_passed() {
-            info!("Validation passed!");
-            let authorizer = Authorizer::new();
-            let (send, recv) = tokio::sync::mpsc::channel(100);
-            let tx = send.clone();
-            tokio::spawn(async move {
-                info!("Serving application server!");
-                policy_store::spawn_watcher(policies_path, tx).await;
-                let c = Self {
-                    entities,
-                    authorizer,
-                    policies,
-                    schema,
-                    recv,
-                };
-                c.serve().await
-            });
+        let policies = load_policies(&policy_src, &schema)?;
+        info!("Validation passed!");
+        let (send, recv) = tokio::sync::mpsc::channel(100);
+        let tx = send.clone();
+        tokio::spawn(async move {
+            info!("Serving application server!");
+            policy_store::spawn_watcher(policies_path, tx).await;
+            let c = Self::new(entities, policies, schema, config, decision_log, recv);
+            c.warn_dangling_attribute_references();
+            c.serve().await
+        });
 
-            Ok(send)
-        } else {
-            let error_string = output
-                .validation_errors()
-                .map(|err| format!("{err}"))
-                .join("\n");
-            Err(ContextError::Validation(error_string))
+        Ok(send)
+    }
+
+    // The context `spawn` serves. It must be built on the runtime, where the webhook and
+    // decision log workers it starts run.
+    fn new(
+        entities: EntityStore,
+        policies: PolicySet,
+        schema: Schema,
+        config: AppConfig,
+        decision_log: Option<Box<dyn DecisionLog>>,
+        recv: Receiver<AppQuery>,
+    ) -> Self {
+        let mut webhooks = WebhookRegistry::spawn();
+        for url in &config.webhooks {
+            webhooks.register_global(url.clone());
+        }
+        for host in &config.webhook_hosts {
+            webhooks.allow_host(host.clone());
+        }
+        let rate_limiter = config.rate_limit.map(|l| Mutex::new(RateLimiter::new(l)));
+        let auth_cache = config
+            .auth_cache_capacity
+            .map(|capacity| Mutex::new(AuthCache::new(capacity)));
+        let policy_slices = config.policy_slicing.then(PolicySlices::default);
+        let decisions = decision_log.map(DecisionLogger::spawn);
+        Self {
+            entities,
+            entities_cache: EntitiesCache::default(),
+            authorizer: Authorizer::new(),
+            policies,
+            schema,
+            config,
+            webhooks,
+            presence: Presence::default(),
+            activity: ActivityLog::default(),
+            tokens: TokenRegistry::default(),
+            rate_limiter,
+            auth_cache,
+            policy_slices,
+            decisions,
+            request_context: ContextBuilder::default(),
+            recv,
         }
     }
 
@@ -343,19 +1465,23 @@ impl AppContext {
     async fn serve(mut self) -> Result<()> {
         loop {
             if let Some(msg) = self.recv.recv().await {
+                let read_only = msg.kind.is_read_only();
+                self.request_context = msg.context;
                 let r = match msg.kind {
-                    AppQueryKind::GetList(r) => self.get_list(r),
-                    AppQueryKind::CreateList(r) => self.create_list(r),
-                    AppQueryKind::UpdateList(r) => self.update_list(r),
-                    AppQueryKind::DeleteList(r) => self.delete_list(r),
-                    AppQueryKind::CreateTask(r) => self.create_task(r),
-                    AppQueryKind::UpdateTask(r) => self.update_task(r),
-                    AppQueryKind::DeleteTask(r) => self.delete_task(r),
-                    AppQueryKind::GetLists(r) => self.get_lists(r),
-                    AppQueryKind::AddShare(r) => self.add_share(r),
-                    AppQueryKind::DeleteShare(r) => self.delete_share(r),
-                    AppQueryKind::UpdatePolicySet(set) => self.update_policy_set(set),
+                    AppQueryKind::CheckAccess(r) if r.groups.is_empty() => {
+                        self.check_access_blocking(r).await
+                    }
+                    kind => self.handle_guarded(read_only, |app| app.handle(kind)),
                 };
+                // Cached responses may be stale once anything has changed
+                if let (false, Some(cache)) = (read_only, &self.auth_cache) {
+                    let mut cache = cache.lock().unwrap();
+                    cache.clear();
+                    debug!("Cleared authorization cache: {:?}", cache.stats());
+                }
+                if let (false, Some(slices)) = (read_only, &self.policy_slices) {
+                    slices.clear();
+                }
                 if let Err(e) = msg.sender.send(r) {
                     trace!("Failed send response: {:?}", e);
                 }
@@ -363,6 +1489,114 @@ impl AppContext {
         }
     }
 
+    fn handle(&mut self, kind: AppQueryKind) -> Result<AppResponse> {
+        match kind {
+            AppQueryKind::GetList(r) => self.get_list(r),
+            AppQueryKind::GetManyLists(r) => self.get_many_lists(r),
+            AppQueryKind::GetTasksPage(r) => self.get_tasks_page(r),
+            AppQueryKind::CreateList(r) => self.create_list(r),
+            AppQueryKind::UpdateList(r) => self.update_list(r),
+            AppQueryKind::DeleteList(r) => self.delete_list(r),
+            AppQueryKind::MergeLists(r) => self.merge_lists(r),
+            AppQueryKind::TransferList(r) => self.transfer_list(r),
+            AppQueryKind::SetTaskSchema(r) => self.set_task_schema(r),
+            AppQueryKind::SetTemplate(r) => self.set_template(r),
+            AppQueryKind::RegisterWebhook(r) => self.register_webhook(r),
+            AppQueryKind::TouchPresence(r) => self.touch_presence(r),
+            AppQueryKind::GetPresence(r) => self.get_presence(r),
+            AppQueryKind::GetActivity(r) => self.get_activity(r),
+            AppQueryKind::GetGrantingTeams(r) => self.get_granting_teams(r),
+            AppQueryKind::IssueListToken(r) => self.issue_list_token(r),
+            AppQueryKind::GetListWithToken(r) => self.get_list_with_token(r),
+            AppQueryKind::CreateTask(r) => self.create_task(r),
+            AppQueryKind::UpdateTask(r) => self.update_task(r),
+            AppQueryKind::CompleteTasks(r) => self.complete_tasks(r),
+            AppQueryKind::DeleteTask(r) => self.delete_task(r),
+            AppQueryKind::CreateTaskWithToken(r) => self.create_task_with_token(r),
+            AppQueryKind::GetLists(r) => self.get_lists(r),
+            AppQueryKind::ReorderLists(r) => self.reorder_lists(r),
+            AppQueryKind::GetListSummaries(r) => self.get_list_summaries(r),
+            AppQueryKind::GetDuplicateLists(r) => self.get_duplicate_lists(r),
+            AppQueryKind::GetListsByOwner(r) => self.get_lists_by_owner(r),
+            AppQueryKind::GetCapabilities(r) => self.get_capabilities(r),
+            AppQueryKind::GetListActions(r) => self.get_list_actions(r),
+            AppQueryKind::GetTrace(r) => self.get_trace(r),
+            AppQueryKind::GetRequirements(r) => self.get_requirements(r),
+            AppQueryKind::GetDeniedActions(r) => self.get_denied_actions(r),
+            AppQueryKind::GetGrantingPolicies(r) => self.get_granting_policies(r),
+            AppQueryKind::CheckAccess(r) => self.check_access(r),
+            AppQueryKind::GetMemberships(r) => self.get_memberships(r),
+            AppQueryKind::ExportUserData(r) => self.export_user_data(r),
+            AppQueryKind::AddShare(r) => self.add_share(r),
+            AppQueryKind::DeleteShare(r) => self.delete_share(r),
+            AppQueryKind::CreateTeam(r) => self.create_team(r),
+            AppQueryKind::SyncGroup(r) => self.sync_group(r),
+            AppQueryKind::OffboardUser(r) => self.offboard(r),
+            AppQueryKind::CloneTeam(r) => self.clone_team(r),
+            AppQueryKind::GetTeamAncestors(r) => self.get_team_ancestors(r),
+            AppQueryKind::SeedEntities(r) => self.seed_entities(r),
+            AppQueryKind::CreateApp(r) => self.create_app(r),
+            AppQueryKind::GetAppLists(r) => self.get_app_lists(r),
+            AppQueryKind::ExportAcls(r) => self.export_acls(r),
+            AppQueryKind::ImportAcls(r) => self.import_acls(r),
+            AppQueryKind::GetStats(r) => self.get_stats(r),
+            AppQueryKind::FindEntities(r) => self.find_entities(r),
+            AppQueryKind::GetDeletionImpact(r) => self.get_deletion_impact(r),
+            AppQueryKind::GetFingerprint(r) => self.get_fingerprint(r),
+            AppQueryKind::VerifySync(r) => self.check_sync(r),
+            AppQueryKind::GetReproCase(r) => self.get_repro_case(r),
+            AppQueryKind::ExportEntities(r) => self.export_entities(r),
+            AppQueryKind::ArchiveEntity(r) => self.archive_entity(r),
+            AppQueryKind::RestoreEntity(r) => self.restore_entity(r),
+            #[cfg(feature = "undo")]
+            AppQueryKind::Undo(r) => self.undo(r),
+            #[cfg(feature = "sealed-fields")]
+            AppQueryKind::ExportSealed(r) => self.export_sealed(r),
+            #[cfg(feature = "partial-eval")]
+            AppQueryKind::GetPartialDecision(r) => self.get_partial_decision(r),
+            AppQueryKind::UpdatePolicySet(set) => self.update_policy_set(set),
+        }
+    }
+
+    // Runs `handler`, recovering if it panics rather than taking the whole server down with it.
+    // A handler that may change anything runs against a snapshot of the store and policies, which
+    // are put back as they were if it panics partway through.
+    fn handle_guarded(
+        &mut self,
+        read_only: bool,
+        handler: impl FnOnce(&mut Self) -> Result<AppResponse>,
+    ) -> Result<AppResponse> {
+        let saved = (!read_only).then(|| (self.entities.snapshot(), self.policies.clone()));
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| handler(self)));
+        handled.unwrap_or_else(|_| {
+            error!("Handler panicked, recovering");
+            if let Some((entities, policies)) = saved {
+                self.entities.restore(entities);
+                self.policies = policies;
+            }
+            self.recover_from_panic();
+            Err(Error::Panicked)
+        })
+    }
+
+    // A handler that panics may have done so holding a lock, poisoning it for every later
+    // request, or partway through updating a cache. The caches are started afresh, and the rate
+    // limiter keeps its buckets behind a new lock.
+    fn recover_from_panic(&mut self) {
+        self.entities_cache = EntitiesCache::default();
+        self.policy_slices = self.config.policy_slicing.then(PolicySlices::default);
+        if let Some(capacity) = self.config.auth_cache_capacity {
+            self.auth_cache = Some(Mutex::new(AuthCache::new(capacity)));
+        }
+        if let (Some(limiter), Some(limit)) = (&mut self.rate_limiter, self.config.rate_limit) {
+            let buckets = std::mem::replace(
+                limiter.get_mut().unwrap_or_else(PoisonError::into_inner),
+                RateLimiter::new(limit),
+            );
+            *limiter = Mutex::new(buckets);
+        }
+    }
+
     #[tracing::instrument(skip(policy_set))]
     fn update_policy_set(&mut self, policy_set: PolicySet) -> Result<AppResponse> {
         let mut new_policies = rename_from_id_annotation(policy_set)?;
@@ -396,13 +1630,17 @@ impl AppContext {
         if !err {
             let validator = Validator::new(self.schema.clone());
             let output = validator.validate(&new_policies, ValidationMode::default());
+            for warning in output.validation_warnings() {
+                warn!("Policy validation warning: {warning}");
+            }
             if !output.validation_passed() {
                 for e in output.validation_errors() {
                     error!("Error validating linked policies: {e}")
                 }
             } else {
                 self.policies = new_policies;
-                info!("Reloaded policy set")
+                info!("Reloaded policy set");
+                self.warn_dangling_attribute_references();
             }
         }
         Ok(AppResponse::Unit(()))
@@ -427,20 +1665,27 @@ impl AppContext {
     }
 
     fn add_share(&mut self, r: AddShare) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        self.share(&r.list, r.share_with, r.role)?;
+        self.notify_list_change(&r.uid, &r.list, Activity::Shared);
+        Ok(AppResponse::Unit(()))
+    }
+
+    // Gives `share_with` the access of `role` to `list`, without authorizing anything
+    fn share(&mut self, list: &ListUid, share_with: UserOrTeamUid, role: ShareRole) -> Result<()> {
         #[cfg(feature = "use-templates")]
         {
             // Confirm that the identified list and sharer are known
-            let _list = self.entities.get_list(&r.list)?;
-            let _target_entity = self.entities.get_user_or_team_mut(&r.share_with)?;
+            let _list = self.entities.get_list(list)?;
+            let _target_entity = self.entities.get_user_or_team_mut(&share_with)?;
             // Link a template to register the new permission
-            let tid = match r.role {
-                ShareRole::Reader => PolicyId::from_str("reader-template")?,
-                ShareRole::Editor => PolicyId::from_str("editor-template")?,
+            let tid = match role {
+                ShareRole::Reader => READER_TEMPLATE.clone(),
+                ShareRole::Editor => EDITOR_TEMPLATE.clone(),
             };
             // Construct template linking environment
-            let target_euid: &cedar_policy::EntityUid = r.share_with.as_ref();
-            let list_euid: &cedar_policy::EntityUid = r.list.as_ref();
+            let target_euid: &cedar_policy::EntityUid = share_with.as_ref();
+            let list_euid: &cedar_policy::EntityUid = list.as_ref();
             let env: HashMap<SlotId, cedar_policy::EntityUid> = [
                 (SlotId::principal(), target_euid.clone()),
                 (SlotId::resource(), list_euid.clone()),
@@ -448,146 +1693,2288 @@ impl AppContext {
             .into_iter()
             .collect();
             // Link it!
-            let pid = Self::linked_policy_id(r.role, r.share_with, r.list)?;
+            let pid = Self::linked_policy_id(role, share_with, list.clone())?;
             self.policies.link(tid, pid.clone(), env)?;
             info!("Created policy {pid}");
         }
         #[cfg(not(feature = "use-templates"))]
         {
-            let list = self.entities.get_list(&r.list)?;
-            let team_uid = list.get_team(r.role).clone();
-            let target_entity = self.entities.get_user_or_team_mut(&r.share_with)?;
+            let list = self.entities.get_list(list)?;
+            let team_uid = list.get_team(role).clone();
+            let target_entity = self.entities.get_user_or_team_mut(&share_with)?;
             target_entity.insert_parent(team_uid);
         }
-        Ok(AppResponse::Unit(()))
+        Ok(())
     }
 
     fn delete_share(&mut self, r: DeleteShare) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
         #[cfg(feature = "use-templates")]
         {
             // Confirm that the identified list and un-sharer are known
             let _list = self.entities.get_list(&r.list)?;
             let _target_entity = self.entities.get_user_or_team_mut(&r.unshare_with)?;
             // Unlink the policy that provided the permission
-            let pid = Self::linked_policy_id(r.role, r.unshare_with, r.list)?;
+            let pid = Self::linked_policy_id(r.role, r.unshare_with, r.list.clone())?;
             self.policies.unlink(pid.clone())?;
             info!("Removed policy {pid}");
         }
         #[cfg(not(feature = "use-templates"))]
-        {
-            let list = self.entities.get_list(&r.list)?;
-            let team_uid = list.get_team(r.role).clone();
-            let target_entity = self.entities.get_user_or_team_mut(&r.unshare_with)?;
-            target_entity.delete_parent(&team_uid);
-        }
+        self.entities.unshare_list(&r.list, &r.unshare_with)?;
+        self.notify_list_change(&r.uid, &r.list, Activity::Unshared);
         Ok(AppResponse::Unit(()))
     }
 
     fn update_task(&mut self, r: UpdateTask) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_UPDATE_TASK, &r.list)?;
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_TASK, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
         let list = self.entities.get_list_mut(&r.list)?;
         let task = list
             .get_task_mut(r.task)
-            .ok_or_else(|| Error::InvalidTaskId(r.list.into(), r.task))?;
+            .ok_or_else(|| Error::InvalidTaskId(r.list.clone().into(), r.task))?;
         if let Some(state) = r.state {
             task.set_state(state);
         }
         if let Some(name) = r.name {
             task.set_name(name);
         }
+        self.notify_list_update(&r.uid, &before, Activity::TaskUpdated);
         Ok(AppResponse::Unit(()))
     }
 
+    fn complete_tasks(&mut self, r: CompleteTasks) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_TASK, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        let pattern = r.name_contains.unwrap_or_default();
+        let changed = self.entities.complete_tasks_where(
+            &r.list,
+            |task| task.name().contains(&pattern),
+            r.completed,
+        )?;
+        if changed > 0 {
+            self.notify_list_update(&r.uid, &before, Activity::TasksCompleted);
+        }
+        Ok(AppResponse::Count(changed))
+    }
+
     fn create_task(&mut self, r: CreateTask) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_CREATE_TASK, &r.list)?;
-        let list = self.entities.get_list_mut(&r.list)?;
-        let task_id = list.create_task(r.name);
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_CREATE_TASK, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        let task_id = if r.fields.is_empty() {
+            let list = self.entities.get_list_mut(&r.list)?;
+            list.create_task(r.name)
+        } else {
+            self.entities
+                .add_task_validated(&r.list, r.name, r.fields)?
+        };
+        self.notify_list_update(&r.uid, &before, Activity::TaskAdded);
         Ok(AppResponse::TaskId(task_id))
     }
 
     fn delete_task(&mut self, r: DeleteTask) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_DELETE_TASK, &r.list)?;
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_DELETE_TASK, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
         let list = self.entities.get_list_mut(&r.list)?;
         list.delete_task(r.task)
-            .ok_or_else(|| Error::InvalidTaskId(r.list.into(), r.task))?;
+            .ok_or_else(|| Error::InvalidTaskId(r.list.clone().into(), r.task))?;
+        self.notify_list_update(&r.uid, &before, Activity::TaskDeleted);
         Ok(AppResponse::Unit(()))
     }
 
     fn get_lists(&self, r: GetLists) -> Result<AppResponse> {
-        let t: EntityTypeName = "List".parse().unwrap();
         self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
 
-        Ok(AppResponse::Lists(
-            self.entities
-                .euids()
-                .filter(|euid| euid.type_name() == &t)
-                .filter(|euid| self.is_authorized(&r.uid, &*ACTION_GET_LIST, euid).is_ok())
-                .cloned()
-                .collect::<Vec<EntityUid>>()
-                .into(),
+        let lists = self
+            .entities
+            .lists()
+            .filter(|list| list.is_template() == r.templates)
+            .map(|list| list.uid().clone().into())
+            .collect::<Vec<EntityUid>>();
+        let mut visible = self
+            .is_authorized_each(&r.uid, &*ACTION_GET_LIST, &lists)
+            .into_iter()
+            .filter_map(|(euid, decision)| decision.ok().map(|()| euid))
+            .collect::<Vec<EntityUid>>();
+        match self.entities.get_user(&r.uid) {
+            Ok(user) => user.sort_lists(&mut visible),
+            Err(_) => visible.sort_by_cached_key(|euid| euid.to_string()),
+        }
+        Ok(AppResponse::Lists(visible.into()))
+    }
+
+    /// Sets the order `get_lists` returns the caller's lists in.
+    /// Every list in the order must be one the caller may see.
+    fn reorder_lists(&mut self, r: ReorderLists) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+        for (list, decision) in
+            r.order
+                .iter()
+                .zip(self.is_authorized_batch(&r.uid, &*ACTION_GET_LIST, &r.order))
+        {
+            decision?;
+            self.entities.get_list(list)?;
+        }
+        self.entities
+            .get_user_mut(&r.uid)?
+            .set_list_order(r.order)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    /// Like `get_lists`, but returns a summary of each list rather than only its euid
+    fn get_list_summaries(&self, r: GetListSummaries) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+
+        let summaries = self.entities.list_summaries();
+        let decisions =
+            self.is_authorized_batch(&r.uid, &*ACTION_GET_LIST, summaries.iter().map(|s| &s.uid));
+        Ok(AppResponse::ListSummaries(
+            summaries
+                .into_iter()
+                .zip(decisions)
+                .filter_map(|(summary, decision)| decision.ok().map(|()| summary))
+                .collect(),
         ))
     }
 
-    fn create_list(&mut self, r: CreateList) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_CREATE_LIST, &*APPLICATION_TINY_TODO)?;
+    /// Groups of lists with the same content, as far as the caller may see them
+    fn get_duplicate_lists(&self, r: GetDuplicateLists) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
 
-        let euid = self
+        let mut groups = self
             .entities
-            .fresh_euid::<ListUid>(TYPE_LIST.clone())
-            .unwrap();
-        let l = List::new(&mut self.entities, euid.clone(), r.uid, r.name);
-        self.entities.insert_list(l);
+            .find_duplicate_lists()
+            .into_iter()
+            .map(|group| {
+                let decisions = self.is_authorized_batch(&r.uid, &*ACTION_GET_LIST, &group);
+                group
+                    .into_iter()
+                    .zip(decisions)
+                    .filter_map(|(list, decision)| decision.ok().map(|()| list))
+                    .sorted_by_cached_key(|list| list.as_ref().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|group| group.len() > 1)
+            .collect::<Vec<_>>();
+        groups.sort_by_cached_key(|group| group[0].as_ref().to_string());
+        Ok(AppResponse::ListGroups(groups))
+    }
 
-        Ok(AppResponse::euid(euid))
+    /// The lists the caller may see, bucketed by their owner
+    fn get_lists_by_owner(&self, r: GetListsByOwner) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+
+        let visible = |lists: Vec<ListUid>| {
+            let decisions = self.is_authorized_batch(&r.uid, &*ACTION_GET_LIST, &lists);
+            lists
+                .into_iter()
+                .zip(decisions)
+                .filter_map(|(list, decision)| decision.ok().map(|()| list))
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let grouped = self.entities.lists_grouped_by_owner();
+        Ok(AppResponse::ListsByOwner(ListsByOwner {
+            owned: grouped
+                .owned
+                .into_iter()
+                .map(|(owner, lists)| (owner, visible(lists)))
+                .filter(|(_, lists)| !lists.is_empty())
+                .collect(),
+            ownerless: visible(grouped.ownerless),
+        }))
     }
 
-    fn get_list(&self, r: GetList) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_GET_LIST, &r.list)?;
-        let list = self.entities.get_list(&r.list)?.clone();
-        Ok(AppResponse::GetList(Box::new(list)))
+    fn get_capabilities(&self, r: GetCapabilities) -> Result<AppResponse> {
+        let mut capabilities = self
+            .capability_summary(&r.uid)?
+            .into_iter()
+            .map(|(resource, actions)| Capabilities {
+                resource,
+                actions: actions.into_iter().sorted().collect(),
+            })
+            .collect::<Vec<_>>();
+        capabilities.sort_by_key(|c| c.resource.to_string());
+        Ok(AppResponse::Capabilities(capabilities))
     }
 
-    fn update_list(&mut self, r: UpdateList) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
-        let list = self.entities.get_list_mut(&r.list)?;
-        list.update_name(r.name);
+    // Like `get_capabilities` for a single list, e.g. to decide which entries of its menu to show
+    fn get_list_actions(&self, r: GetListActions) -> Result<AppResponse> {
+        let allowed = self.is_authorized_any(&r.uid, &r.list, &*LIST_ACTIONS)?;
+        let names = ACTIONS
+            .iter()
+            .filter(|(_, action)| allowed.contains(action))
+            .map(|(name, _)| *name)
+            .sorted()
+            .collect();
+        Ok(AppResponse::Actions(names))
+    }
+
+    // Traces reveal the policies and the attributes of the entities involved, so they're only
+    // handed out by deployments that already show callers why they were denied
+    fn get_trace(&self, r: GetTrace) -> Result<AppResponse> {
+        if self.config.denial_verbosity != DenialVerbosity::Detailed {
+            return Err(Error::DiagnosticsDisabled);
+        }
+        let action = parse_action(&r.action)?;
+        let trace = self.authorization_trace_json(&r.uid, &action, &r.resource)?;
+        Ok(AppResponse::Trace(trace))
+    }
+
+    fn check_access(&self, r: CheckAccess) -> Result<AppResponse> {
+        let action = parse_action(&r.action)?;
+        self.is_authorized_with_dynamic_groups(&r.uid, &action, &r.resource, &r.groups)?;
         Ok(AppResponse::Unit(()))
     }
 
-    fn delete_list(&mut self, r: DeleteList) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_DELETE_LIST, &r.list)?;
-        self.entities.delete_entity(&r.list)?;
+    // A check with no extra teams needs nothing but the store's own entities, so it's evaluated
+    // on the blocking pool, where a large policy set can't hold up the app task
+    async fn check_access_blocking(&self, r: CheckAccess) -> Result<AppResponse> {
+        let action = parse_action(&r.action)?;
+        self.is_authorized_spawn_blocking(&r.uid, &action, &r.resource)
+            .await?;
         Ok(AppResponse::Unit(()))
     }
 
-    #[tracing::instrument(skip_all)]
-    pub fn is_authorized(
-        &self,
-        principal: impl AsRef<EntityUid>,
-        action: impl AsRef<EntityUid>,
-        resource: impl AsRef<EntityUid>,
-    ) -> Result<()> {
-        let es = self.entities.as_entities(&self.schema);
-        let q = Request::new(
-            Some(principal.as_ref().clone().into()),
-            Some(action.as_ref().clone().into()),
-            Some(resource.as_ref().clone().into()),
-            Context::empty(),
-            Some(&self.schema),
-        )
-        .map_err(|e| Error::Request(e.to_string()))?;
-        info!(
-            "is_authorized request: principal: {}, action: {}, resource: {}",
-            principal.as_ref(),
-            action.as_ref(),
-            resource.as_ref()
-        );
-        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
-        info!("Auth response: {:?}", response);
-        match response.decision() {
-            Decision::Allow => Ok(()),
-            Decision::Deny => Err(Error::AuthDenied(response.diagnostics().clone())),
+    fn get_requirements(&self, r: GetRequirements) -> Result<AppResponse> {
+        if self.config.denial_verbosity != DenialVerbosity::Detailed {
+            return Err(Error::DiagnosticsDisabled);
+        }
+        let action = parse_action(&r.action)?;
+        let requirements = self.authorization_requirements(&r.uid, &action, &r.resource)?;
+        Ok(AppResponse::Requirements(requirements))
+    }
+
+    // Like traces, these name the permit policies that matched
+    fn get_granting_policies(&self, r: GetGrantingPolicies) -> Result<AppResponse> {
+        if self.config.denial_verbosity != DenialVerbosity::Detailed {
+            return Err(Error::DiagnosticsDisabled);
+        }
+        let action = parse_action(&r.action)?;
+        let info = self.is_authorized_with_diagnostics(&r.uid, &action, &r.resource)?;
+        Ok(AppResponse::Granted(info))
+    }
+
+    // Like traces, these name the forbid policies that matched
+    fn get_denied_actions(&self, r: GetDeniedActions) -> Result<AppResponse> {
+        if self.config.denial_verbosity != DenialVerbosity::Detailed {
+            return Err(Error::DiagnosticsDisabled);
+        }
+        let denied = self
+            .denied_actions(&r.uid, &r.resource)?
+            .into_iter()
+            .map(|(action, reason)| DeniedAction {
+                action,
+                forbidden_by: match reason {
+                    DenialReason::ExplicitForbid(forbids) => forbids,
+                    DenialReason::NoMatchingPermit => vec![],
+                },
+            })
+            .collect();
+        Ok(AppResponse::DeniedActions(denied))
+    }
+
+    fn create_team(&mut self, r: CreateTeam) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let team = self.entities.create_team_with_members(r.members)?;
+        Ok(AppResponse::euid(team))
+    }
+
+    // Syncing a group more than once, even from requests that race, finds the same team
+    fn sync_group(&mut self, r: SyncGroup) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let (team, created) = self.entities.get_or_create_team(&r.name)?;
+        let team: EntityUid = team.uid().clone().into();
+        if created {
+            info!("Created team {team} for group `{}`", r.name);
+        }
+        Ok(AppResponse::euid(team))
+    }
+
+    fn offboard(&mut self, r: OffboardUser) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let report = self.offboard_user(&r.user, &r.successor)?;
+        Ok(AppResponse::Offboard(report))
+    }
+
+    fn clone_team(&mut self, r: CloneTeam) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let team = self.entities.clone_team(&r.team)?;
+        Ok(AppResponse::euid(team))
+    }
+
+    fn get_team_ancestors(&self, r: GetTeamAncestors) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let mut teams = self
+            .entities
+            .team_ancestors(&r.team)?
+            .into_iter()
+            .map(EntityUid::from)
+            .collect::<Vec<_>>();
+        teams.sort();
+        Ok(AppResponse::Euids(teams))
+    }
+
+    // All of `r.entities` are inserted, or none are
+    fn seed_entities(&mut self, r: SeedEntities) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let euids = self.entities.insert_many(r.entities, &self.schema)?;
+        Ok(AppResponse::Euids(euids))
+    }
+
+    // Another application root, e.g. for another tenant, whose users are those with it as a parent
+    fn create_app(&mut self, r: CreateApp) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        if r.app.type_name() != &*TYPE_APPLICATION {
+            return Err(EntityTypeError::single(&TYPE_APPLICATION, r.app).into());
+        }
+        self.entities.insert_app(Application::new(r.app.clone()));
+        Ok(AppResponse::euid(r.app))
+    }
+
+    fn get_app_lists(&self, r: GetAppLists) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let app = self.entities.get_app(&r.app)?;
+        let mut lists = self
+            .entities
+            .lists_in_app(app.euid())
+            .map(|list| list.uid().clone().into())
+            .collect::<Vec<EntityUid>>();
+        lists.sort();
+        Ok(AppResponse::Lists(lists.into()))
+    }
+
+    fn export_acls(&self, r: ExportAcls) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        Ok(AppResponse::Acls(self.entities.export_acls()))
+    }
+
+    fn import_acls(&mut self, r: ImportAcls) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        self.entities.import_acls(r.snapshot)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    // Attributes such as users' locations are only for admins to search
+    fn find_entities(&self, r: FindEntities) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let found = self.entities.find_by_attribute(r.ty, &r.attr, &r.value);
+        Ok(AppResponse::Euids(found))
+    }
+
+    fn get_deletion_impact(&self, r: GetDeletionImpact) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        Ok(AppResponse::DeletionImpact(self.deletion_impact(&r.entity)))
+    }
+
+    fn get_fingerprint(&self, r: GetFingerprint) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let fingerprint = self
+            .entities
+            .authz_fingerprint(&self.policies, &self.schema)?;
+        Ok(AppResponse::Fingerprint(Fingerprint(fingerprint)))
+    }
+
+    // For a replica to check itself against the fingerprint its primary gave
+    fn check_sync(&self, r: VerifySync) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        self.verify_sync(r.fingerprint.0)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    // The case is decided with the context this request came with, so support can reproduce
+    // what a user saw by sending the same headers
+    fn get_repro_case(&self, r: GetReproCase) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let action = parse_action(&r.action)?;
+        let case = self.repro_case(&r.principal, &action, &r.resource)?;
+        Ok(AppResponse::Repro(Box::new(case)))
+    }
+
+    fn export_entities(&self, r: ExportEntities) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let json = self.entities.export_entities_json(&self.schema)?;
+        let entities = serde_json::from_str(&json).expect("entities are exported as JSON");
+        Ok(AppResponse::Snapshot(entities))
+    }
+
+    // Archiving and restoring are for administrators only: an archived entity is invisible to
+    // every policy, so no policy could let anyone else bring it back
+    fn archive_entity(&mut self, r: ArchiveEntity) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        self.entities.archive_entity(&r.entity)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn restore_entity(&mut self, r: RestoreEntity) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        self.entities.restore_entity(&r.entity)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn get_stats(&self, r: GetStats) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        Ok(AppResponse::Stats(AdminStats {
+            cache: self.cache_stats(),
+            store: self.entities.stats(),
+        }))
+    }
+
+    /// Reverts the most recent change to the entities, for admins backing out a mistake
+    #[cfg(feature = "undo")]
+    fn undo(&mut self, r: Undo) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        self.entities.undo()?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    #[cfg(feature = "partial-eval")]
+    fn get_partial_decision(&self, r: GetPartialDecision) -> Result<AppResponse> {
+        let action = parse_action(&r.action)?;
+        let decision = self.is_authorized_partial(&r.uid, &action, &r.resource)?;
+        Ok(AppResponse::PartialDecision(decision))
+    }
+
+    /// The entities as they'd be saved, sealed with the key they were loaded with, so that a
+    /// plaintext entities file can be replaced with a sealed one
+    #[cfg(feature = "sealed-fields")]
+    fn export_sealed(&self, r: ExportSealed) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO)?;
+        let key = self
+            .config
+            .entities_key
+            .as_ref()
+            .ok_or(Error::NoEntitiesKey)?;
+        Ok(AppResponse::Snapshot(self.entities.to_sealed(key)))
+    }
+
+    fn create_list(&mut self, r: CreateList) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_CREATE_LIST, &*APPLICATION_TINY_TODO)?;
+        // Checked before anything is allocated, so a refused list leaves no teams behind
+        self.entities.check_list_quota(&r.uid)?;
+
+        // The list's reader and editor teams are inserted before the list itself, so they
+        // mustn't outlive a failed insert
+        let owner = r.uid.clone();
+        let euid = self.entities.with_transaction(|store| {
+            let euid = store.fresh_euid::<ListUid>(EntityType::List)?;
+            let l = List::new(store, euid.clone(), owner, r.name)?;
+            store.insert_list(l)?;
+            Ok(euid)
+        })?;
+
+        self.notify_list_change(&r.uid, &euid, Activity::Created);
+        if r.apply_team_defaults {
+            for (team, role) in self.entities.default_shares(&r.uid)? {
+                info!(
+                    "Sharing new list {} with {} as {:?}, the team's default",
+                    euid.as_ref(),
+                    team.as_ref(),
+                    role
+                );
+                self.share(&euid, team.into(), role)?;
+                self.notify_list_change(&r.uid, &euid, Activity::Shared);
+            }
+        }
+        Ok(AppResponse::euid(euid))
+    }
+
+    fn get_list(&self, r: GetList) -> Result<AppResponse> {
+        let list = self.authorize_and_get_list(&r.uid, &r.list)?.clone();
+        Ok(AppResponse::GetList(Box::new(list)))
+    }
+
+    // Each list is authorized as `get_list` would be, and one that can't be given doesn't
+    // fail the rest
+    fn get_many_lists(&self, r: GetManyLists) -> Result<AppResponse> {
+        let euids: Vec<EntityUid> = r.lists.iter().cloned().map(EntityUid::from).collect();
+        let lookups = r
+            .lists
+            .iter()
+            .zip(self.entities.get_many(&euids))
+            .map(
+                |(list, found)| match self.authorize_read(&r.uid, list).and(found) {
+                    Ok(EntityRef::List(l)) => ListLookup::List(Box::new(l.clone())),
+                    Ok(_) => ListLookup::Error(Error::no_such_entity(list.clone()).to_string()),
+                    Err(e) => ListLookup::Error(e.to_string()),
+                },
+            )
+            .collect();
+        Ok(AppResponse::ListLookups(lookups))
+    }
+
+    // A token lets its bearer act for the issuer on the list much as sharing the list with them
+    // would, so issuing one takes the same permission as sharing
+    fn issue_list_token(&mut self, r: IssueListToken) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        self.entities.get_list(&r.list)?;
+        let ttl_secs = r.ttl_secs.unwrap_or(60 * 60);
+        let now = Instant::now();
+        let expires = now
+            .checked_add(Duration::from_secs(ttl_secs))
+            .ok_or(Error::TokenTtl(ttl_secs))?;
+        let token = ScopedToken {
+            issuer: r.uid,
+            list: r.list,
+            capabilities: r.capabilities,
+            expires,
+        };
+        Ok(AppResponse::Token(self.tokens.issue(token, now)))
+    }
+
+    fn get_list_with_token(&self, r: GetListWithToken) -> Result<AppResponse> {
+        let token = self
+            .tokens
+            .check(&r.token, TokenCapability::Read, Instant::now())?;
+        self.get_list(GetList {
+            uid: token.issuer.clone(),
+            list: token.list.clone(),
+        })
+    }
+
+    fn create_task_with_token(&mut self, r: CreateTaskWithToken) -> Result<AppResponse> {
+        let token = self
+            .tokens
+            .check(&r.token, TokenCapability::Append, Instant::now())?;
+        let (uid, list) = (token.issuer.clone(), token.list.clone());
+        self.create_task(CreateTask {
+            uid,
+            list,
+            name: r.name,
+            fields: HashMap::new(),
+        })
+    }
+
+    fn get_tasks_page(&self, r: GetTasksPage) -> Result<AppResponse> {
+        let list = self.authorize_and_get_list(&r.uid, &r.list)?;
+        let (tasks, next) = list.tasks_page(r.after, r.limit.unwrap_or(50));
+        Ok(AppResponse::TasksPage(TasksPage {
+            tasks: tasks.to_vec(),
+            next,
+        }))
+    }
+
+    /// Authorizes `principal` to `GetList` on `list` and, if that is allowed, returns the list.
+    /// The authorization happens first, so a caller who may not read `list` can't learn
+    /// whether it exists.
+    pub fn authorize_and_get_list(&self, principal: &UserUid, list: &ListUid) -> Result<&List> {
+        self.authorize_read(principal, list)?;
+        self.entities.get_list(list)
+    }
+
+    /// Authorizes `principal` to `GetList` on `list`, reporting a denial as the deployment's
+    /// `NotFoundPolicy` asks. A missing list has no owner, readers or editors for a policy to
+    /// match, so it is always denied here; the only way to reach `NoSuchEntity` afterwards is
+    /// a policy that lets `principal` read any list, in which case existence isn't secret.
+    fn authorize_read(&self, principal: &UserUid, list: &ListUid) -> Result<()> {
+        match self.is_authorized(principal, &*ACTION_GET_LIST, list) {
+            Err(Error::AuthDenied { .. })
+                if self.config.not_found_policy == NotFoundPolicy::NotFound =>
+            {
+                Err(Error::no_such_entity(list.clone()))
+            }
+            r => r,
+        }
+    }
+
+    fn update_list(&mut self, r: UpdateList) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        let version = self.entities.update_list_name(
+            &r.list,
+            r.name,
+            &r.uid.to_string(),
+            r.version.as_ref(),
+        )?;
+        self.notify_list_update(&r.uid, &before, Activity::Renamed);
+        Ok(AppResponse::Version(version))
+    }
+
+    fn set_task_schema(&mut self, r: SetTaskSchema) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        let list = self.entities.get_list_mut(&r.list)?;
+        list.set_task_schema(r.schema);
+        self.notify_list_update(&r.uid, &before, Activity::TaskSchemaChanged);
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn set_template(&mut self, r: SetTemplate) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        let list = self.entities.get_list_mut(&r.list)?;
+        list.set_template(r.is_template);
+        self.notify_list_update(&r.uid, &before, Activity::TemplateChanged);
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn delete_list(&mut self, r: DeleteList) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_DELETE_LIST, &r.list)?;
+        self.entities.delete_entity(&r.list)?;
+        self.notify_list_change(&r.uid, &r.list, Activity::Deleted);
+        self.webhooks.unregister_all(&r.list);
+        self.presence.forget(&r.list);
+        self.activity.forget(&r.list);
+        self.tokens.forget(&r.list);
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn merge_lists(&mut self, r: MergeLists) -> Result<AppResponse> {
+        self.spend_rate_limit(&r.uid, &*ACTION_UPDATE_LIST)?;
+        self.is_authorized_all(&r.uid, &*ACTION_UPDATE_LIST, [&r.into, &r.from])?;
+        self.is_authorized(&r.uid, &*ACTION_DELETE_LIST, &r.from)?;
+        #[cfg(feature = "use-templates")]
+        let saved = (self.entities.snapshot(), self.policies.clone());
+        self.entities.merge_lists(&r.into, &r.from)?;
+        // The shares are policies, so they move separately; if they can't, the merge is undone
+        #[cfg(feature = "use-templates")]
+        if r.into != r.from {
+            if let Err(e) = self.move_linked_shares(&r.from, &r.into) {
+                let (entities, policies) = saved;
+                self.entities.restore(entities);
+                self.policies = policies;
+                return Err(e);
+            }
+        }
+        self.notify_list_change(&r.uid, &r.into, Activity::Merged);
+        if r.into != r.from {
+            self.notify_list_change(&r.uid, &r.from, Activity::Deleted);
+            self.webhooks.unregister_all(&r.from);
+            self.presence.forget(&r.from);
+            self.activity.forget(&r.from);
+            self.tokens.forget(&r.from);
+        }
+        Ok(AppResponse::Unit(()))
+    }
+
+    // The new owner gets access the way any owner does, through the policies, and the old owner
+    // keeps only the access the policies give them without ownership
+    fn transfer_list(&mut self, r: TransferList) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_TRANSFER_LIST, &r.list)?;
+        let before = self.entities.get_list(&r.list)?.clone();
+        if before.owner() == &r.new_owner {
+            return Ok(AppResponse::Unit(()));
+        }
+        self.entities.get_user(&r.new_owner)?;
+        if !before.is_template() {
+            self.entities.check_list_quota(&r.new_owner)?;
+        }
+        self.entities.get_list_mut(&r.list)?.set_owner(r.new_owner);
+        self.notify_list_update(&r.uid, &before, Activity::Transferred);
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn register_webhook(&mut self, r: RegisterWebhook) -> Result<AppResponse> {
+        self.is_authorized_rate_limited(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
+        self.entities.get_list(&r.list)?;
+        self.webhooks.register(r.list, r.url)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn touch_presence(&mut self, r: TouchPresence) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LIST, &r.list)?;
+        self.entities.get_list(&r.list)?;
+        let now = Instant::now();
+        self.presence.prune(self.config.presence_ttl, now);
+        self.presence.touch(r.list, r.uid, now);
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn get_presence(&self, r: GetPresence) -> Result<AppResponse> {
+        self.authorize_read(&r.uid, &r.list)?;
+        let users = self
+            .presence
+            .present_users(&r.list, self.config.presence_ttl, Instant::now());
+        Ok(AppResponse::Users(users))
+    }
+
+    /// The most recent changes to a list, newest first
+    fn get_activity(&self, r: GetActivity) -> Result<AppResponse> {
+        self.authorize_read(&r.uid, &r.list)?;
+        self.entities.get_list(&r.list)?;
+        Ok(AppResponse::Activity(
+            self.activity.recent(&r.list, r.limit.unwrap_or(20)),
+        ))
+    }
+
+    // Only those who may change a list's shares may audit them
+    fn get_granting_teams(&self, r: GetGrantingTeams) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        let mut teams = self.granting_teams(&r.list)?;
+        teams.readers.sort_by_key(|t| t.as_ref().to_string());
+        teams.editors.sort_by_key(|t| t.as_ref().to_string());
+        Ok(AppResponse::GrantingTeams(teams))
+    }
+
+    /// The teams the caller is in and the lists shared with them, directly or through a team
+    fn get_memberships(&self, r: GetMemberships) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+        let mut teams = self.entities.teams_for_user(&r.uid)?;
+        let mut lists = self.lists_shared_with(&r.uid, &teams)?;
+        teams.sort_by_key(|t| t.as_ref().to_string());
+        lists.sort_by_key(|l| l.as_ref().to_string());
+        Ok(AppResponse::Memberships(Memberships { teams, lists }))
+    }
+
+    /// Everything the store holds about a user, for answering their data-subject access request.
+    /// Only the user themselves may ask.
+    fn export_user_data(&self, r: ExportUserData) -> Result<AppResponse> {
+        if r.uid != r.user {
+            return Err(Error::AuthDeniedOn(vec![r.user.into()]));
+        }
+        let export = self.entities.export_user_data(&r.user)?;
+        Ok(AppResponse::UserData(Box::new(export)))
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    fn lists_shared_with(&self, user: &UserUid, _teams: &[TeamUid]) -> Result<Vec<ListUid>> {
+        self.entities.lists_shared_with(&user.clone().into())
+    }
+
+    // With "use-templates", shares are the policies linked against the list, not store state
+    #[cfg(feature = "use-templates")]
+    fn lists_shared_with(&self, user: &UserUid, teams: &[TeamUid]) -> Result<Vec<ListUid>> {
+        let principals = std::iter::once(user.as_ref())
+            .chain(teams.iter().map(|t| t.as_ref()))
+            .map(|euid| &**euid)
+            .collect::<HashSet<&cedar_policy::EntityUid>>();
+        let lists = self
+            .policies
+            .policies()
+            .filter_map(|p| {
+                let env = p.template_links()?;
+                if !principals.contains(&env[&SlotId::principal()]) {
+                    return None;
+                }
+                ListUid::try_from(EntityUid::from(env[&SlotId::resource()].clone())).ok()
+            })
+            .unique()
+            .collect();
+        Ok(lists)
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    fn granting_teams(&self, list: &ListUid) -> Result<GrantingTeams> {
+        self.entities.granting_teams(list)
+    }
+
+    // With "use-templates", shares are the policies linked against the list, not store state
+    #[cfg(feature = "use-templates")]
+    fn granting_teams(&self, list: &ListUid) -> Result<GrantingTeams> {
+        self.entities.get_list(list)?;
+        let list_euid: &cedar_policy::EntityUid = list.as_ref();
+        let mut teams = GrantingTeams::default();
+        for p in self.policies.policies() {
+            let (Some(tid), Some(env)) = (p.template_id(), p.template_links()) else {
+                continue;
+            };
+            if env.get(&SlotId::resource()) != Some(list_euid) {
+                continue;
+            }
+            let principal = EntityUid::from(env[&SlotId::principal()].clone());
+            if let Ok(team) = TeamUid::try_from(principal) {
+                if tid == &*EDITOR_TEMPLATE {
+                    teams.editors.push(team);
+                } else {
+                    teams.readers.push(team);
+                }
+            }
+        }
+        Ok(teams)
+    }
+
+    // Records `activity` in the list's activity feed and notifies its webhooks
+    fn notify_list_change(&mut self, by: &UserUid, list: &ListUid, activity: Activity) {
+        self.activity.record(list.clone(), activity, by.clone());
+        self.webhooks.notify(ListChange {
+            list: list.clone(),
+            change: activity.change_kind(),
+            by: by.clone(),
+            fields: vec![],
+            tasks: TasksDiff::default(),
+        });
+    }
+
+    // Like `notify_list_change`, for a list that still exists, also telling webhooks which of its
+    // fields and tasks now differ from `before`
+    fn notify_list_update(&mut self, by: &UserUid, before: &List, activity: Activity) {
+        let list = before.uid();
+        let (fields, tasks) = match self.entities.get_list(list) {
+            Ok(after) => (before.metadata_diff(after), before.tasks_diff(after)),
+            Err(_) => (vec![], TasksDiff::default()),
+        };
+        self.activity.record(list.clone(), activity, by.clone());
+        self.webhooks.notify(ListChange {
+            list: list.clone(),
+            change: activity.change_kind(),
+            by: by.clone(),
+            fields,
+            tasks,
+        });
+    }
+
+    /// Offboards a departing `user` as `EntityStore::offboard_user` does, also revoking the
+    /// template-linked shares made to them when the "use-templates" feature is enabled
+    pub fn offboard_user(&mut self, user: &UserUid, successor: &UserUid) -> Result<OffboardReport> {
+        #[allow(unused_mut)]
+        let mut report = self.entities.offboard_user(user, successor)?;
+        #[cfg(feature = "use-templates")]
+        {
+            let user_euid: &cedar_policy::EntityUid = user.as_ref();
+            let shares = self
+                .policies
+                .policies()
+                .filter_map(|p| {
+                    let tid = p.template_id()?;
+                    let env = p.template_links()?;
+                    (env.get(&SlotId::principal()) == Some(user_euid)).then(|| {
+                        let role = if tid == &*EDITOR_TEMPLATE {
+                            ShareRole::Editor
+                        } else {
+                            ShareRole::Reader
+                        };
+                        let list = EntityUid::from(env[&SlotId::resource()].clone())
+                            .try_into()
+                            .expect("share templates are only linked against lists");
+                        (p.id().clone(), Grant { list, role })
+                    })
+                })
+                .collect::<Vec<_>>();
+            for (pid, grant) in shares {
+                self.policies.unlink(pid)?;
+                report.grants_revoked.push(grant);
+            }
+        }
+        Ok(report)
+    }
+
+    // Re-links every share policy on the list `from` against the list `into`;
+    // only relevant with "use-templates" feature enabled
+    #[cfg(feature = "use-templates")]
+    fn move_linked_shares(&mut self, from: &ListUid, into: &ListUid) -> Result<()> {
+        let from_euid: &cedar_policy::EntityUid = from.as_ref();
+        let into_euid: &cedar_policy::EntityUid = into.as_ref();
+        let shares = self
+            .policies
+            .policies()
+            .filter_map(|p| {
+                let tid = p.template_id()?.clone();
+                let env = p.template_links()?;
+                (env.get(&SlotId::resource()) == Some(from_euid))
+                    .then(|| (p.id().clone(), tid, env))
+            })
+            .collect::<Vec<_>>();
+        for (pid, tid, mut env) in shares {
+            self.policies.unlink(pid.clone())?;
+            let role = if tid == *EDITOR_TEMPLATE {
+                ShareRole::Editor
+            } else {
+                ShareRole::Reader
+            };
+            let target: UserOrTeamUid = EntityUid::from(env[&SlotId::principal()].clone())
+                .try_into()
+                .expect("share templates are only linked against users and teams");
+            let new_pid = Self::linked_policy_id(role, target, into.clone())?;
+            // Shares are unioned, so a share the target already has on `into` is kept as is
+            if self.policies.policy(&new_pid).is_none() {
+                env.insert(SlotId::resource(), into_euid.clone());
+                self.policies.link(tid, new_pid.clone(), env)?;
+                info!("Moved policy {pid} to {new_pid}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Authorizes a request with the context of the query being handled, see
+    /// `is_authorized_with_context`
+    #[tracing::instrument(skip_all)]
+    pub fn is_authorized(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+    ) -> Result<()> {
+        self.is_authorized_with_context(principal, action, resource, &self.request_context)
+    }
+
+    /// Authorizes a request whose context is `context`, checked against the context the schema
+    /// declares for `action`
+    pub fn is_authorized_with_context(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        context: &ContextBuilder,
+    ) -> Result<()> {
+        let (principal, action, resource) =
+            (principal.as_ref(), action.as_ref(), resource.as_ref());
+        let response = self.authorize(principal, action, resource, context)?;
+        self.check_response(principal, action, resource, response)
+    }
+
+    /// Like `is_authorized`, but on an allow also says which permit policies granted access
+    pub fn is_authorized_with_diagnostics(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+    ) -> Result<AuthorizationInfo> {
+        let (principal, action, resource) =
+            (principal.as_ref(), action.as_ref(), resource.as_ref());
+        let response = self.authorize(principal, action, resource, &self.request_context)?;
+        let info = AuthorizationInfo {
+            determining_policies: response.diagnostics().reason().cloned().collect(),
+        };
+        self.check_response(principal, action, resource, response)?;
+        Ok(info)
+    }
+
+    /// Whether `principal` may perform `action` on `resource` whatever the request's context turns
+    /// out to be, e.g. to grey out a button that can never work. The context is left unknown, so
+    /// only policies whose conditions read it leave the decision open.
+    #[cfg(feature = "partial-eval")]
+    pub fn is_authorized_partial(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+    ) -> Result<PartialDecision> {
+        let (principal, action, resource) =
+            (principal.as_ref(), action.as_ref(), resource.as_ref());
+        self.check_applies_to(action, resource)?;
+        let q = RequestBuilder::default()
+            .principal(Some(principal.clone().into()))
+            .action(Some(action.clone().into()))
+            .resource(Some(resource.clone().into()))
+            .build();
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let response = self
+            .authorizer
+            .is_authorized_partial(&q, &self.policies, &es);
+        Ok(match response.decision() {
+            Some(Decision::Allow) => PartialDecision::Allow,
+            Some(Decision::Deny) => PartialDecision::Deny,
+            None => PartialDecision::Residual(
+                response
+                    .nontrivial_residuals()
+                    .map(|policy| policy.id().clone())
+                    .collect(),
+            ),
+        })
+    }
+
+    // Answers from the cache when possible, and logs the decision either way
+    fn authorize(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        context: &ContextBuilder,
+    ) -> Result<Response> {
+        // Cached responses are keyed without a context, so only those made without one are kept
+        let auth_cache = self.auth_cache.as_ref().filter(|_| context.is_empty());
+        if let Some(cache) = auth_cache {
+            if let Some(response) = cache.lock().unwrap().get(principal, action, resource) {
+                self.log_decision(principal, action, resource, &response);
+                return Ok(response);
+            }
+        }
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let q = self.build_request_with_context(principal, action, resource, context)?;
+        let response = match &self.policy_slices {
+            Some(slices) => {
+                let policies = slices.get(action, resource.type_name(), &self.policies, &es);
+                self.authorizer.is_authorized(&q, &policies, &es)
+            }
+            None => self.authorizer.is_authorized(&q, &self.policies, &es),
+        };
+        if let Some(cache) = auth_cache {
+            cache
+                .lock()
+                .unwrap()
+                .insert(principal, action, resource, response.clone());
+        }
+        self.log_decision(principal, action, resource, &response);
+        Ok(response)
+    }
+
+    fn log_decision(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        response: &Response,
+    ) {
+        if let Some(decisions) = &self.decisions {
+            let reason = match response.decision() {
+                Decision::Allow => None,
+                Decision::Deny => {
+                    let diagnostics = response.diagnostics();
+                    Some(describe_denial(&DenialReason::of(diagnostics), diagnostics))
+                }
+            };
+            decisions.record(principal, action, resource, response, reason);
+        }
+    }
+
+    /// How the authorization cache is doing, if there is one
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.auth_cache
+            .as_ref()
+            .map(|cache| cache.lock().unwrap().stats())
+    }
+
+    /// Like `is_authorized`, but with `principal` also a member of `teams` for this request only,
+    /// e.g. for group memberships taken from an identity provider's token rather than the store.
+    /// Every team must exist.
+    pub fn is_authorized_with_dynamic_groups(
+        &self,
+        principal: &UserUid,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        teams: &[TeamUid],
+    ) -> Result<()> {
+        let es = self
+            .entities
+            .as_entities_with_teams(&self.schema, principal, teams)?;
+        let (action, resource) = (action.as_ref(), resource.as_ref());
+        let q = self.build_request(principal.as_ref(), action, resource)?;
+        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+        self.log_decision(principal.as_ref(), action, resource, &response);
+        self.check_response(principal.as_ref(), action, resource, response)
+    }
+
+    /// Authorizes a request and describes it in a JSON shape meant for sharing with external tooling:
+    /// the request, the principal and resource along with their ancestors in Cedar's entity JSON
+    /// format, the decision, and the policies and errors that produced it.
+    /// Unlike `is_authorized`, a deny is part of the trace rather than an error.
+    pub fn authorization_trace_json(
+        &self,
+        principal: &UserUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+    ) -> Result<serde_json::Value> {
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let q = self.build_request(principal.as_ref(), action, resource)?;
+        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+        let relevant = [principal.as_ref(), resource]
+            .into_iter()
+            .flat_map(|euid| {
+                std::iter::once((**euid).clone())
+                    .chain(es.ancestors(euid).into_iter().flatten().cloned())
+            })
+            .unique()
+            .filter_map(|euid| es.get(&euid).cloned());
+        let entities = Entities::from_entities(relevant, None)
+            .and_then(|relevant| relevant.to_json_value())
+            .map_err(|e| Error::Trace(e.to_string()))?;
+        let decision = match response.decision() {
+            Decision::Allow => "Allow",
+            Decision::Deny => "Deny",
+        };
+        Ok(json!({
+            "request": {
+                "principal": principal.as_ref().to_string(),
+                "action": action.to_string(),
+                "resource": resource.to_string(),
+                "context": {},
+            },
+            "entities": entities,
+            "decision": decision,
+            "diagnostics": {
+                "reason": response.diagnostics().reason().map(|id| id.to_string()).collect::<Vec<_>>(),
+                "errors": response.diagnostics().errors().map(|e| e.to_string()).collect::<Vec<_>>(),
+            },
+        }))
+    }
+
+    /// Captures the decision on a request as a `ReproCase` for support to replay on its own.
+    /// The policies kept are those that determined the decision: the permits that matched an
+    /// allow, or the forbids that matched a deny, so a deny no policy permitted keeps none.
+    /// Nothing else could change the decision. The entities kept are the principal, action, and
+    /// resource, with their ancestors and every entity their attributes refer to, transitively,
+    /// so that conditions such as `resource.owner.location` still evaluate. The request is made
+    /// with the current request's context, which the case keeps too.
+    pub fn repro_case(
+        &self,
+        principal: &UserUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+    ) -> Result<ReproCase> {
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let q = self.build_request(principal.as_ref(), action, resource)?;
+        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+        let policies = response
+            .diagnostics()
+            .reason()
+            .filter_map(|id| self.policies.policy(id))
+            .map(|policy| {
+                let est = policy.to_json().map_err(|e| Error::Repro(e.to_string()))?;
+                Ok((policy.id().to_string(), est))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let mut pending: Vec<cedar_policy::EntityUid> = [principal.as_ref(), action, resource]
+            .into_iter()
+            .map(|euid| (**euid).clone())
+            .collect();
+        let mut seen = HashSet::new();
+        let mut relevant = vec![];
+        while let Some(euid) = pending.pop() {
+            if !seen.insert(euid.clone()) {
+                continue;
+            }
+            let Some(entity) = es.get(&euid) else {
+                continue;
+            };
+            let json = Entities::from_entities([entity.clone()], None)
+                .and_then(|e| e.to_json_value())
+                .map_err(|e| Error::Repro(e.to_string()))?;
+            referenced_entities(&json, &mut pending);
+            pending.extend(es.ancestors(&euid).into_iter().flatten().cloned());
+            relevant.push(entity.clone());
+        }
+        let entities = Entities::from_entities(relevant, None)
+            .and_then(|relevant| relevant.to_json_value())
+            .map_err(|e| Error::Repro(e.to_string()))?;
+        let case = ReproCase {
+            principal: principal.clone().into(),
+            action: action.clone(),
+            resource: resource.clone(),
+            context: serde_json::Value::Object(self.request_context.0.clone()),
+            entities,
+            policies,
+            allowed: response.decision() == Decision::Allow,
+        };
+        // A case that decides differently on its own would only mislead whoever replays it
+        if case.replay().map_err(Error::Repro)? != case.allowed {
+            return Err(Error::Repro(
+                "the case decides differently on its own".into(),
+            ));
+        }
+        Ok(case)
+    }
+
+    /// A best-effort static analysis of which context attributes, if supplied, could turn a deny
+    /// into an allow, e.g. to prompt for step-up authentication. The request is made with the
+    /// query's context, and these are the attributes read by the conditions of each permit policy
+    /// whose scope matches the request, less those the context already supplies: the deny was
+    /// decided with their values. An attribute is only a chance at an allow, as the policy's other
+    /// conditions may still fail. Nothing is required of an allowed request, or of one a forbid
+    /// policy denies.
+    pub fn authorization_requirements(
+        &self,
+        principal: &UserUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+    ) -> Result<Vec<ContextRequirement>> {
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let q = self.build_request(principal.as_ref(), action, resource)?;
+        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+        if response.decision() == Decision::Allow
+            || response.diagnostics().reason().next().is_some()
+        {
+            return Ok(vec![]);
+        }
+        let mut requirements = vec![];
+        for policy in self.policies.policies() {
+            if policy.effect() != Effect::Permit {
+                continue;
+            }
+            let Ok(mut est) = policy.to_json() else {
+                continue;
+            };
+            let attributes = accessed_attributes(&est, true)
+                .into_iter()
+                .filter(|attribute| !self.request_context.0.contains_key(attribute))
+                .collect::<Vec<_>>();
+            if attributes.is_empty() {
+                continue;
+            }
+            // The policy with its conditions dropped allows exactly the requests in its scope
+            est["conditions"] = json!([]);
+            let in_scope = Policy::from_json(None, est)
+                .ok()
+                .and_then(|scope| PolicySet::from_policies([scope]).ok())
+                .map_or(false, |scope| {
+                    self.authorizer.is_authorized(&q, &scope, &es).decision() == Decision::Allow
+                });
+            if in_scope {
+                requirements.extend(attributes.into_iter().map(|attribute| ContextRequirement {
+                    attribute,
+                    policy: policy.id().to_string(),
+                }));
+            }
+        }
+        Ok(requirements)
+    }
+
+    /// The attributes that policy conditions read from entities but that no stored entity of any
+    /// type carries, by policy, as these conditions can never be satisfied. Policies are validated
+    /// against the schema before they're loaded, so an attribute the schema doesn't declare never
+    /// gets this far; what's left are declared attributes that are optional and that nothing sets,
+    /// which are often a misspelling of a similar attribute. The only records entities carry are
+    /// tasks, which sit in a set that conditions can't look inside, so every attribute read is
+    /// taken to be an entity's.
+    pub fn dangling_attribute_references(&self) -> Vec<(PolicyId, String)> {
+        let provided = [
+            EntityType::User,
+            EntityType::Team,
+            EntityType::List,
+            EntityType::Application,
+        ]
+        .into_iter()
+        .flat_map(|ty| self.entities.observed_attributes(ty).into_keys())
+        .collect::<HashSet<_>>();
+        self.policies
+            .policies()
+            .filter_map(|policy| policy.to_json().ok().map(|est| (policy.id(), est)))
+            .flat_map(|(id, est)| {
+                accessed_attributes(&est, false)
+                    .into_iter()
+                    .filter(|attr| !provided.contains(attr))
+                    .map(|attr| (id.clone(), attr))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Checks that this replica's entities and policies match a primary whose
+    /// `EntityStore::authz_fingerprint` is `expected`, failing with `Error::ReplicaDiverged`
+    /// if they don't, in which case the replica should resync in full
+    pub fn verify_sync(&self, expected: [u8; 32]) -> Result<()> {
+        if self
+            .entities
+            .authz_fingerprint(&self.policies, &self.schema)?
+            == expected
+        {
+            Ok(())
+        } else {
+            Err(Error::ReplicaDiverged)
+        }
+    }
+
+    /// What would be left referring to `euid` if it were deleted, for checking before
+    /// `EntityStore::delete_entity`: the stored entities that refer to it, and the policies
+    /// that name it and would then never match.
+    pub fn deletion_impact(&self, euid: &EntityUid) -> DeletionImpact {
+        let policies = self
+            .policies
+            .policies()
+            .filter(|policy| {
+                let linked = policy
+                    .template_links()
+                    .map_or(false, |env| env.values().any(|v| v == &**euid));
+                linked
+                    || policy
+                        .to_json()
+                        .map_or(false, |est| mentions_entity(&est, euid))
+            })
+            .map(|policy| policy.id().clone())
+            .collect();
+        DeletionImpact {
+            entities: self.entities.referrers(euid),
+            policies,
+        }
+    }
+
+    fn warn_dangling_attribute_references(&self) {
+        for (policy, attr) in self.dangling_attribute_references() {
+            warn!("Policy {policy} reads attribute `{attr}`, which no entity has");
+        }
+    }
+
+    /// Every action `principal` may perform, by resource, leaving out resources it may do nothing
+    /// to. All the checks share one entity set and authorizer. Only the application and the lists
+    /// `principal` has a grant path to, by owning them or being in their readers or editors, are
+    /// considered. With the `use-templates` feature shares are policies rather than team
+    /// memberships, so every list is considered.
+    pub fn capability_summary(
+        &self,
+        principal: &UserUid,
+    ) -> Result<HashMap<EntityUid, HashSet<&'static str>>> {
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        #[cfg(not(feature = "use-templates"))]
+        let ancestors: HashSet<EntityUid> = es
+            .ancestors(principal.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|euid| euid.clone().into())
+            .collect();
+        let reachable = |list: &&List| {
+            #[cfg(not(feature = "use-templates"))]
+            let shared = [ShareRole::Reader, ShareRole::Editor]
+                .into_iter()
+                .any(|role| ancestors.contains(list.get_team(role).as_ref()));
+            #[cfg(feature = "use-templates")]
+            let shared = true;
+            list.owner() == principal || shared
+        };
+        let resources = self
+            .entities
+            .lists()
+            .filter(reachable)
+            .map(|list| list.uid().clone().into())
+            .chain(std::iter::once(APPLICATION_TINY_TODO.clone()));
+
+        let mut summary = HashMap::new();
+        for resource in resources {
+            let permitted: HashSet<&'static str> = ACTIONS
+                .iter()
+                .filter(|(_, action)| {
+                    // Requests for actions that don't apply to the resource fail to build
+                    self.build_request(principal.as_ref(), action, &resource)
+                        .map_or(false, |q| {
+                            self.authorizer
+                                .is_authorized(&q, &self.policies, &es)
+                                .decision()
+                                == Decision::Allow
+                        })
+                })
+                .map(|(name, _)| *name)
+                .collect();
+            if !permitted.is_empty() {
+                summary.insert(resource, permitted);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// The complement of `capability_summary` for one resource: every action `principal` may not
+    /// perform on `resource`, with why it's denied. Actions that don't apply to `resource` are
+    /// left out. All the checks share one entity set and authorizer.
+    pub fn denied_actions(
+        &self,
+        principal: &UserUid,
+        resource: &EntityUid,
+    ) -> Result<Vec<(&'static str, DenialReason)>> {
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        Ok(ACTIONS
+            .iter()
+            .filter_map(|(name, action)| {
+                let q = self
+                    .build_request(principal.as_ref(), action, resource)
+                    .ok()?;
+                let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+                (response.decision() == Decision::Deny)
+                    .then(|| (*name, DenialReason::of(response.diagnostics())))
+            })
+            .collect())
+    }
+
+    /// Like `is_authorized`, but first spends a token from the rate limit bucket for
+    /// `principal` performing `action`, failing with `Error::RateLimited` if it's empty.
+    /// Requests that go on to be denied still spend a token, so that denied spam is limited too.
+    pub fn is_authorized_rate_limited(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+    ) -> Result<()> {
+        self.spend_rate_limit(principal.as_ref(), action.as_ref())?;
+        self.is_authorized(principal, action, resource)
+    }
+
+    // Spends a token from the rate limit bucket for `principal` performing `action`, if limited
+    fn spend_rate_limit(&self, principal: &EntityUid, action: &EntityUid) -> Result<()> {
+        if let Some(limiter) = &self.rate_limiter {
+            let acquired = limiter
+                .lock()
+                .unwrap()
+                .try_acquire(principal, action, Instant::now());
+            if !acquired {
+                info!("Rate limited {} performing {}", principal, action);
+                return Err(Error::RateLimited);
+            }
+        }
+        Ok(())
+    }
+
+    /// Authorizes `principal` to perform `action` on every one of `resources`, for operations
+    /// that touch several resources and must not go ahead on only some of them. If any are
+    /// denied, fails with `Error::AuthDeniedOn` naming all of them; any other error is returned
+    /// as soon as it's hit.
+    pub fn is_authorized_all<R: AsRef<EntityUid>>(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resources: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        let mut denied = vec![];
+        for resource in resources {
+            match self.is_authorized(principal.as_ref(), action.as_ref(), resource.as_ref()) {
+                Ok(()) => (),
+                Err(Error::AuthDenied { .. }) => denied.push(resource.as_ref().clone()),
+                Err(e) => return Err(e),
+            }
+        }
+        if denied.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::AuthDeniedOn(denied))
+        }
+    }
+
+    /// The actions among `actions` that `principal` may perform on `resource`, e.g. to decide
+    /// which entries of a menu to show. `principal` may perform all of them exactly when every
+    /// one is returned. Errors other than denials are returned as soon as they're hit.
+    pub fn is_authorized_any(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        actions: &[&'static EntityUid],
+    ) -> Result<Vec<&'static EntityUid>> {
+        let (principal, resource) = (principal.as_ref(), resource.as_ref());
+        let mut allowed = vec![];
+        for &action in actions {
+            let response = self.authorize(principal, action, resource, &self.request_context)?;
+            if response.decision() == Decision::Allow {
+                allowed.push(action);
+            }
+        }
+        Ok(allowed)
+    }
+
+    /// Authorizes `principal` to perform `action` on each of `resources`, returning one result per resource.
+    /// Every item is decided against the same entities and policy set: reloads arrive as
+    /// `UpdatePolicySet` queries, which need `&mut self` and so can't run until the batch returns.
+    /// Each decision is logged like one made by `is_authorized`, but the authorization cache is
+    /// neither read nor filled.
+    #[tracing::instrument(skip_all)]
+    pub fn is_authorized_batch<R: AsRef<EntityUid>>(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resources: impl IntoIterator<Item = R>,
+    ) -> Vec<Result<()>> {
+        let es = match self.entities_cache.get(&self.entities, &self.schema) {
+            Ok(es) => es,
+            Err(e) => {
+                return resources
+                    .into_iter()
+                    .map(|_| Err(e.clone().into()))
+                    .collect()
+            }
+        };
+        let policies = &self.policies;
+        resources
+            .into_iter()
+            .map(|resource| {
+                let (action, resource) = (action.as_ref(), resource.as_ref());
+                let q = self.build_request(principal.as_ref(), action, resource)?;
+                let response = self.authorizer.is_authorized(&q, policies, &es);
+                self.log_decision(principal.as_ref(), action, resource, &response);
+                self.check_response(principal.as_ref(), action, resource, response)
+            })
+            .collect()
+    }
+
+    /// Like `is_authorized_batch`, but pairs each result with the resource it's for,
+    /// e.g. to show which of a user's lists they may edit.
+    pub fn is_authorized_each<R: AsRef<EntityUid>>(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resources: impl IntoIterator<Item = R>,
+    ) -> Vec<(EntityUid, Result<()>)> {
+        let resources = resources
+            .into_iter()
+            .map(|resource| resource.as_ref().clone())
+            .collect::<Vec<_>>();
+        let decisions = self.is_authorized_batch(principal, action, &resources);
+        resources.into_iter().zip(decisions).collect()
+    }
+
+    /// Like `is_authorized`, but runs the evaluation on tokio's blocking thread pool,
+    /// so that a large policy set doesn't stall the runtime thread.
+    /// The entities and policies are snapshotted before evaluation starts. The decision is logged,
+    /// but the authorization cache is neither read nor filled.
+    #[tracing::instrument(skip_all)]
+    pub async fn is_authorized_spawn_blocking(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+    ) -> Result<()> {
+        let (principal, action, resource) =
+            (principal.as_ref(), action.as_ref(), resource.as_ref());
+        let es = self.entities_cache.get(&self.entities, &self.schema)?;
+        let q = self.build_request(principal, action, resource)?;
+        let policies = self.policies.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            Authorizer::new().is_authorized(&q, &policies, &es)
+        })
+        .await?;
+        self.log_decision(principal, action, resource, &response);
+        self.check_response(principal, action, resource, response)
+    }
+
+    // A request with the context of the query being handled
+    fn build_request(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+    ) -> Result<Request> {
+        self.build_request_with_context(principal, action, resource, &self.request_context)
+    }
+
+    fn build_request_with_context(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        context: &ContextBuilder,
+    ) -> Result<Request> {
+        self.check_applies_to(action, resource)?;
+        if self.config.not_found_policy == NotFoundPolicy::Distinct
+            && !self.entities.contains(resource)
+        {
+            return Err(Error::ResourceMissing(resource.clone()));
+        }
+        let q = Request::new(
+            Some(principal.clone().into()),
+            Some(action.clone().into()),
+            Some(resource.clone().into()),
+            context.clone().build(&self.schema, action)?,
+            Some(&self.schema),
+        )
+        .map_err(|e| Error::Request(e.to_string()))?;
+        info!(
+            "is_authorized request: principal: {}, action: {}, resource: {}",
+            principal, action, resource
+        );
+        Ok(q)
+    }
+
+    // Rejects a request whose resource type the schema doesn't list for the action,
+    // so that it's reported as a client error rather than evaluated to a deny
+    fn check_applies_to(&self, action: &EntityUid, resource: &EntityUid) -> Result<()> {
+        let applies = self
+            .schema
+            .resources_for_action(action)
+            .map_or(false, |mut tys| tys.any(|ty| ty == resource.type_name()));
+        if applies {
+            Ok(())
+        } else {
+            Err(Error::ActionNotApplicable {
+                action: action.clone(),
+                resource: resource.clone(),
+            })
+        }
+    }
+
+    fn check_response(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        response: Response,
+    ) -> Result<()> {
+        info!("Auth response: {:?}", response);
+        match response.decision() {
+            Decision::Allow => Ok(()),
+            Decision::Deny => {
+                let request = AuthRequest {
+                    principal: principal.clone(),
+                    action: action.clone(),
+                    resource: resource.clone(),
+                };
+                info!("Denied request: {request}");
+                let diagnostics = response.diagnostics().clone();
+                let reason = DenialReason::of(&diagnostics);
+                let detail = match self.config.denial_verbosity {
+                    DenialVerbosity::Generic => None,
+                    DenialVerbosity::Detailed => Some(describe_denial(&reason, &diagnostics)),
+                };
+                Err(Error::AuthDenied {
+                    request,
+                    diagnostics,
+                    reason,
+                    detail,
+                })
+            }
+        }
+    }
+}
+
+/// The request behind an `Error::AuthDenied`. It displays in Cedar's request syntax, e.g.
+/// `principal = User::"emina", action = Action::"GetList", resource = List::"0"`, though the
+/// error itself doesn't show it to callers, so that its message stays the same for every denial.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthRequest {
+    pub principal: EntityUid,
+    pub action: EntityUid,
+    pub resource: EntityUid,
+}
+
+impl std::fmt::Display for AuthRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "principal = {}, action = {}, resource = {}",
+            self.principal, self.action, self.resource
+        )
+    }
+}
+
+/// What lay behind an allowed request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthorizationInfo {
+    /// The permit policies that matched the request
+    pub determining_policies: Vec<PolicyId>,
+}
+
+/// The decision on a request whose context isn't known yet, see `AppContext::is_authorized_partial`
+#[cfg(feature = "partial-eval")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartialDecision {
+    /// Allowed in every context
+    Allow,
+    /// Denied in every context
+    Deny,
+    /// Depends on the context through these policies
+    Residual(Vec<PolicyId>),
+}
+
+/// Why a request was denied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DenialReason {
+    /// These forbid policies matched the request
+    ExplicitForbid(Vec<PolicyId>),
+    /// Nothing forbade the request, but no permit policy matched it either
+    NoMatchingPermit,
+}
+
+impl DenialReason {
+    /// The reason for a deny with these diagnostics, whose reasons are the forbid policies that
+    /// matched, if any did
+    pub fn of(diagnostics: &Diagnostics) -> Self {
+        let forbids = diagnostics.reason().cloned().collect::<Vec<_>>();
+        if forbids.is_empty() {
+            Self::NoMatchingPermit
+        } else {
+            Self::ExplicitForbid(forbids)
+        }
+    }
+}
+
+fn describe_denial(reason: &DenialReason, diagnostics: &Diagnostics) -> String {
+    let mut detail = match reason {
+        DenialReason::NoMatchingPermit => "no policy permitted the request".to_string(),
+        DenialReason::ExplicitForbid(forbids) => {
+            format!("forbidden by {}", forbids.iter().join(", "))
+        }
+    };
+    let errors = diagnostics.errors().map(|e| e.to_string()).join("; ");
+    if !errors.is_empty() {
+        detail.push_str(&format!(" (errors: {errors})"));
+    }
+    detail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::simple_query_inner;
+
+    // The example schema and policies, as `main` loads them
+    fn schema_and_policies() -> (&'static str, &'static str) {
+        #[cfg(not(feature = "use-templates"))]
+        let paths = ("./tinytodo.cedarschema", "./policies.cedar");
+        #[cfg(feature = "use-templates")]
+        let paths = (
+            "./tinytodo-templates.cedarschema",
+            "./policies-templates.cedar",
+        );
+        paths
+    }
+
+    // A server over the example entities, schema, and policies, as `main` starts one
+    fn spawn_app() -> Sender<AppQuery> {
+        spawn_app_with(AppConfig::default())
+    }
+
+    fn spawn_app_with(config: AppConfig) -> Sender<AppQuery> {
+        let (schema, policies) = schema_and_policies();
+        AppContext::spawn("./entities.json", schema, policies, config).unwrap()
+    }
+
+    // A context over the same, to call directly rather than through queries
+    fn app_context() -> AppContext {
+        let (schema_path, policies_path) = schema_and_policies();
+        let schema_src = std::fs::read_to_string(schema_path).unwrap();
+        let (schema, _) = Schema::from_str_natural(&schema_src).unwrap();
+        let config = AppConfig::default();
+        let entities = load_entities(Path::new("./entities.json"), &schema, &config).unwrap();
+        let policy_src = std::fs::read_to_string(policies_path).unwrap();
+        let policies = load_policies(&policy_src, &schema).unwrap();
+        let (_, recv) = tokio::sync::mpsc::channel(1);
+        AppContext::new(entities, policies, schema, config, None, recv)
+    }
+
+    #[tokio::test]
+    async fn a_handler_that_panics_partway_leaves_the_store_unchanged() {
+        let mut app = app_context();
+        let andrew: UserUid = r#"User::"andrew""#.parse().unwrap();
+        let [into, from] = ["foo", "bar"].map(|name| {
+            let uid: ListUid = app.entities.fresh_euid(EntityType::List).unwrap();
+            let list = List::new(&mut app.entities, uid.clone(), andrew.clone(), name.into());
+            app.entities.insert_list(list.unwrap()).unwrap();
+            uid
+        });
+        let before = app.entities.export_entities_json(&app.schema).unwrap();
+
+        let merge = MergeLists {
+            uid: andrew.clone(),
+            into: into.clone(),
+            from: from.clone(),
+        };
+        let merged = app.handle_guarded(false, |app| {
+            app.handle(merge.into())?;
+            panic!("injected once the merge has deleted the source list");
+        });
+        assert!(matches!(merged, Err(Error::Panicked)));
+        assert!(app.entities.get_list(&from).is_ok());
+        let after = app.entities.export_entities_json(&app.schema).unwrap();
+        assert_eq!(after, before);
+    }
+
+    #[tokio::test]
+    async fn spawn_blocking_decides_as_is_authorized_does() {
+        let app = app_context();
+        let (action, resource) = (&*ACTION_ADMINISTER, &*APPLICATION_TINY_TODO);
+        let decide = |principal: &str| {
+            let principal: EntityUid = principal.parse().unwrap();
+            let sync = app.is_authorized(&principal, action, resource);
+            let blocking = app.is_authorized_spawn_blocking(principal, action, resource);
+            async move { (sync, blocking.await) }
+        };
+
+        let (sync, blocking) = decide(r#"User::"andrew""#).await;
+        assert!(sync.is_ok());
+        assert!(blocking.is_ok());
+        let (sync, blocking) = decide(r#"User::"kesha""#).await;
+        assert!(matches!(sync, Err(Error::AuthDenied { .. })));
+        assert!(matches!(blocking, Err(Error::AuthDenied { .. })));
+    }
+
+    #[tokio::test]
+    async fn a_batch_is_decided_against_one_policy_set() {
+        async fn kesha_sees(app: Sender<AppQuery>) -> usize {
+            let get = GetLists {
+                uid: r#"User::"kesha""#.parse().unwrap(),
+                templates: false,
+            };
+            let lists: Lists = simple_query_inner(app, ContextBuilder::default(), get)
+                .await
+                .unwrap();
+            serde_json::to_value(lists)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len()
+        }
+        async fn reload(app: Sender<AppQuery>, src: &str) {
+            let update = AppQueryKind::UpdatePolicySet(src.parse().unwrap());
+            let _: Empty = simple_query_inner(app, ContextBuilder::default(), update)
+                .await
+                .unwrap();
+        }
+
+        let app = spawn_app();
+        for i in 0..10 {
+            let create = CreateList {
+                uid: r#"User::"andrew""#.parse().unwrap(),
+                name: format!("list{i}"),
+                apply_team_defaults: false,
+            };
+            let _: EntityUid = simple_query_inner(app.clone(), ContextBuilder::default(), create)
+                .await
+                .unwrap();
+        }
+        let (_, policies) = schema_and_policies();
+        let closed = std::fs::read_to_string(policies).unwrap();
+        let open = closed.clone()
+            + r#"permit (principal == User::"kesha", action == Action::"GetList", resource);"#;
+        reload(app.clone(), &open).await;
+        let all = kesha_sees(app.clone()).await;
+        reload(app.clone(), &closed).await;
+        let few = kesha_sees(app.clone()).await;
+        assert!(few + 10 <= all);
+
+        // Reloads race the batches, but each batch sees one policy set or the other, never a mix
+        let reloads = {
+            let app = app.clone();
+            tokio::spawn(async move {
+                for i in 0..50 {
+                    reload(app.clone(), if i % 2 == 0 { &open } else { &closed }).await;
+                }
+            })
+        };
+        for _ in 0..50 {
+            let seen = kesha_sees(app.clone()).await;
+            assert!(seen == all || seen == few, "kesha saw {seen} lists");
+        }
+        reloads.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_mutation_between_authorizations_rebuilds_the_entities() {
+        let mut app = app_context();
+        let andrew: UserUid = r#"User::"andrew""#.parse().unwrap();
+        let kesha: EntityUid = r#"User::"kesha""#.parse().unwrap();
+        let uid: ListUid = app.entities.fresh_euid(EntityType::List).unwrap();
+        let list = List::new(&mut app.entities, uid.clone(), andrew, "foo".to_string());
+        app.entities.insert_list(list.unwrap()).unwrap();
+        let list: EntityUid = uid.clone().into();
+
+        let denied = app.is_authorized(&kesha, &*ACTION_GET_LIST, &list);
+        assert!(matches!(denied, Err(Error::AuthDenied { .. })));
+        let before = app.entities_cache.get(&app.entities, &app.schema).unwrap();
+        let again = app.entities_cache.get(&app.entities, &app.schema).unwrap();
+        assert!(std::sync::Arc::ptr_eq(&before, &again));
+
+        let owner = kesha.clone().try_into().unwrap();
+        app.entities.get_list_mut(&uid).unwrap().set_owner(owner);
+        assert!(app.is_authorized(&kesha, &*ACTION_GET_LIST, &list).is_ok());
+        let after = app.entities_cache.get(&app.entities, &app.schema).unwrap();
+        assert!(!std::sync::Arc::ptr_eq(&before, &after));
+    }
+
+    #[tokio::test]
+    async fn requirements_leave_out_attributes_the_context_supplies() {
+        let app = spawn_app_with(AppConfig {
+            denial_verbosity: DenialVerbosity::Detailed,
+            ..AppConfig::default()
+        });
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), ContextBuilder::default(), create)
+            .await
+            .unwrap();
+        let requirements = |context: ContextBuilder| {
+            let get = GetRequirements {
+                uid: r#"User::"emina""#.parse().unwrap(),
+                action: "GetList".to_string(),
+                resource: list.clone(),
+            };
+            simple_query_inner::<Vec<ContextRequirement>>(app.clone(), context, get)
+        };
+
+        let missing = requirements(ContextBuilder::default()).await.unwrap();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].attribute, "mfa");
+        assert_eq!(missing[0].policy, "admin-mfa-read");
+        let supplied = requirements(ContextBuilder::default().bool("mfa", false));
+        assert!(supplied.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mfa_context_lets_admins_read_any_list() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let get = || GetList {
+            uid: r#"User::"emina""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+        };
+
+        let denied = simple_query_inner::<List>(app.clone(), none(), get()).await;
+        assert!(matches!(denied, Err(Error::AuthDenied { .. })));
+        let failed = none().bool("mfa", false);
+        let denied = simple_query_inner::<List>(app.clone(), failed, get()).await;
+        assert!(matches!(denied, Err(Error::AuthDenied { .. })));
+        let passed = none().bool("mfa", true);
+        let allowed = simple_query_inner::<List>(app.clone(), passed, get()).await;
+        assert_eq!(allowed.unwrap().metadata().name, "foo");
+    }
+
+    #[tokio::test]
+    async fn repro_cases_replay_to_the_same_decision() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let repro = || GetReproCase {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            principal: r#"User::"emina""#.parse().unwrap(),
+            action: "GetList".to_string(),
+            resource: list.clone(),
+        };
+
+        let passed = none().bool("mfa", true);
+        let case: ReproCase = simple_query_inner(app.clone(), passed, repro())
+            .await
+            .unwrap();
+        assert!(case.allowed);
+        assert_eq!(case.policies.keys().collect::<Vec<_>>(), ["admin-mfa-read"]);
+        assert_eq!(case.context, serde_json::json!({ "mfa": true }));
+        let saved = serde_json::to_string(&case).unwrap();
+        let mut loaded: ReproCase = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded.replay(), Ok(true));
+        loaded.context = serde_json::json!({});
+        assert_eq!(loaded.replay(), Ok(false));
+
+        let case: ReproCase = simple_query_inner(app.clone(), none(), repro())
+            .await
+            .unwrap();
+        assert!(!case.allowed);
+        assert!(case.policies.is_empty());
+        assert_eq!(case.replay(), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn deletion_impact_reports_referring_policies_and_entities() {
+        let app = spawn_app();
+        let impact = |entity: &str| GetDeletionImpact {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            entity: entity.parse().unwrap(),
+        };
+        let context = ContextBuilder::default;
+
+        let admin: DeletionImpact =
+            simple_query_inner(app.clone(), context(), impact(r#"Team::"admin""#))
+                .await
+                .unwrap();
+        let mut policies: Vec<String> = admin.policies.iter().map(ToString::to_string).collect();
+        policies.sort();
+        assert_eq!(policies, ["admin-administer", "admin-mfa-read"]);
+        let mut members: Vec<String> = admin
+            .entities
+            .iter()
+            .map(|(e, f)| format!("{e}.{f}"))
+            .collect();
+        members.sort();
+        assert_eq!(
+            members,
+            [r#"User::"andrew".parents"#, r#"User::"emina".parents"#]
+        );
+
+        let interns: DeletionImpact =
+            simple_query_inner(app.clone(), context(), impact(r#"Team::"interns""#))
+                .await
+                .unwrap();
+        assert!(interns.policies.is_empty());
+        assert_eq!(interns.entities.len(), 1);
+        assert_eq!(interns.entities[0].0.to_string(), r#"User::"aaron""#);
+    }
+
+    #[tokio::test]
+    async fn replicas_detect_divergence_from_the_primary() {
+        let (primary, replica) = (spawn_app(), spawn_app());
+        let context = ContextBuilder::default;
+        let fingerprint = |app: Sender<AppQuery>| {
+            simple_query_inner::<Fingerprint>(
+                app,
+                context(),
+                GetFingerprint {
+                    uid: r#"User::"andrew""#.parse().unwrap(),
+                },
+            )
+        };
+        let verify = |app: Sender<AppQuery>, fingerprint: Fingerprint| {
+            simple_query_inner::<Empty>(
+                app,
+                context(),
+                VerifySync {
+                    uid: r#"User::"andrew""#.parse().unwrap(),
+                    fingerprint,
+                },
+            )
+        };
+
+        let synced = fingerprint(primary.clone()).await.unwrap();
+        assert!(verify(replica.clone(), synced).await.is_ok());
+
+        let create = CreateList {
+            uid: r#"User::"kesha""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let _: EntityUid = simple_query_inner(primary.clone(), context(), create)
+            .await
+            .unwrap();
+        let moved = fingerprint(primary.clone()).await.unwrap();
+        let diverged = verify(replica.clone(), moved).await;
+        assert!(matches!(diverged, Err(Error::ReplicaDiverged)));
+        let own = fingerprint(replica.clone()).await.unwrap();
+        assert!(verify(replica, own).await.is_ok());
+    }
+
+    #[cfg(feature = "partial-eval")]
+    #[tokio::test]
+    async fn partial_decisions_leave_context_dependent_requests_open() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let decide = |uid: &str| {
+            let query = GetPartialDecision {
+                uid: uid.parse().unwrap(),
+                action: "GetList".to_string(),
+                resource: list.clone(),
+            };
+            simple_query_inner::<PartialDecision>(app.clone(), none(), query)
+        };
+
+        let owner = decide(r#"User::"andrew""#).await.unwrap();
+        assert_eq!(owner, PartialDecision::Allow);
+        let admin = decide(r#"User::"emina""#).await.unwrap();
+        let mfa_read: PolicyId = "admin-mfa-read".parse().unwrap();
+        assert_eq!(admin, PartialDecision::Residual(vec![mfa_read]));
+        let other = decide(r#"User::"kesha""#).await.unwrap();
+        assert_eq!(other, PartialDecision::Deny);
+    }
+
+    #[tokio::test]
+    async fn granting_policies_name_the_permit_that_allowed() {
+        let app = spawn_app_with(AppConfig {
+            denial_verbosity: DenialVerbosity::Detailed,
+            ..AppConfig::default()
+        });
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let granted = || GetGrantingPolicies {
+            uid: r#"User::"emina""#.parse().unwrap(),
+            action: "GetList".to_string(),
+            resource: list.clone(),
+        };
+
+        let denied = simple_query_inner::<AuthorizationInfo>(app.clone(), none(), granted()).await;
+        assert!(matches!(denied, Err(Error::AuthDenied { .. })));
+        let passed = none().bool("mfa", true);
+        let info: AuthorizationInfo = simple_query_inner(app.clone(), passed, granted())
+            .await
+            .unwrap();
+        let policies: Vec<String> = info
+            .determining_policies
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(policies, ["admin-mfa-read"]);
+    }
+
+    #[tokio::test]
+    async fn list_actions_leave_out_the_denied_ones() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let share = AddShare {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+            share_with: r#"User::"kesha""#.parse().unwrap(),
+            role: ShareRole::Reader,
+        };
+        let _: Empty = simple_query_inner(app.clone(), none(), share)
+            .await
+            .unwrap();
+        let actions = GetListActions {
+            uid: r#"User::"kesha""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+        };
+
+        let allowed: Vec<&str> = simple_query_inner(app.clone(), none(), actions)
+            .await
+            .unwrap();
+        assert_eq!(allowed, ["GetList"]);
+    }
+
+    #[tokio::test]
+    async fn denials_carry_the_request_in_cedar_syntax() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let get = GetList {
+            uid: r#"User::"emina""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+        };
+
+        let denied = simple_query_inner::<List>(app.clone(), none(), get).await;
+        let Err(Error::AuthDenied { request, .. }) = denied else {
+            panic!("expected a denial");
+        };
+        assert_eq!(
+            request.to_string(),
+            format!(r#"principal = User::"emina", action = Action::"GetList", resource = {list}"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn archived_lists_come_back_only_when_an_admin_restores_them() {
+        let app = spawn_app();
+        let none = ContextBuilder::default;
+        let create = CreateList {
+            uid: r#"User::"kesha""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), none(), create)
+            .await
+            .unwrap();
+        let get = || GetList {
+            uid: r#"User::"kesha""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+        };
+        let archive = ArchiveEntity {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            entity: list.clone(),
+        };
+        let restore = |uid: &str| RestoreEntity {
+            uid: uid.parse().unwrap(),
+            entity: list.clone(),
+        };
+
+        let _: Empty = simple_query_inner(app.clone(), none(), archive)
+            .await
+            .unwrap();
+        assert!(simple_query_inner::<List>(app.clone(), none(), get())
+            .await
+            .is_err());
+        let denied = simple_query_inner::<Empty>(app.clone(), none(), restore(r#"User::"kesha""#));
+        assert!(matches!(denied.await, Err(Error::AuthDenied { .. })));
+        let _: Empty = simple_query_inner(app.clone(), none(), restore(r#"User::"andrew""#))
+            .await
+            .unwrap();
+        let restored = simple_query_inner::<List>(app.clone(), none(), get()).await;
+        assert_eq!(restored.unwrap().metadata().name, "foo");
+    }
+
+    #[tokio::test]
+    async fn denied_actions_tell_forbids_from_missing_permits() {
+        let app = spawn_app_with(AppConfig {
+            denial_verbosity: DenialVerbosity::Detailed,
+            ..AppConfig::default()
+        });
+        let context = ContextBuilder::default;
+        let (_, policies) = schema_and_policies();
+        let src = std::fs::read_to_string(policies).unwrap()
+            + r#"@id("emina-keeps-lists") forbid (principal == User::"emina", action == Action::"DeleteList", resource);"#;
+        let update = AppQueryKind::UpdatePolicySet(src.parse().unwrap());
+        let _: Empty = simple_query_inner(app.clone(), context(), update)
+            .await
+            .unwrap();
+
+        let create = CreateList {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            name: "foo".to_string(),
+            apply_team_defaults: false,
+        };
+        let list: EntityUid = simple_query_inner(app.clone(), context(), create)
+            .await
+            .unwrap();
+        let share = AddShare {
+            uid: r#"User::"andrew""#.parse().unwrap(),
+            list: list.clone().try_into().unwrap(),
+            share_with: r#"User::"emina""#.parse().unwrap(),
+            role: ShareRole::Editor,
+        };
+        let _: Empty = simple_query_inner(app.clone(), context(), share)
+            .await
+            .unwrap();
+
+        let denied = GetDeniedActions {
+            uid: r#"User::"emina""#.parse().unwrap(),
+            resource: list,
+        };
+        let denied: Vec<DeniedAction> = simple_query_inner(app.clone(), context(), denied)
+            .await
+            .unwrap();
+        let reasons: HashMap<&str, Vec<String>> = denied
+            .iter()
+            .map(|d| {
+                (
+                    d.action,
+                    d.forbidden_by.iter().map(ToString::to_string).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(reasons["DeleteList"], ["emina-keeps-lists"]);
+        assert!(reasons["EditShare"].is_empty());
+        assert!(reasons["TransferList"].is_empty());
+        assert!(!reasons.contains_key("UpdateList"));
+        assert!(!reasons.contains_key("GetList"));
+    }
+
+    #[test]
+    fn context_builder_checks_the_schema() {
+        let (schema, _) =
+            Schema::from_str_natural(include_str!("../tinytodo.cedarschema")).unwrap();
+        let get_list = r#"Action::"GetList""#.parse().unwrap();
+        assert!(ContextBuilder::default().build(&schema, &get_list).is_ok());
+        let mfa = ContextBuilder::default().bool("mfa", true);
+        assert!(mfa.build(&schema, &get_list).is_ok());
+        let mistyped = ContextBuilder::default().string("mfa", "yes");
+        assert!(matches!(
+            mistyped.build(&schema, &get_list),
+            Err(Error::Context(_))
+        ));
+        let undeclared = ContextBuilder::default().bool("sudo", true);
+        assert!(matches!(
+            undeclared.build(&schema, &get_list),
+            Err(Error::Context(_))
+        ));
+    }
+
+    #[test]
+    fn parse_rate_limit_accepts_positive_limits() {
+        assert_eq!(
+            parse_rate_limit("10/0.5").unwrap(),
+            RateLimit {
+                capacity: 10.0,
+                refill_per_sec: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_rate_limit_rejects_non_positive_or_non_finite_limits() {
+        for s in [
+            "0/1", "5/0", "-5/1", "5/-1", "NaN/1", "5/NaN", "inf/1", "5/inf", "5", "a/b",
+        ] {
+            assert!(parse_rate_limit(s).is_err(), "accepted `{s}`");
         }
     }
 }
@@ -0,0 +1,162 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cedar_policy::{Decision, Response};
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::{error, info};
+
+use crate::util::EntityUid;
+
+// How many records may be waiting before new decisions go unlogged
+const QUEUE_CAPACITY: usize = 1024;
+
+/// One authorization decision, as handed to a `DecisionLog`
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionRecord {
+    pub principal: EntityUid,
+    pub action: EntityUid,
+    pub resource: EntityUid,
+    #[serde(serialize_with = "serialize_decision")]
+    pub decision: Decision,
+    /// The policies that determined the decision: the permits behind an allow, or the forbids
+    /// behind a deny
+    pub policies: Vec<String>,
+    /// Why the request was denied, for a deny
+    pub reason: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub at: u128,
+}
+
+fn serialize_decision<S>(decision: &Decision, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(match decision {
+        Decision::Allow => "Allow",
+        Decision::Deny => "Deny",
+    })
+}
+
+/// Somewhere to forward authorization decisions, such as a SIEM
+pub trait DecisionLog: Send + 'static {
+    fn log(&self, record: DecisionRecord);
+}
+
+impl DecisionLog for Box<dyn DecisionLog> {
+    fn log(&self, record: DecisionRecord) {
+        (**self).log(record);
+    }
+}
+
+/// Where to send decisions: `tracing` for the application log, or else a file path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecisionLogTarget {
+    File(PathBuf),
+    Tracing,
+}
+
+impl From<String> for DecisionLogTarget {
+    fn from(s: String) -> Self {
+        if s == "tracing" {
+            Self::Tracing
+        } else {
+            Self::File(s.into())
+        }
+    }
+}
+
+/// Emits each record as a `tracing` event with target `audit`, alongside the application's own
+/// logs
+#[derive(Debug, Default)]
+pub struct TracingLog;
+
+impl DecisionLog for TracingLog {
+    fn log(&self, record: DecisionRecord) {
+        info!(
+            target: "audit",
+            principal = %record.principal,
+            action = %record.action,
+            resource = %record.resource,
+            decision = ?record.decision,
+            policies = ?record.policies,
+            reason = record.reason.as_deref(),
+            at = %record.at,
+            "Authorization decision"
+        );
+    }
+}
+
+/// Forwards decisions to a `DecisionLog`. Recording only queues the decision; a background task
+/// hands it to the log, so a slow log never holds up the request being authorized.
+#[derive(Debug)]
+pub struct DecisionLogger {
+    queue: Sender<DecisionRecord>,
+}
+
+impl DecisionLogger {
+    /// Creates a logger and spawns the task that hands its records to `log`
+    pub fn spawn(log: impl DecisionLog) -> Self {
+        let (queue, recv) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(log_all(log, recv));
+        Self { queue }
+    }
+
+    pub fn record(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        response: &Response,
+        reason: Option<String>,
+    ) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let record = DecisionRecord {
+            principal: principal.clone(),
+            action: action.clone(),
+            resource: resource.clone(),
+            decision: response.decision(),
+            policies: response
+                .diagnostics()
+                .reason()
+                .map(ToString::to_string)
+                .collect(),
+            reason,
+            at,
+        };
+        match self.queue.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => error!("Decision log queue full, dropping record"),
+            Err(TrySendError::Closed(_)) => error!("Decision log queue closed, dropping record"),
+        }
+    }
+}
+
+async fn log_all(log: impl DecisionLog, mut recv: Receiver<DecisionRecord>) {
+    while let Some(record) = recv.recv().await {
+        log.log(record);
+    }
+}
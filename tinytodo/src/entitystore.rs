@@ -14,78 +14,1207 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use thiserror::Error;
 
-use cedar_policy::{Entities, EntityId, EntityTypeName, EvaluationError, Schema};
-use serde::{Deserialize, Serialize};
+use cedar_policy::{
+    Entities, Entity, EntityId, EntityTypeName, EvaluationError, PolicySet, RestrictedExpression,
+    Schema,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
+use crate::api::ShareRole;
 use crate::{
-    context::Error,
-    objects::{Application, List, Team, User, UserOrTeam},
-    util::{EntityUid, ListUid, TeamUid, UserOrTeamUid, UserUid},
+    context::{Error, ACTIONS},
+    objects::{Application, List, ListSummary, Task, TaskState, Team, User, UserOrTeam},
+    util::{
+        EntityTypeError, EntityUid, ListUid, TeamUid, UserOrTeamUid, UserUid, TYPE_APPLICATION,
+        TYPE_LIST, TYPE_TEAM, TYPE_USER,
+    },
+    versionvector::VersionVector,
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// `entities` in Cedar's entity JSON format, sorted by uid and otherwise as `canonical_json` sorts
+/// it, so that the same entities always give the same text, e.g. for snapshot tests of what the
+/// authorizer was handed
+pub fn entities_to_json(entities: &Entities) -> Result<String, EntityDecodeError> {
+    let json = entities
+        .to_json_value()
+        .map_err(|e| EntityDecodeError::EntityJson(e.to_string()))?;
+    let Value::Array(entities) = json else {
+        unreachable!("Cedar writes entities as a JSON array")
+    };
+    let entities: Vec<Value> = entities
+        .iter()
+        .map(canonical_json)
+        .sorted_by_key(|entity| entity["uid"].to_string())
+        .collect();
+    Ok(serde_json::to_string_pretty(&entities).expect("JSON values always serialize"))
+}
+
+/// The most characters a list's name may have, see `EntityStore::update_list_name`
+pub const MAX_LIST_NAME_LEN: usize = 256;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(from = "SavedStore")]
 pub struct EntityStore {
     users: HashMap<EntityUid, User>,
     teams: HashMap<EntityUid, Team>,
     lists: HashMap<EntityUid, List>,
-    app: Application,
+    apps: HashMap<EntityUid, Application>,
+    // Set aside by `archive_entity`, and invisible to everything but `restore_entity`
+    archived: HashMap<EntityUid, StoreEntity>,
     #[serde(skip)]
     uid: usize,
+    // Where `fresh_euid` takes ids from instead of `uid`, if anywhere
+    #[serde(skip)]
+    id_source: Option<Box<dyn IdSource>>,
+    #[serde(skip)]
+    quarantine: Vec<Quarantined>,
+    #[serde(skip)]
+    extra: Vec<Box<dyn ExtraEntities>>,
+    // Only entities written through `insert_list_versioned` have a version
+    #[serde(skip)]
+    versions: HashMap<EntityUid, VersionVector>,
+    // Changes whenever anything Cedar sees might have, see `generation`
+    #[serde(skip)]
+    generation: u64,
+    #[cfg(feature = "undo")]
+    #[serde(skip)]
+    undo: UndoStack,
+}
+
+// A store with just the TinyTodo application, as every deployment with a single tenant has
+impl Default for EntityStore {
+    fn default() -> Self {
+        Self::with_apps([Application::default()])
+    }
 }
 
 impl EntityStore {
+    /// An empty store with these application roots, e.g. one for each tenant
+    pub fn with_apps(apps: impl IntoIterator<Item = Application>) -> Self {
+        Self {
+            users: HashMap::new(),
+            teams: HashMap::new(),
+            lists: HashMap::new(),
+            apps: apps
+                .into_iter()
+                .map(|app| (app.euid().clone(), app))
+                .collect(),
+            archived: HashMap::new(),
+            uid: 0,
+            id_source: None,
+            quarantine: vec![],
+            extra: vec![],
+            versions: HashMap::new(),
+            generation: next_generation(),
+            #[cfg(feature = "undo")]
+            undo: UndoStack::default(),
+        }
+    }
+
+    /// This store, with `fresh_euid` taking ids from `source` from now on, e.g. to hand out
+    /// predictable ids in tests or UUIDs in production
+    pub fn with_id_source(mut self, source: impl IdSource + 'static) -> Self {
+        self.id_source = Some(Box::new(source));
+        self
+    }
+
+    /// Identifies the store's current contents: it changes whenever an entity is inserted,
+    /// deleted, or borrowed mutably, and no two stores ever share one. Anything derived from the
+    /// store, such as its `Entities`, can be kept until the generation changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn touch(&mut self) {
+        self.generation = next_generation();
+    }
+
+    /// A store with just the TinyTodo application, like `default`, but only once `schema` is
+    /// known to declare every entity type the store holds and every action the server
+    /// authorizes. The first that's missing is named in the error.
+    pub fn bootstrap(schema: &Schema) -> Result<Self, EntityDecodeError> {
+        let types = [
+            EntityType::Application,
+            EntityType::User,
+            EntityType::Team,
+            EntityType::List,
+        ];
+        for ty in types {
+            let name = ty.type_name();
+            if !schema.entity_types().any(|declared| *declared == name) {
+                return Err(EntityDecodeError::UndeclaredType(name));
+            }
+        }
+        let actions = schema
+            .action_entities()
+            .map_err(|e| EntityDecodeError::SchemaActions(e.to_string()))?;
+        for (_, action) in ACTIONS.iter() {
+            if actions.get(action).is_none() {
+                return Err(EntityDecodeError::UndeclaredAction((*action).clone()));
+            }
+        }
+        Ok(Self::default())
+    }
+
     pub fn euids(&self) -> impl Iterator<Item = &EntityUid> {
         self.users
             .keys()
             .chain(self.teams.keys())
             .chain(self.lists.keys())
-            .chain(std::iter::once(self.app.euid()))
+            .chain(self.apps.keys())
+            .chain(self.extra.iter().flat_map(|extra| extra.euids()))
+    }
+
+    /// Adds entities of a type defined outside this crate to the store
+    pub fn register_extra(&mut self, extra: impl ExtraEntities + 'static) {
+        self.touch();
+        self.extra.push(Box::new(extra));
+    }
+
+    /// Checks that every entity `batch` refers to is either in `batch` or already in the store,
+    /// reporting each reference that is neither, so that a batch can be rejected before any
+    /// of it is inserted
+    pub fn validate_batch(&self, batch: &[StoreEntity]) -> Vec<BatchError> {
+        let in_batch = batch.iter().map(StoreEntity::euid).collect::<HashSet<_>>();
+        let exists = |euid: &EntityUid| {
+            in_batch.contains(euid)
+                || self.users.contains_key(euid)
+                || self.teams.contains_key(euid)
+                || self.lists.contains_key(euid)
+        };
+        batch
+            .iter()
+            .flat_map(|entity| {
+                entity
+                    .references()
+                    .into_iter()
+                    .filter(|(_, target)| !exists(target))
+                    .map(|(field, target)| BatchError {
+                        entity: entity.euid().clone(),
+                        field,
+                        missing: target,
+                    })
+            })
+            .collect()
+    }
+
+    /// Every reference from a stored user, team, or list to a user, team, or list that isn't in
+    /// the store, e.g. from a list to a reader team that was deleted out from under it. These
+    /// are found without Cedar, so they can be reported before `as_entities` trips over them.
+    pub fn validate_references(&self) -> Vec<DanglingRef> {
+        let users = self.users.values().map(EntityRef::User);
+        let teams = self.teams.values().map(EntityRef::Team);
+        let lists = self.lists.values().map(EntityRef::List);
+        users
+            .chain(teams)
+            .chain(lists)
+            .flat_map(move |entity| {
+                entity
+                    .references()
+                    .into_iter()
+                    .filter(move |(_, target)| self.get(target).is_err())
+                    .map(move |(field, target)| DanglingRef {
+                        entity: entity.euid().clone(),
+                        field,
+                        missing: target,
+                    })
+            })
+            .sorted_by_key(|dangling| (dangling.entity.to_string(), dangling.field))
+            .collect()
+    }
+
+    /// Inserts every entity in `entities`, or none of them. The batch is rejected if any
+    /// entity's euid is already taken, in the store, its archive, or earlier in the batch; if
+    /// any refers to an entity that is in neither the batch nor the store; if any doesn't
+    /// conform to `schema`; or if a list would put its owner over their quota. Returns the
+    /// euids inserted, in order.
+    pub fn insert_many(
+        &mut self,
+        entities: Vec<StoreEntity>,
+        schema: &Schema,
+    ) -> Result<Vec<EntityUid>, Error> {
+        let mut seen = HashSet::new();
+        for entity in &entities {
+            let euid = entity.euid();
+            if self.exists(euid) || self.archived.contains_key(euid) || !seen.insert(euid) {
+                return Err(Error::AlreadyExists(euid.clone()));
+            }
+            Entities::from_entities([entity.clone().into()], Some(schema))
+                .map_err(|e| EntityDecodeError::Nonconforming(euid.clone(), e.to_string()))?;
+        }
+        if let Some(e) = self.validate_batch(&entities).into_iter().next() {
+            return Err(Error::DanglingReference {
+                entity: e.entity,
+                field: e.field,
+                missing: e.missing,
+            });
+        }
+        let euids = entities.iter().map(|e| e.euid().clone()).collect();
+        self.with_transaction(|store| {
+            for entity in entities {
+                match entity {
+                    StoreEntity::User(u) => store.insert_user(u),
+                    StoreEntity::Team(t) => store.insert_team(t),
+                    StoreEntity::List(l) => store.insert_list(l)?,
+                }
+            }
+            Ok(())
+        })?;
+        Ok(euids)
+    }
+
+    /// The inverse of `as_entities`: rebuilds a store from `entities`, which may have been
+    /// produced by other Cedar tooling. The action entities declared by `schema` are skipped,
+    /// and any other entity that isn't a user, team, list, or application is an error.
+    ///
+    /// Only what Cedar sees survives the round trip: each user's `joblevel`, `location`, and
+    /// memberships, each team's memberships, and each list's owner, name, tasks, and, without the
+    /// `use-templates` feature, reader and editor teams. State kept beside the Cedar attributes
+    /// starts out empty: users' list orders and quotas, teams' names and default shares, and lists'
+    /// task schemas and template flags. Archived entities aren't Cedar entities, so they're lost.
+    pub fn from_entities(entities: &Entities, schema: &Schema) -> Result<Self, EntityDecodeError> {
+        let actions = schema
+            .action_entities()
+            .map_err(|e| EntityDecodeError::SchemaActions(e.to_string()))?;
+        let mut store = Self::default();
+        for entity in entities.iter() {
+            let euid = EntityUid::from(entity.uid());
+            let ty = euid.type_name();
+            if ty == &*TYPE_USER {
+                store.users.insert(euid, User::try_from(entity)?);
+            } else if ty == &*TYPE_TEAM {
+                store.teams.insert(euid, Team::try_from(entity)?);
+            } else if ty == &*TYPE_LIST {
+                store.lists.insert(euid, List::try_from(entity)?);
+            } else if ty == &*TYPE_APPLICATION {
+                store.apps.insert(euid.clone(), Application::new(euid));
+            } else if actions.get(&entity.uid()).is_none() {
+                return Err(EntityDecodeError::UnexpectedType(euid));
+            }
+        }
+        store.repair_uid_counter();
+        Ok(store)
+    }
+
+    /// The store in Cedar's entity JSON format, as the Cedar CLI and other Cedar tooling read it,
+    /// and ordered as `entities_to_json` orders it.
+    /// State Cedar doesn't see, such as list orders, quotas, and team default shares, is left out.
+    pub fn export_entities_json(&self, schema: &Schema) -> Result<String, EntityDecodeError> {
+        entities_to_json(&self.as_entities(schema)?)
+    }
+
+    /// Rebuilds a store from Cedar's entity JSON format, as `from_entities` does.
+    /// The inverse of `export_entities_json`.
+    pub fn import_entities_json(json: &str, schema: &Schema) -> Result<Self, EntityDecodeError> {
+        let entities = Entities::from_json_str(json, Some(schema))
+            .map_err(|e| EntityDecodeError::EntityJson(e.to_string()))?;
+        Self::from_entities(&entities, schema)
+    }
+
+    /// Loads a snapshot, setting aside rather than failing on any entity that doesn't decode or
+    /// doesn't conform to `schema`. The entities set aside are listed by `quarantined`, and are
+    /// left out of `as_entities`. Only a snapshot that isn't a JSON object of the expected
+    /// shape is an error.
+    pub fn load_lenient(snapshot: Value, schema: &Schema) -> Result<Self, serde_json::Error> {
+        let raw: RawSnapshot = serde_json::from_value(snapshot)?;
+        let mut store = Self::default();
+        for (key, value) in raw.users {
+            if let Some(user) = admit::<User>(key, value, schema, &mut store.quarantine) {
+                store.users.insert(user.uid().clone().into(), user);
+            }
+        }
+        for (key, value) in raw.teams {
+            if let Some(team) = admit::<Team>(key, value, schema, &mut store.quarantine) {
+                store.teams.insert(team.uid().clone().into(), team);
+            }
+        }
+        for (key, value) in raw.lists {
+            if let Some(list) = admit::<List>(key, value, schema, &mut store.quarantine) {
+                store.lists.insert(list.uid().clone().into(), list);
+            }
+        }
+        for (key, value) in raw.archived {
+            if let Some(entity) = admit::<StoreEntity>(key, value, schema, &mut store.quarantine) {
+                store.archived.insert(entity.euid().clone(), entity);
+            }
+        }
+        // A snapshot saved before there could be several applications has just the one
+        let single = raw
+            .app
+            .map(|value| (Application::default().euid().to_string(), value));
+        let mut apps = HashMap::new();
+        for (key, value) in raw.apps.into_iter().chain(single) {
+            if let Some(app) = admit::<Application>(key, value, schema, &mut store.quarantine) {
+                apps.insert(app.euid().clone(), app);
+            }
+        }
+        if !apps.is_empty() {
+            store.apps = apps;
+        }
+        store.repair_uid_counter();
+        Ok(store)
     }
 
-    pub fn as_entities(&self, schema: &Schema) -> Entities {
-        let users = self.users.values().map(|user| user.clone().into());
+    /// The entities `load_lenient` set aside
+    pub fn quarantined(&self) -> &[Quarantined] {
+        &self.quarantine
+    }
+
+    /// Checks every stored entity against `schema`, naming the first that doesn't conform.
+    /// `as_entities` fails the same way, so a store that wasn't loaded with `load_lenient` should
+    /// be checked before it's used, rather than failing every request.
+    pub fn validate(&self, schema: &Schema) -> Result<(), EntityDecodeError> {
+        let users = self
+            .users
+            .iter()
+            .map(|(euid, user)| (euid, Entity::from(user.clone())));
+        let teams = self
+            .teams
+            .iter()
+            .map(|(euid, team)| (euid, Entity::from(team.clone())));
+        let lists = self
+            .lists
+            .iter()
+            .map(|(euid, list)| (euid, Entity::from(list.clone())));
+        let apps = self
+            .apps
+            .iter()
+            .map(|(euid, app)| (euid, Entity::from(app.clone())));
+        for (euid, entity) in users.chain(teams).chain(lists).chain(apps) {
+            Entities::from_entities([entity], Some(schema))
+                .map_err(|e| EntityDecodeError::Nonconforming(euid.clone(), e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The store's entities as Cedar sees them, extra entities included. Fails as `validate`
+    /// does, naming the entity at fault, if any stored entity doesn't conform to `schema`.
+    pub fn as_entities(&self, schema: &Schema) -> Result<Entities, EntityDecodeError> {
+        self.bundle(schema, self.users.values().cloned())
+    }
+
+    /// Like `as_entities`, but with `principal` made a member of each of `teams` as well.
+    /// Nothing is stored, and every team must exist.
+    pub fn as_entities_with_teams(
+        &self,
+        schema: &Schema,
+        principal: &UserUid,
+        teams: &[TeamUid],
+    ) -> Result<Entities, Error> {
+        let mut user = self.get_user(principal)?.clone();
+        for team in teams {
+            self.get_team(team)?;
+            user.insert_parent(team.clone());
+        }
+        let users = self
+            .users
+            .values()
+            .filter(|u| u.uid() != principal)
+            .cloned()
+            .chain(std::iter::once(user));
+        Ok(self.bundle(schema, users)?)
+    }
+
+    // `users` with the store's teams, lists, applications, and extra entities
+    fn bundle(
+        &self,
+        schema: &Schema,
+        users: impl Iterator<Item = User>,
+    ) -> Result<Entities, EntityDecodeError> {
+        self.validate(schema)?;
+        let users = users.map(User::into);
         let teams = self.teams.values().map(|team| team.clone().into());
         let lists = self.lists.values().map(|list| list.clone().into());
-        let app = std::iter::once(self.app.clone().into());
-        let all = users.chain(teams).chain(lists).chain(app);
-        Entities::from_entities(all, Some(schema)).unwrap()
+        let apps = self.apps.values().map(|app| app.clone().into());
+        let extra = self.extra.iter().flat_map(|extra| extra.entities());
+        let all = users.chain(teams).chain(lists).chain(apps).chain(extra);
+        Entities::from_entities(all, Some(schema))
+            .map_err(|e| EntityDecodeError::Bundle(e.to_string()))
+    }
+
+    /// A SHA-256 digest of everything authorization depends on: the entities as Cedar sees them,
+    /// and `policies`. Two stores holding the same entities give the same fingerprint with the
+    /// same policies, however they were built, so it can key caches shared between services.
+    /// State Cedar doesn't see, such as list orders, doesn't change the fingerprint.
+    pub fn authz_fingerprint(
+        &self,
+        policies: &PolicySet,
+        schema: &Schema,
+    ) -> Result<[u8; 32], EntityDecodeError> {
+        let entities = self
+            .as_entities(schema)?
+            .to_json_value()
+            .map_err(|e| EntityDecodeError::EntityJson(e.to_string()))?;
+        let mut hasher = Sha256::new();
+        hasher.update(canonical_json(&entities).to_string());
+        let templates = policies
+            .templates()
+            .map(|t| (t.id().to_string(), t.to_string()));
+        let policies = policies
+            .policies()
+            .map(|p| (p.id().to_string(), p.to_string()));
+        for (id, src) in templates.chain(policies).sorted() {
+            hasher.update(id);
+            hasher.update([0u8]);
+            hasher.update(src);
+            hasher.update([0u8]);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// What changed going from this store to `other`, by entity type. An entity counts as
+    /// modified if its saved form differs at all, e.g. a list whose tasks changed, or a user
+    /// who joined a team. Extra entities aren't compared.
+    pub fn diff(&self, other: &EntityStore) -> StoreDiff {
+        StoreDiff {
+            users: diff_entities(&self.users, &other.users),
+            teams: diff_entities(&self.teams, &other.teams),
+            lists: diff_entities(&self.lists, &other.lists),
+            apps: diff_entities(&self.apps, &other.apps),
+        }
+    }
+
+    /// Counts, for every attribute key carried by an entity of type `ty`, how many
+    /// stored entities of that type carry it. This reflects the data as stored,
+    /// independent of what the schema declares.
+    pub fn observed_attributes(&self, ty: EntityType) -> HashMap<String, usize> {
+        let attrs: Vec<HashMap<String, RestrictedExpression>> = match ty {
+            EntityType::User => self.users.values().map(User::attrs).collect(),
+            EntityType::Team => self.teams.values().map(Team::attrs).collect(),
+            EntityType::List => self.lists.values().map(List::attrs).collect(),
+            EntityType::Application => self.apps.values().map(Application::attrs).collect(),
+        };
+        let mut counts = HashMap::new();
+        for key in attrs.into_iter().flat_map(HashMap::into_keys) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The euids of every entity of type `ty` whose attribute `attr` is `value`, written as in
+    /// Cedar's entity JSON format, e.g. `{"__entity": {"type": "User", "id": "kesha"}}` for an
+    /// entity reference. This scans every entity of type `ty`, so it costs O(n) in their number;
+    /// an attribute that's queried often is better served by an index of its own.
+    pub fn find_by_attribute(&self, ty: EntityType, attr: &str, value: &Value) -> Vec<EntityUid> {
+        let entities: Vec<(&EntityUid, Entity)> = match ty {
+            EntityType::User => self
+                .users
+                .iter()
+                .map(|(euid, user)| (euid, user.clone().into()))
+                .collect(),
+            EntityType::Team => self
+                .teams
+                .iter()
+                .map(|(euid, team)| (euid, team.clone().into()))
+                .collect(),
+            EntityType::List => self
+                .lists
+                .iter()
+                .map(|(euid, list)| (euid, list.clone().into()))
+                .collect(),
+            EntityType::Application => self
+                .apps
+                .iter()
+                .map(|(euid, app)| (euid, app.clone().into()))
+                .collect(),
+        };
+        entities
+            .into_iter()
+            .filter(|(_, entity)| {
+                Entities::from_entities([entity.clone()], None)
+                    .and_then(|es| es.to_json_value())
+                    .map_or(false, |json| json[0]["attrs"][attr] == *value)
+            })
+            .map(|(euid, _)| euid.clone())
+            .collect()
+    }
+
+    pub fn lists(&self) -> impl Iterator<Item = &List> {
+        self.lists.values()
+    }
+
+    /// The lists `owner` owns, templates included
+    pub fn lists_owned_by<'a>(&'a self, owner: &'a UserUid) -> impl Iterator<Item = &'a List> {
+        self.lists
+            .values()
+            .filter(move |list| list.owner() == owner)
+    }
+
+    /// Summaries of every list that isn't a template
+    pub fn list_summaries(&self) -> Vec<ListSummary> {
+        self.lists
+            .values()
+            .filter(|list| !list.is_template())
+            .map(List::summary)
+            .collect()
+    }
+
+    /// Groups the lists with the same name and tasks by their content hash, for offering to merge
+    /// them. Templates are meant to be copied, so they're left out. Only groups of more than one
+    /// list are returned.
+    pub fn find_duplicate_lists(&self) -> Vec<Vec<ListUid>> {
+        self.lists
+            .values()
+            .filter(|list| !list.is_template())
+            .map(|list| (list.content_hash(), list.uid().clone()))
+            .into_group_map()
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect()
     }
 
-    pub fn fresh_euid<T: TryFrom<EntityUid>>(&mut self, ty: EntityTypeName) -> Result<T, T::Error> {
+    pub fn templates(&self) -> impl Iterator<Item = &List> {
+        self.lists.values().filter(|list| list.is_template())
+    }
+
+    /// Buckets every list under its owner in a single pass over the lists
+    pub fn lists_grouped_by_owner(&self) -> ListsByOwner {
+        let mut grouped = ListsByOwner::default();
+        for list in self.lists.values() {
+            if self.users.contains_key(list.owner().as_ref()) {
+                grouped
+                    .owned
+                    .entry(list.owner().clone())
+                    .or_default()
+                    .push(list.uid().clone());
+            } else {
+                grouped.ownerless.push(list.uid().clone());
+            }
+        }
+        grouped
+    }
+
+    /// Allocates an euid of type `ty` that isn't used by any stored entity, from the store's
+    /// `IdSource` if it has one, otherwise from its counter. Ids that are taken are skipped.
+    /// Fails with `Error::IdSpaceExhausted` rather than wrapping once the counter
+    /// reaches `usize::MAX`, or once the `IdSource` runs out.
+    pub fn fresh_euid<T>(&mut self, ty: EntityType) -> Result<T, Error>
+    where
+        T: TryFrom<EntityUid>,
+        Error: From<T::Error>,
+    {
+        let type_name = ty.type_name();
         loop {
-            let new_uid: EntityId = format!("{}", self.uid).parse().unwrap();
-            self.uid += 1;
-            let euid = cedar_policy::EntityUid::from_type_name_and_id(ty.clone(), new_uid).into();
-            if !self.euid_exists(&euid) {
-                return T::try_from(euid);
+            let new_uid = match &mut self.id_source {
+                Some(source) => source.next_id(ty).ok_or(Error::IdSpaceExhausted)?,
+                None => {
+                    let next = self.uid.checked_add(1).ok_or(Error::IdSpaceExhausted)?;
+                    let id: EntityId = format!("{}", self.uid).parse().unwrap();
+                    self.uid = next;
+                    id
+                }
+            };
+            let euid =
+                cedar_policy::EntityUid::from_type_name_and_id(type_name.clone(), new_uid).into();
+            if !self.exists(&euid) && !self.archived.contains_key(&euid) {
+                return Ok(T::try_from(euid)?);
             }
         }
     }
 
-    fn euid_exists(&self, euid: &EntityUid) -> bool {
-        self.lists.contains_key(euid)
-            || self.teams.contains_key(euid)
-            || self.users.contains_key(euid)
-            || self.app.euid() == euid
+    /// How many of each kind of entity the store holds, e.g. for an admin dashboard. The teams
+    /// backing list shares aren't counted as teams.
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            users: self.users.len(),
+            teams: self
+                .teams
+                .values()
+                .filter(|t| !self.is_share_team(t.uid()))
+                .count(),
+            lists: self.lists.len(),
+            #[cfg(not(feature = "use-templates"))]
+            shares: self
+                .lists
+                .values()
+                .map(|list| {
+                    self.members_of(list.get_team(ShareRole::Reader)).len()
+                        + self.members_of(list.get_team(ShareRole::Editor)).len()
+                })
+                .sum(),
+            next_uid: self.uid,
+        }
+    }
+
+    /// Moves the counter `fresh_euid` allocates from to one past the largest numeric id of any
+    /// stored entity, and returns it. The counter isn't saved, so this is done whenever a store
+    /// is loaded; otherwise `fresh_euid` would have to skip every taken id.
+    pub fn repair_uid_counter(&mut self) -> usize {
+        let max = self
+            .euids()
+            .chain(self.archived.keys())
+            .filter_map(|euid| euid.id().as_ref().parse::<usize>().ok())
+            .max();
+        self.uid = max.map_or(0, |max| max.saturating_add(1));
+        self.uid
+    }
+
+    /// Which kind of entity `euid` is in the store, if it's a user, team, list, or application.
+    /// Extra entities aren't counted.
+    pub fn entity_type_of(&self, euid: &EntityUid) -> Option<EntityType> {
+        if self.users.contains_key(euid) {
+            Some(EntityType::User)
+        } else if self.teams.contains_key(euid) {
+            Some(EntityType::Team)
+        } else if self.lists.contains_key(euid) {
+            Some(EntityType::List)
+        } else if self.apps.contains_key(euid) {
+            Some(EntityType::Application)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `euid` is a user, team, list, or application in the store
+    pub fn exists(&self, euid: &EntityUid) -> bool {
+        self.entity_type_of(euid).is_some()
     }
 
     pub fn insert_user(&mut self, e: User) {
+        self.touch();
+        #[cfg(feature = "undo")]
+        self.record_undo(e.uid().as_ref());
         self.users.insert(e.uid().clone().into(), e);
     }
 
     pub fn insert_team(&mut self, e: Team) {
+        self.touch();
+        #[cfg(feature = "undo")]
+        self.record_undo(e.uid().as_ref());
         self.teams.insert(e.uid().clone().into(), e);
     }
 
-    pub fn insert_list(&mut self, e: List) {
+    /// The team named `name`, creating it if there is none, along with whether it was created.
+    /// Syncing the same external group twice therefore finds the team the first sync created
+    /// rather than adding another.
+    pub fn get_or_create_team(&mut self, name: &str) -> Result<(&Team, bool), Error> {
+        let existing = self
+            .teams
+            .values()
+            .find(|team| team.name() == Some(name))
+            .map(|team| team.uid().clone());
+        let (uid, created) = match existing {
+            Some(uid) => (uid, false),
+            None => {
+                let uid: TeamUid = self.fresh_euid(EntityType::Team)?;
+                self.insert_team(Team::new(uid.clone()).with_name(name));
+                (uid, true)
+            }
+        };
+        Ok((self.get_team(&uid)?, created))
+    }
+
+    /// Adds an application root, e.g. for another tenant
+    pub fn insert_app(&mut self, app: Application) {
+        self.touch();
+        self.apps.insert(app.euid().clone(), app);
+    }
+
+    pub fn get_app(&self, euid: &EntityUid) -> Result<&Application, Error> {
+        self.apps
+            .get(euid)
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    /// The application `euid` belongs to: for a user or team, the first of its parents that's an
+    /// application in the store, and for a list, its owner's
+    pub fn app_of(&self, euid: &EntityUid) -> Option<&Application> {
+        let parents = if let Some(user) = self.users.get(euid) {
+            user.parent_apps()
+        } else if let Some(team) = self.teams.get(euid) {
+            team.parent_apps()
+        } else if let Some(list) = self.lists.get(euid) {
+            return self.app_of(list.owner().as_ref());
+        } else {
+            return None;
+        };
+        parents.iter().find_map(|app| self.apps.get(app))
+    }
+
+    /// The lists belonging to application `app`
+    pub fn lists_in_app<'a>(&'a self, app: &'a EntityUid) -> impl Iterator<Item = &'a List> {
+        self.lists.values().filter(move |list| {
+            self.app_of(list.uid().as_ref()).map(Application::euid) == Some(app)
+        })
+    }
+
+    /// Inserts `e`, failing with `Error::QuotaExceeded` if it is a new list and its owner
+    /// already owns as many lists as their quota allows
+    pub fn insert_list(&mut self, e: List) -> Result<(), Error> {
+        self.touch();
+        if !self.lists.contains_key(e.uid().as_ref()) {
+            self.check_list_quota(e.owner())?;
+        }
+        #[cfg(feature = "undo")]
+        self.record_undo(e.uid().as_ref());
         self.lists.insert(e.uid().clone().into(), e);
+        Ok(())
+    }
+
+    /// The version of the entity `euid`, which is empty if it was never written through
+    /// `insert_list_versioned`
+    pub fn version(&self, euid: &EntityUid) -> VersionVector {
+        self.versions.get(euid).cloned().unwrap_or_default()
+    }
+
+    /// Inserts `e` as `insert_list` does, on behalf of `writer`, who last read the list at
+    /// version `base`. Fails with `Error::WriteConflict` if another writer has written the list
+    /// since, in which case the caller should resolve the two and retry with the merged version.
+    /// Returns the list's new version.
+    pub fn insert_list_versioned(
+        &mut self,
+        e: List,
+        writer: &str,
+        base: &VersionVector,
+    ) -> Result<VersionVector, Error> {
+        let euid: EntityUid = e.uid().clone().into();
+        let current = self.version(&euid);
+        if !base.descends_from(&current) {
+            return Err(Error::WriteConflict {
+                entity: euid,
+                base: base.clone(),
+                current,
+            });
+        }
+        self.insert_list(e)?;
+        let mut version = base.clone();
+        version.bump(writer);
+        self.versions.insert(euid, version.clone());
+        Ok(version)
+    }
+
+    /// Fails with `Error::QuotaExceeded` if `owner` may not own any more lists.
+    /// Templates don't count toward the quota.
+    pub fn check_list_quota(&self, owner: &UserUid) -> Result<(), Error> {
+        let quota = match self.get_user(owner)?.list_quota() {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+        let owned = self
+            .lists_owned_by(owner)
+            .filter(|list| !list.is_template())
+            .count();
+        if owned >= quota {
+            Err(Error::QuotaExceeded(owner.clone().into(), quota))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates a team whose members are `members`, returning its euid.
+    /// Every member is checked to exist before anything is allocated or inserted,
+    /// so on error the store is left unchanged.
+    pub fn create_team_with_members(
+        &mut self,
+        members: Vec<UserOrTeamUid>,
+    ) -> Result<TeamUid, Error> {
+        if let Some(missing) = members.iter().find(|m| !self.user_or_team_exists(m)) {
+            return Err(Error::no_such_entity(missing.clone()));
+        }
+        let uid = self.fresh_euid::<TeamUid>(EntityType::Team)?;
+        self.insert_team(Team::new(uid.clone()));
+        for member in &members {
+            self.get_user_or_team_mut(member)?
+                .insert_parent(uid.clone());
+        }
+        Ok(uid)
+    }
+
+    /// Creates a team with the same direct members as `source`, returning its euid.
+    /// Teams have no name, so the clone only differs by its fresh euid. It isn't made a member
+    /// of anything, so it doesn't get `source`'s shares, which are held on the lists.
+    pub fn clone_team(&mut self, source: &TeamUid) -> Result<TeamUid, Error> {
+        self.get_team(source)?;
+        let members = self.members_of(source);
+        self.create_team_with_members(members)
+    }
+
+    /// Gathers everything the store holds about `user`, for answering a data-subject access request.
+    /// Lists shared with `user` are only named, with the role they were shared at, since their
+    /// contents belong to their owners. Under `use-templates` there are no grants, see `Grant`.
+    pub fn export_user_data(&self, user: &UserUid) -> Result<UserDataExport, Error> {
+        let profile = self.get_user(user)?.clone();
+        let owned_lists = self.lists_owned_by(user).cloned().collect();
+        #[cfg(not(feature = "use-templates"))]
+        let grants = self.grants_of(&profile);
+        let teams = profile
+            .parent_teams()
+            .into_iter()
+            .filter(|team| self.teams.contains_key(team.as_ref()) && !self.is_share_team(team))
+            .collect();
+        Ok(UserDataExport {
+            profile,
+            owned_lists,
+            teams,
+            #[cfg(not(feature = "use-templates"))]
+            grants,
+        })
+    }
+
+    // The lists shared with `user` directly, and the role each was shared at
+    #[cfg(not(feature = "use-templates"))]
+    fn grants_of(&self, user: &User) -> Vec<Grant> {
+        self.lists
+            .values()
+            .flat_map(|list| {
+                [ShareRole::Reader, ShareRole::Editor]
+                    .into_iter()
+                    .filter(|role| user.has_parent(list.get_team(*role)))
+                    .map(|role| Grant {
+                        list: list.uid().clone(),
+                        role,
+                    })
+            })
+            .collect()
+    }
+
+    // Shares are policies with the "use-templates" feature, so the store holds no grants
+    #[cfg(feature = "use-templates")]
+    fn grants_of(&self, _user: &User) -> Vec<Grant> {
+        vec![]
+    }
+
+    /// Hands every list `user` owns to `successor`, takes `user` out of every team, and revokes
+    /// every share made to `user` directly, reporting each change. Both users are checked to exist
+    /// before anything changes. Under `use-templates` `AppContext::offboard_user` revokes the
+    /// shares instead, see `Grant`.
+    pub fn offboard_user(
+        &mut self,
+        user: &UserUid,
+        successor: &UserUid,
+    ) -> Result<OffboardReport, Error> {
+        let profile = self.get_user(user)?;
+        self.get_user(successor)?;
+        let report = OffboardReport {
+            lists_transferred: self
+                .lists_owned_by(user)
+                .map(|list| list.uid().clone())
+                .collect(),
+            teams_left: profile
+                .parent_teams()
+                .into_iter()
+                .filter(|team| !self.is_share_team(team))
+                .collect(),
+            grants_revoked: self.grants_of(profile),
+        };
+        for list in &report.lists_transferred {
+            self.get_list_mut(list)?.set_owner(successor.clone());
+        }
+        let profile = self.get_user_mut(user)?;
+        for team in profile.parent_teams() {
+            profile.delete_parent(&team);
+        }
+        Ok(report)
+    }
+
+    /// Captures who is a member of which team and, without the `use-templates` feature,
+    /// who each list is shared with, leaving out the lists' contents
+    pub fn export_acls(&self) -> AclSnapshot {
+        let users = self
+            .users
+            .values()
+            .map(|u| (UserOrTeamUid::from(u.uid().clone()), u as &dyn UserOrTeam));
+        let teams = self
+            .teams
+            .values()
+            .map(|t| (UserOrTeamUid::from(t.uid().clone()), t as &dyn UserOrTeam));
+        let memberships = users
+            .chain(teams)
+            .map(|(member, entity)| Membership {
+                member,
+                teams: entity
+                    .parent_teams()
+                    .into_iter()
+                    .filter(|team| !self.is_share_team(team))
+                    .collect(),
+            })
+            .collect();
+        AclSnapshot {
+            memberships,
+            #[cfg(not(feature = "use-templates"))]
+            shares: self
+                .lists
+                .values()
+                .map(|list| ListShares {
+                    list: list.uid().clone(),
+                    readers: self.members_of(list.get_team(ShareRole::Reader)),
+                    editors: self.members_of(list.get_team(ShareRole::Editor)),
+                })
+                .collect(),
+        }
+    }
+
+    /// The teams that are directly shared `list`, by role. Users it is shared with, and the
+    /// members of those teams, aren't included.
+    #[cfg(not(feature = "use-templates"))]
+    pub fn granting_teams(&self, list: &ListUid) -> Result<GrantingTeams, Error> {
+        let list = self.get_list(list)?;
+        let teams = |role| {
+            self.teams
+                .values()
+                .filter(|t| t.has_parent(list.get_team(role)))
+                .map(|t| t.uid().clone())
+                .collect()
+        };
+        Ok(GrantingTeams {
+            readers: teams(ShareRole::Reader),
+            editors: teams(ShareRole::Editor),
+        })
+    }
+
+    /// Reapplies an `AclSnapshot`. Every user, team, and list it names is checked to exist
+    /// before anything changes, so on error the store is left unchanged.
+    /// Users and teams the snapshot doesn't name keep their memberships, and lists it doesn't
+    /// name keep their shares.
+    pub fn import_acls(&mut self, snapshot: AclSnapshot) -> Result<(), Error> {
+        for membership in &snapshot.memberships {
+            if !self.user_or_team_exists(&membership.member) {
+                return Err(Error::no_such_entity(membership.member.clone()));
+            }
+            for team in &membership.teams {
+                self.get_team(team)?;
+            }
+        }
+        #[cfg(not(feature = "use-templates"))]
+        for shares in &snapshot.shares {
+            self.get_list(&shares.list)?;
+            if let Some(missing) = shares
+                .readers
+                .iter()
+                .chain(&shares.editors)
+                .find(|m| !self.user_or_team_exists(m))
+            {
+                return Err(Error::no_such_entity(missing.clone()));
+            }
+        }
+
+        for membership in snapshot.memberships {
+            let current: Vec<TeamUid> = self
+                .user_or_team(&membership.member)?
+                .parent_teams()
+                .into_iter()
+                .filter(|team| !self.is_share_team(team))
+                .collect();
+            let member = self.get_user_or_team_mut(&membership.member)?;
+            for team in current {
+                member.delete_parent(&team);
+            }
+            for team in membership.teams {
+                member.insert_parent(team);
+            }
+        }
+        #[cfg(not(feature = "use-templates"))]
+        for shares in snapshot.shares {
+            let list = self.get_list(&shares.list)?;
+            let roles = [
+                (list.get_team(ShareRole::Reader).clone(), shares.readers),
+                (list.get_team(ShareRole::Editor).clone(), shares.editors),
+            ];
+            for (team, members) in roles {
+                for member in self.members_of(&team) {
+                    self.get_user_or_team_mut(&member)?.delete_parent(&team);
+                }
+                for member in members {
+                    self.get_user_or_team_mut(&member)?
+                        .insert_parent(team.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn user_or_team(&self, euid: &UserOrTeamUid) -> Result<&dyn UserOrTeam, Error> {
+        match self.users.get(euid.as_ref()) {
+            Some(u) => Ok(u),
+            None => self
+                .teams
+                .get(euid.as_ref())
+                .map(|t| t as &dyn UserOrTeam)
+                .ok_or_else(|| Error::no_such_entity(euid.clone())),
+        }
+    }
+
+    fn user_or_team_exists(&self, euid: &UserOrTeamUid) -> bool {
+        self.users.contains_key(euid.as_ref()) || self.teams.contains_key(euid.as_ref())
+    }
+
+    /// Every team `user` is a member of, directly or through teams nested in other teams,
+    /// not counting the teams backing list shares
+    pub fn teams_for_user(&self, user: &UserUid) -> Result<Vec<TeamUid>, Error> {
+        let teams = self.ancestor_teams(self.get_user(user)?);
+        Ok(teams
+            .into_iter()
+            .filter(|team| !self.is_share_team(team))
+            .collect())
+    }
+
+    /// Every team `team` is in, directly or through nesting, each once. Teams that are members
+    /// of each other are each other's ancestors, so a team in such a cycle is its own ancestor.
+    /// `as_entities` needn't flatten this chain itself, since Cedar computes the same closure
+    /// when it builds the entities.
+    pub fn team_ancestors(&self, team: &TeamUid) -> Result<Vec<TeamUid>, Error> {
+        Ok(self.ancestor_teams(self.get_team(team)?))
+    }
+
+    /// The lists shared with `who`, or with any team it is a member of, directly or through
+    /// nesting. Under `use-templates` `AppContext` finds them instead, see `Grant`.
+    #[cfg(not(feature = "use-templates"))]
+    pub fn lists_shared_with(&self, who: &UserOrTeamUid) -> Result<Vec<ListUid>, Error> {
+        let member: &dyn UserOrTeam = match self.users.get(who.as_ref()) {
+            Some(user) => user,
+            None => self
+                .teams
+                .get(who.as_ref())
+                .ok_or_else(|| Error::no_such_entity(who.clone()))?,
+        };
+        let teams = self
+            .ancestor_teams(member)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        Ok(self
+            .lists
+            .values()
+            .filter(|list| {
+                [ShareRole::Reader, ShareRole::Editor]
+                    .into_iter()
+                    .any(|role| teams.contains(list.get_team(role)))
+            })
+            .map(|list| list.uid().clone())
+            .collect())
+    }
+
+    /// Stops sharing `list` with `from` directly, at either role, and returns the role it had
+    /// been shared at, the higher one if both. Unsharing a list that wasn't shared with `from`
+    /// changes nothing and returns `None`. Shares through a team `from` is in are left alone.
+    #[cfg(not(feature = "use-templates"))]
+    pub fn unshare_list(
+        &mut self,
+        list: &ListUid,
+        from: &UserOrTeamUid,
+    ) -> Result<Option<ShareRole>, Error> {
+        let list = self.get_list(list)?;
+        let readers = list.get_team(ShareRole::Reader).clone();
+        let editors = list.get_team(ShareRole::Editor).clone();
+        let member = self.get_user_or_team_mut(from)?;
+        let previous = if member.has_parent(&editors) {
+            Some(ShareRole::Editor)
+        } else if member.has_parent(&readers) {
+            Some(ShareRole::Reader)
+        } else {
+            None
+        };
+        member.delete_parent(&readers);
+        member.delete_parent(&editors);
+        Ok(previous)
+    }
+
+    /// The stored entities that refer to `euid`, each with the field that refers to it
+    pub fn referrers(&self, euid: &EntityUid) -> Vec<(EntityUid, &'static str)> {
+        let members = self
+            .users
+            .values()
+            .map(|u| (u.uid().as_ref(), u as &dyn UserOrTeam))
+            .chain(
+                self.teams
+                    .values()
+                    .map(|t| (t.uid().as_ref(), t as &dyn UserOrTeam)),
+            )
+            .filter(|(_, member)| {
+                TeamUid::try_from(euid.clone()).map_or(false, |team| member.has_parent(&team))
+            })
+            .map(|(member, _)| (member.clone(), "parents"));
+        let lists = self.lists.values().flat_map(|list| {
+            let fields: Vec<(&'static str, &EntityUid)> = vec![
+                ("owner", list.owner().as_ref()),
+                #[cfg(not(feature = "use-templates"))]
+                ("readers", list.get_team(ShareRole::Reader).as_ref()),
+                #[cfg(not(feature = "use-templates"))]
+                ("editors", list.get_team(ShareRole::Editor).as_ref()),
+            ];
+            fields
+                .into_iter()
+                .filter(|(_, field)| *field == euid)
+                .map(|(name, _)| (list.uid().clone().into(), name))
+        });
+        members.chain(lists).collect()
+    }
+
+    // The stored teams `member` is in, directly or through nesting, each once
+    fn ancestor_teams(&self, member: &dyn UserOrTeam) -> Vec<TeamUid> {
+        let mut found = vec![];
+        let mut seen = HashSet::new();
+        let mut pending = member.parent_teams();
+        while let Some(team) = pending.pop() {
+            if !seen.insert(team.clone()) {
+                continue;
+            }
+            if let Ok(t) = self.get_team(&team) {
+                pending.extend(t.parent_teams());
+                found.push(team);
+            }
+        }
+        found
+    }
+
+    /// The teams `user` is a direct member of that have a default share, with its role
+    pub fn default_shares(&self, user: &UserUid) -> Result<Vec<(TeamUid, ShareRole)>, Error> {
+        let shares = self
+            .get_user(user)?
+            .parent_teams()
+            .into_iter()
+            .filter(|team| !self.is_share_team(team))
+            .filter_map(|team| {
+                let role = self.get_team(&team).ok()?.default_share()?;
+                Some((team, role))
+            })
+            .collect();
+        Ok(shares)
+    }
+
+    // Whether `team` is one of the teams backing a list's readers or editors,
+    // as opposed to a team of users in its own right
+    #[cfg(not(feature = "use-templates"))]
+    fn is_share_team(&self, team: &TeamUid) -> bool {
+        self.lists.values().any(|list| {
+            list.get_team(ShareRole::Reader) == team || list.get_team(ShareRole::Editor) == team
+        })
+    }
+
+    #[cfg(feature = "use-templates")]
+    fn is_share_team(&self, _team: &TeamUid) -> bool {
+        false
+    }
+
+    // The users and teams that are direct members of `team`
+    fn members_of(&self, team: &TeamUid) -> Vec<UserOrTeamUid> {
+        let users = self
+            .users
+            .values()
+            .filter(|u| u.has_parent(team))
+            .map(|u| u.uid().clone().into());
+        let teams = self
+            .teams
+            .values()
+            .filter(|t| t.has_parent(team))
+            .map(|t| t.uid().clone().into());
+        users.chain(teams).collect()
     }
 
     pub fn delete_entity(&mut self, e: impl AsRef<EntityUid>) -> Result<(), Error> {
+        self.touch();
         let r = e.as_ref();
+        #[cfg(feature = "undo")]
+        self.record_undo_if_exists(r);
         if self.users.contains_key(r) {
             self.users.remove(r);
             Ok(())
@@ -94,66 +1223,1037 @@ impl EntityStore {
             Ok(())
         } else if self.lists.contains_key(r) {
             self.lists.remove(r);
+            self.versions.remove(r);
+            Ok(())
+        } else if self.extra.iter_mut().any(|extra| extra.remove(r)) {
             Ok(())
         } else {
             Err(Error::NoSuchEntity(r.clone()))
         }
     }
 
-    pub fn get_user(&self, euid: &UserUid) -> Result<&User, Error> {
-        self.users
-            .get(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    /// Sets the user, team, or list `euid` aside instead of deleting it. It's kept, and saved with
+    /// the store, but it's gone from `euids`, `get`, and `as_entities`, so no policy can see it
+    /// until it's brought back with `restore_entity`. Its euid stays taken meanwhile, so
+    /// `fresh_euid` won't hand it out again.
+    pub fn archive_entity(&mut self, euid: &EntityUid) -> Result<(), Error> {
+        let entity = self
+            .take_live(euid)
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))?;
+        self.touch();
+        self.versions.remove(euid);
+        self.archived.insert(euid.clone(), entity);
+        #[cfg(feature = "undo")]
+        self.undo.push(UndoEntry::Unarchive(euid.clone()));
+        Ok(())
     }
 
-    pub fn get_user_mut(&mut self, euid: &UserUid) -> Result<&mut User, Error> {
-        self.users
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    /// Brings back an entity set aside by `archive_entity`. A list is subject to its owner's
+    /// quota, as any list being inserted is, and stays archived if the owner has no room.
+    pub fn restore_entity(&mut self, euid: &EntityUid) -> Result<(), Error> {
+        let entity = self
+            .archived
+            .get(euid)
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))?;
+        if let StoreEntity::List(l) = entity {
+            self.check_list_quota(l.owner())?;
+        }
+        let entity = self.archived.remove(euid).unwrap();
+        self.touch();
+        self.put_live(entity);
+        #[cfg(feature = "undo")]
+        self.undo.push(UndoEntry::Rearchive(euid.clone()));
+        Ok(())
     }
 
-    pub fn get_team(&self, euid: &TeamUid) -> Result<&Team, Error> {
-        self.teams
-            .get(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    // Takes the user, team, or list `euid` out of the store, without recording an undo
+    fn take_live(&mut self, euid: &EntityUid) -> Option<StoreEntity> {
+        if let Some(u) = self.users.remove(euid) {
+            Some(StoreEntity::User(u))
+        } else if let Some(t) = self.teams.remove(euid) {
+            Some(StoreEntity::Team(t))
+        } else {
+            self.lists.remove(euid).map(StoreEntity::List)
+        }
     }
 
-    pub fn get_team_mut(&mut self, euid: &TeamUid) -> Result<&mut Team, Error> {
-        self.teams
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    // Puts `entity` into the store, replacing any with its euid, without recording an undo
+    fn put_live(&mut self, entity: StoreEntity) {
+        match entity {
+            StoreEntity::User(u) => {
+                self.users.insert(u.uid().clone().into(), u);
+            }
+            StoreEntity::Team(t) => {
+                self.teams.insert(t.uid().clone().into(), t);
+            }
+            StoreEntity::List(l) => {
+                self.lists.insert(l.uid().clone().into(), l);
+            }
+        }
     }
 
-    pub fn get_user_or_team_mut(
-        &mut self,
-        euid: &UserOrTeamUid,
-    ) -> Result<&mut dyn UserOrTeam, Error> {
-        let euid_ref = euid.as_ref();
-        if self.users.contains_key(euid_ref) {
-            let u = self.users.get_mut(euid_ref).unwrap();
-            Ok(u)
-        } else if self.teams.contains_key(euid_ref) {
-            let t = self.teams.get_mut(euid_ref).unwrap();
-            Ok(t)
-        } else {
-            Err(Error::no_such_entity(euid_ref.clone()))
+    /// The euids of every archived entity
+    pub fn archived(&self) -> impl Iterator<Item = &EntityUid> {
+        self.archived.keys()
+    }
+
+    /// Like `delete_entity`, but nothing is left referring to the deleted entity. A deleted team
+    /// is first taken out of every user and team that is a member of it, and a list whose
+    /// readers or editors it was gets a fresh, empty team in its place, so the list is shared
+    /// with no one through it. Refuses, leaving the store unchanged, to delete a user who owns
+    /// lists, since a list can't be left without an owner; they should be offboarded first.
+    pub fn delete_entity_cascade(&mut self, e: impl AsRef<EntityUid>) -> Result<(), Error> {
+        let euid = e.as_ref();
+        if self.get(euid).is_err() {
+            return Err(Error::no_such_entity(euid.clone()));
         }
+        let referrers = self.referrers(euid);
+        if let Some((list, _)) = referrers.iter().find(|(_, field)| *field == "owner") {
+            return Err(Error::StillReferenced {
+                entity: euid.clone(),
+                by: list.clone(),
+            });
+        }
+        self.with_transaction(|store| {
+            for (referrer, field) in referrers {
+                match field {
+                    "parents" => {
+                        let team = TeamUid::try_from(euid.clone())?;
+                        let member = UserOrTeamUid::try_from(referrer)?;
+                        store.get_user_or_team_mut(&member)?.delete_parent(&team);
+                    }
+                    #[cfg(not(feature = "use-templates"))]
+                    "readers" | "editors" => {
+                        let role = match field {
+                            "readers" => ShareRole::Reader,
+                            _ => ShareRole::Editor,
+                        };
+                        let list = ListUid::try_from(referrer)?;
+                        let team = store.fresh_euid::<TeamUid>(EntityType::Team)?;
+                        store.insert_team(Team::new(team.clone()));
+                        store.get_list_mut(&list)?.set_team(role, team);
+                    }
+                    _ => unreachable!("owners were refused above"),
+                }
+            }
+            store.delete_entity(euid)
+        })
     }
 
-    pub fn get_list(&self, euid: &ListUid) -> Result<&List, Error> {
-        self.lists
-            .get(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    /// Runs `f` on the store, keeping its changes only if it succeeds. If it fails, the store is
+    /// put back as `snapshot` found it, counter `fresh_euid` allocates from and all, so a failed
+    /// create doesn't use up any ids.
+    pub fn with_transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let saved = self.snapshot();
+        let result = f(self);
+        if result.is_err() {
+            self.restore(saved);
+        }
+        result
     }
 
-    pub fn get_list_mut(&mut self, euid: &ListUid) -> Result<&mut List, Error> {
-        self.lists
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    /// A copy of the users, teams, lists, applications, and archived and quarantined entities, and
+    /// of the counter `fresh_euid` allocates from, the list versions, and the undo history, for
+    /// `restore` to put back, e.g. after a mutation panics partway through. Extra entities belong
+    /// to whoever registered them, so they can't be copied; nothing the store does within a
+    /// transaction changes them.
+    pub fn snapshot(&self) -> StoreSnapshot {
+        StoreSnapshot {
+            users: self.users.clone(),
+            teams: self.teams.clone(),
+            lists: self.lists.clone(),
+            apps: self.apps.clone(),
+            archived: self.archived.clone(),
+            quarantine: self.quarantine.clone(),
+            uid: self.uid,
+            versions: self.versions.clone(),
+            #[cfg(feature = "undo")]
+            undo: self.undo.entries.clone(),
+        }
     }
-}
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    /// Puts back everything `snapshot` copied, undoing every change made since
+    pub fn restore(&mut self, snapshot: StoreSnapshot) {
+        self.touch();
+        self.users = snapshot.users;
+        self.teams = snapshot.teams;
+        self.lists = snapshot.lists;
+        self.apps = snapshot.apps;
+        self.archived = snapshot.archived;
+        self.quarantine = snapshot.quarantine;
+        self.uid = snapshot.uid;
+        self.versions = snapshot.versions;
+        #[cfg(feature = "undo")]
+        {
+            self.undo.entries = snapshot.undo;
+        }
+    }
+
+    /// Applies `ops` in order, all or nothing. If any fails, every change the earlier ones made
+    /// is rolled back, and the error names the failing op by its index in `ops`.
+    pub fn batch(&mut self, ops: Vec<StoreOp>) -> Result<(), Error> {
+        self.with_transaction(|store| {
+            for (index, op) in ops.into_iter().enumerate() {
+                store.apply(op).map_err(|e| Error::BatchFailed {
+                    index,
+                    source: Box::new(e),
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn apply(&mut self, op: StoreOp) -> Result<(), Error> {
+        match op {
+            StoreOp::InsertUser(user) => self.insert_user(user),
+            StoreOp::InsertTeam(team) => self.insert_team(team),
+            StoreOp::InsertList(list) => self.insert_list(list)?,
+            StoreOp::Delete(euid) => {
+                self.get(&euid)?;
+                self.delete_entity_cascade(euid)?
+            }
+            StoreOp::AddMember { member, team } => {
+                self.get_team(&team)?;
+                self.get_user_or_team_mut(&member)?.insert_parent(team);
+            }
+            StoreOp::RemoveMember { member, team } => {
+                self.get_user_or_team_mut(&member)?.delete_parent(&team);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames `list` on behalf of `writer` through `insert_list_versioned`, failing with
+    /// `Error::InvalidListName` if `name` is empty or longer than `MAX_LIST_NAME_LEN` characters.
+    /// Without a `base` version, the rename is applied over whatever was written before it.
+    pub fn update_list_name(
+        &mut self,
+        list: &ListUid,
+        name: String,
+        writer: &str,
+        base: Option<&VersionVector>,
+    ) -> Result<VersionVector, Error> {
+        let len = name.chars().count();
+        if len == 0 || len > MAX_LIST_NAME_LEN {
+            return Err(Error::InvalidListName);
+        }
+        let mut renamed = self.get_list(list)?.clone();
+        renamed.update_name(name);
+        let base = match base {
+            Some(base) => base.clone(),
+            None => self.version(list.as_ref()),
+        };
+        self.insert_list_versioned(renamed, writer, &base)
+    }
+
+    /// Merges the list `from` into the list `into`, deleting `from`.
+    /// The tasks of `from` are appended after those of `into`, and everyone `from` was shared with
+    /// is given the same role on `into`. Shares are unioned per role, so a user or team that is a
+    /// reader of one list and an editor of the other ends up an editor of the merged list.
+    /// Under `use-templates` only the tasks are merged here, see `Grant`.
+    pub fn merge_lists(&mut self, into: &ListUid, from: &ListUid) -> Result<(), Error> {
+        let source = self.get_list(from)?.clone();
+        if into == from {
+            return Ok(());
+        }
+        #[cfg(not(feature = "use-templates"))]
+        {
+            let target = self.get_list(into)?;
+            let shares = [ShareRole::Reader, ShareRole::Editor]
+                .map(|role| (source.get_team(role).clone(), target.get_team(role).clone()));
+            for (from_team, into_team) in shares {
+                for member in self.members_of(&from_team) {
+                    self.get_user_or_team_mut(&member)?
+                        .insert_parent(into_team.clone());
+                }
+            }
+        }
+        self.get_list_mut(into)?.append_tasks(source);
+        self.delete_entity(from)
+    }
+
+    /// Adds a task carrying the custom `fields` to `list`, validating them against the list's
+    /// task schema. Returns the new task's id.
+    pub fn add_task_validated(
+        &mut self,
+        list: &ListUid,
+        name: String,
+        fields: HashMap<String, Value>,
+    ) -> Result<i64, Error> {
+        let id = self
+            .get_list_mut(list)?
+            .create_task_with_fields(name, fields)?;
+        Ok(id)
+    }
+
+    /// Marks every task on `list` matching `pred` as checked if `completed`, or unchecked if not.
+    /// Returns how many tasks changed state; tasks keep their ids and order.
+    pub fn complete_tasks_where(
+        &mut self,
+        list: &ListUid,
+        pred: impl Fn(&Task) -> bool,
+        completed: bool,
+    ) -> Result<usize, Error> {
+        let state = if completed {
+            TaskState::Checked
+        } else {
+            TaskState::Unchecked
+        };
+        Ok(self.get_list_mut(list)?.set_state_where(pred, state))
+    }
+
+    /// Whether `euid` is any user, team, list, or application in the store, or an extra entity
+    pub fn contains(&self, euid: &EntityUid) -> bool {
+        self.get(euid).is_ok()
+            || self.apps.contains_key(euid)
+            || self
+                .extra
+                .iter()
+                .any(|extra| extra.euids().any(|e| e == euid))
+    }
+
+    /// Resolves any user, team, or list by its euid
+    pub fn get(&self, euid: &EntityUid) -> Result<EntityRef<'_>, Error> {
+        if let Some(u) = self.users.get(euid) {
+            Ok(EntityRef::User(u))
+        } else if let Some(t) = self.teams.get(euid) {
+            Ok(EntityRef::Team(t))
+        } else if let Some(l) = self.lists.get(euid) {
+            Ok(EntityRef::List(l))
+        } else {
+            Err(Error::no_such_entity(euid.clone()))
+        }
+    }
+
+    /// Resolves each of `euids` as `get` does, in the same order, so that one missing euid
+    /// doesn't fail the rest
+    pub fn get_many(&self, euids: &[EntityUid]) -> Vec<Result<EntityRef<'_>, Error>> {
+        euids.iter().map(|euid| self.get(euid)).collect()
+    }
+
+    pub fn get_user(&self, euid: &UserUid) -> Result<&User, Error> {
+        self.users
+            .get(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    pub fn get_user_mut(&mut self, euid: &UserUid) -> Result<&mut User, Error> {
+        self.touch();
+        #[cfg(feature = "undo")]
+        self.record_undo_if_exists(euid.as_ref());
+        self.users
+            .get_mut(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    pub fn get_team(&self, euid: &TeamUid) -> Result<&Team, Error> {
+        self.teams
+            .get(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    pub fn get_team_mut(&mut self, euid: &TeamUid) -> Result<&mut Team, Error> {
+        self.touch();
+        #[cfg(feature = "undo")]
+        self.record_undo_if_exists(euid.as_ref());
+        self.teams
+            .get_mut(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    pub fn get_user_or_team_mut(
+        &mut self,
+        euid: &UserOrTeamUid,
+    ) -> Result<&mut dyn UserOrTeam, Error> {
+        self.touch();
+        let euid_ref = euid.as_ref();
+        #[cfg(feature = "undo")]
+        self.record_undo_if_exists(euid_ref);
+        if self.users.contains_key(euid_ref) {
+            let u = self.users.get_mut(euid_ref).unwrap();
+            Ok(u)
+        } else if self.teams.contains_key(euid_ref) {
+            let t = self.teams.get_mut(euid_ref).unwrap();
+            Ok(t)
+        } else {
+            Err(Error::no_such_entity(euid_ref.clone()))
+        }
+    }
+
+    pub fn get_list(&self, euid: &ListUid) -> Result<&List, Error> {
+        self.lists
+            .get(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+
+    pub fn get_list_mut(&mut self, euid: &ListUid) -> Result<&mut List, Error> {
+        self.touch();
+        #[cfg(feature = "undo")]
+        self.record_undo_if_exists(euid.as_ref());
+        self.lists
+            .get_mut(euid.as_ref())
+            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+    }
+}
+
+// Generations are handed out from one counter, so that a store that replaces another never
+// reuses the old store's generation
+fn next_generation() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A store's `Entities`, kept until the store's generation changes, so that authorizing many
+/// requests against an unchanged store builds them just once
+#[derive(Debug, Default)]
+pub struct EntitiesCache(Mutex<Option<(u64, Arc<Entities>)>>);
+
+impl EntitiesCache {
+    /// `store.as_entities(schema)`, rebuilt only if `store` changed since the last call.
+    /// A failure isn't cached, so it's tried again next time.
+    pub fn get(
+        &self,
+        store: &EntityStore,
+        schema: &Schema,
+    ) -> Result<Arc<Entities>, EntityDecodeError> {
+        let mut cached = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((generation, es)) = &*cached {
+            if *generation == store.generation() {
+                return Ok(es.clone());
+            }
+        }
+        let es = Arc::new(store.as_entities(schema)?);
+        *cached = Some((store.generation(), es.clone()));
+        Ok(es)
+    }
+}
+
+/// Everything the store holds about a single user, see `EntityStore::export_user_data`
+#[derive(Debug, Clone, Serialize)]
+pub struct UserDataExport {
+    pub profile: User,
+    pub owned_lists: Vec<List>,
+    /// The teams the user is a direct member of, not counting the teams backing list shares
+    pub teams: Vec<TeamUid>,
+    #[cfg(not(feature = "use-templates"))]
+    pub grants: Vec<Grant>,
+}
+
+/// The state of an `EntityStore` at some point, see `EntityStore::snapshot`
+#[derive(Debug)]
+pub struct StoreSnapshot {
+    users: HashMap<EntityUid, User>,
+    teams: HashMap<EntityUid, Team>,
+    lists: HashMap<EntityUid, List>,
+    apps: HashMap<EntityUid, Application>,
+    archived: HashMap<EntityUid, StoreEntity>,
+    quarantine: Vec<Quarantined>,
+    uid: usize,
+    versions: HashMap<EntityUid, VersionVector>,
+    #[cfg(feature = "undo")]
+    undo: std::collections::VecDeque<UndoEntry>,
+}
+
+/// A mutation `EntityStore::batch` can apply
+#[derive(Debug, Clone)]
+pub enum StoreOp {
+    /// Inserts a user, replacing any with the same euid
+    InsertUser(User),
+    /// Inserts a team, replacing any with the same euid
+    InsertTeam(Team),
+    /// Inserts a list as `insert_list` does, subject to its owner's quota
+    InsertList(List),
+    /// Deletes a user, team, or list as `delete_entity_cascade` does. Extra entities can't be
+    /// deleted in a batch, since a batch that fails couldn't put them back.
+    Delete(EntityUid),
+    /// Makes `member` a direct member of `team`
+    AddMember {
+        member: UserOrTeamUid,
+        team: TeamUid,
+    },
+    /// Takes `member` out of `team`, if it is a direct member
+    RemoveMember {
+        member: UserOrTeamUid,
+        team: TeamUid,
+    },
+}
+
+/// What `EntityStore::offboard_user` changed
+#[derive(Debug, Clone, Serialize)]
+pub struct OffboardReport {
+    pub lists_transferred: Vec<ListUid>,
+    /// The teams the user was taken out of, not counting the teams backing list shares
+    pub teams_left: Vec<TeamUid>,
+    pub grants_revoked: Vec<Grant>,
+}
+
+/// What `EntityStore::diff` found, for each type of entity
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreDiff {
+    pub users: EntityDiff,
+    pub teams: EntityDiff,
+    pub lists: EntityDiff,
+    pub apps: EntityDiff,
+}
+
+impl StoreDiff {
+    pub fn is_empty(&self) -> bool {
+        [&self.users, &self.teams, &self.lists, &self.apps]
+            .iter()
+            .all(|diff| diff.is_empty())
+    }
+}
+
+/// The entities of one type that were added, removed, or modified, each sorted by euid
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EntityDiff {
+    pub added: Vec<EntityUid>,
+    pub removed: Vec<EntityUid>,
+    pub modified: Vec<EntityUid>,
+}
+
+impl EntityDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn diff_entities<T: Serialize>(
+    before: &HashMap<EntityUid, T>,
+    after: &HashMap<EntityUid, T>,
+) -> EntityDiff {
+    let added = after.keys().filter(|euid| !before.contains_key(euid));
+    let removed = before.keys().filter(|euid| !after.contains_key(euid));
+    let modified = before.iter().filter_map(|(euid, old)| {
+        let new = after.get(euid)?;
+        (serde_json::to_value(old).ok() != serde_json::to_value(new).ok()).then_some(euid)
+    });
+    EntityDiff {
+        added: sorted_euids(added),
+        removed: sorted_euids(removed),
+        modified: sorted_euids(modified),
+    }
+}
+
+fn sorted_euids<'a>(euids: impl Iterator<Item = &'a EntityUid>) -> Vec<EntityUid> {
+    euids.cloned().sorted().collect()
+}
+
+/// A list shared with a user, and the role it was shared at.
+///
+/// Without the `use-templates` feature a share is membership of the list's readers or editors
+/// team, which the store holds. With it, a share is a template-linked policy, which `AppContext`
+/// holds instead, so the store has no shares to report, revoke, or move.
+#[derive(Debug, Clone, Serialize)]
+pub struct Grant {
+    pub list: ListUid,
+    pub role: ShareRole,
+}
+
+/// An entity that `EntityStore::load_lenient` left out of the store, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct Quarantined {
+    /// The key the entity was stored under, which may not even parse as an euid
+    pub key: String,
+    pub reason: String,
+    pub raw: Value,
+}
+
+// The part of an `EntityStore` that is saved; the rest is rebuilt when it's loaded
+#[derive(Debug, Deserialize)]
+struct SavedStore {
+    users: HashMap<EntityUid, User>,
+    teams: HashMap<EntityUid, Team>,
+    lists: HashMap<EntityUid, List>,
+    #[serde(default)]
+    apps: HashMap<EntityUid, Application>,
+    #[serde(default)]
+    archived: HashMap<EntityUid, StoreEntity>,
+    // Stores saved before there could be several applications have just the one
+    #[serde(default)]
+    app: Option<Application>,
+}
+
+impl From<SavedStore> for EntityStore {
+    fn from(saved: SavedStore) -> Self {
+        let mut apps = saved.apps;
+        apps.extend(saved.app.map(|app| (app.euid().clone(), app)));
+        let mut store = Self {
+            users: saved.users,
+            teams: saved.teams,
+            lists: saved.lists,
+            archived: saved.archived,
+            ..Self::default()
+        };
+        if !apps.is_empty() {
+            store.apps = apps;
+        }
+        store.repair_uid_counter();
+        store
+    }
+}
+
+// A snapshot with each entity left undecoded, so that they can be decoded one at a time
+#[derive(Debug, Deserialize)]
+struct RawSnapshot {
+    #[serde(default)]
+    users: serde_json::Map<String, Value>,
+    #[serde(default)]
+    teams: serde_json::Map<String, Value>,
+    #[serde(default)]
+    lists: serde_json::Map<String, Value>,
+    #[serde(default)]
+    apps: serde_json::Map<String, Value>,
+    #[serde(default)]
+    app: Option<Value>,
+    #[serde(default)]
+    archived: serde_json::Map<String, Value>,
+}
+
+// Rewrites Cedar's entity JSON so that equal entities give equal JSON: objects get sorted keys,
+// and arrays, which hold either all the entities or a set, are sorted
+fn canonical_json(v: &Value) -> Value {
+    match v {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(k, v)| (k.clone(), canonical_json(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(canonical_json)
+                .sorted_by_key(|item| item.to_string())
+                .collect(),
+        ),
+        _ => v.clone(),
+    }
+}
+
+// Decodes `raw` and validates it against `schema`, quarantining it if either fails
+fn admit<T>(
+    key: String,
+    raw: Value,
+    schema: &Schema,
+    quarantine: &mut Vec<Quarantined>,
+) -> Option<T>
+where
+    T: DeserializeOwned + Clone + Into<Entity>,
+{
+    let checked = serde_json::from_value::<T>(raw.clone())
+        .map_err(|e| e.to_string())
+        .and_then(|decoded| {
+            Entities::from_entities([decoded.clone().into()], Some(schema))
+                .map(|_| decoded)
+                .map_err(|e| e.to_string())
+        });
+    match checked {
+        Ok(decoded) => Some(decoded),
+        Err(reason) => {
+            quarantine.push(Quarantined { key, reason, raw });
+            None
+        }
+    }
+}
+
+/// The access-control state of the store, see `EntityStore::export_acls`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclSnapshot {
+    /// The teams each user and team is a direct member of, not counting the teams backing list shares
+    pub memberships: Vec<Membership>,
+    #[cfg(not(feature = "use-templates"))]
+    pub shares: Vec<ListShares>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub member: UserOrTeamUid,
+    pub teams: Vec<TeamUid>,
+}
+
+/// Who a list is shared with, by role
+#[cfg(not(feature = "use-templates"))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListShares {
+    pub list: ListUid,
+    pub readers: Vec<UserOrTeamUid>,
+    pub editors: Vec<UserOrTeamUid>,
+}
+
+/// The teams a list is directly shared with, by role, see `EntityStore::granting_teams`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GrantingTeams {
+    pub readers: Vec<TeamUid>,
+    pub editors: Vec<TeamUid>,
+}
+
+/// Entity counts, see `EntityStore::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    pub users: usize,
+    pub teams: usize,
+    pub lists: usize,
+    /// Reader and editor entries across all lists, each user or team a list is directly shared
+    /// with counting once per role
+    #[cfg(not(feature = "use-templates"))]
+    pub shares: usize,
+    pub next_uid: usize,
+}
+
+/// The teams a user is in and the lists shared with them, see `EntityStore::teams_for_user`
+/// and `EntityStore::lists_shared_with`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Memberships {
+    pub teams: Vec<TeamUid>,
+    pub lists: Vec<ListUid>,
+}
+
+/// An `EntityStore` shared between threads, which may read it concurrently but write it only
+/// one at a time. The `get_*` queries take the read lock for just the lookup, and return a
+/// clone. A poisoned lock is taken over rather than failing. Changes made through `update` are
+/// rolled back before a panic poisons it, so they never leave the store half-updated; changes
+/// made through `write` directly aren't.
+#[derive(Debug, Clone, Default)]
+pub struct SharedEntityStore(Arc<RwLock<EntityStore>>);
+
+impl SharedEntityStore {
+    pub fn new(store: EntityStore) -> Self {
+        Self(Arc::new(RwLock::new(store)))
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, EntityStore> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, EntityStore> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` on the store under the write lock, as `EntityStore::with_transaction` does, but
+    /// puts the store back as it was if `f` panics, too. The panic is then resumed.
+    pub fn update<T>(
+        &self,
+        f: impl FnOnce(&mut EntityStore) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut store = self.write();
+        let saved = store.snapshot();
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *store))) {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => {
+                store.restore(saved);
+                Err(e)
+            }
+            Err(panicked) => {
+                store.restore(saved);
+                drop(store);
+                panic::resume_unwind(panicked)
+            }
+        }
+    }
+
+    pub fn get_user(&self, euid: &UserUid) -> Result<User, Error> {
+        self.read().get_user(euid).cloned()
+    }
+
+    pub fn get_team(&self, euid: &TeamUid) -> Result<Team, Error> {
+        self.read().get_team(euid).cloned()
+    }
+
+    pub fn get_list(&self, euid: &ListUid) -> Result<List, Error> {
+        self.read().get_list(euid).cloned()
+    }
+}
+
+/// Entities of a type the store doesn't hold itself, such as a `Project` type a deployment adds to
+/// its schema. Once registered with `EntityStore::register_extra` they take part in `euids`,
+/// `as_entities`, and `delete_entity` like the built-in types, but they aren't saved with the store.
+pub trait ExtraEntities: std::fmt::Debug + Send + Sync {
+    fn euids(&self) -> Box<dyn Iterator<Item = &EntityUid> + '_>;
+    /// These entities as handed to Cedar
+    fn entities(&self) -> Vec<Entity>;
+    /// Removes the entity `euid`, returning whether there was one
+    fn remove(&mut self, euid: &EntityUid) -> bool;
+}
+
+/// Extra entities read from Cedar's entity JSON format, such as the file named by
+/// `TINYTODO_EXTRA_ENTITIES`
+#[derive(Debug, Default)]
+pub struct JsonEntities(HashMap<EntityUid, Entity>);
+
+impl JsonEntities {
+    /// Reads `json`, checking each entity against `schema`. The schema's actions are skipped,
+    /// and an entity of a type the store holds itself is an error, as it belongs in the store.
+    pub fn from_json_str(json: &str, schema: &Schema) -> Result<Self, EntityDecodeError> {
+        let actions = schema
+            .action_entities()
+            .map_err(|e| EntityDecodeError::SchemaActions(e.to_string()))?;
+        let entities = Entities::from_json_str(json, Some(schema))
+            .map_err(|e| EntityDecodeError::EntityJson(e.to_string()))?;
+        let mut extra = HashMap::new();
+        for entity in entities.iter() {
+            let euid = EntityUid::from(entity.uid());
+            let ty = euid.type_name();
+            if [&*TYPE_USER, &*TYPE_TEAM, &*TYPE_LIST, &*TYPE_APPLICATION].contains(&ty) {
+                return Err(EntityDecodeError::BuiltInType(euid));
+            } else if actions.get(&entity.uid()).is_none() {
+                extra.insert(euid, entity.clone());
+            }
+        }
+        Ok(Self(extra))
+    }
+}
+
+impl ExtraEntities for JsonEntities {
+    fn euids(&self) -> Box<dyn Iterator<Item = &EntityUid> + '_> {
+        Box::new(self.0.keys())
+    }
+
+    fn entities(&self) -> Vec<Entity> {
+        self.0.values().cloned().collect()
+    }
+
+    fn remove(&mut self, euid: &EntityUid) -> bool {
+        self.0.remove(euid).is_some()
+    }
+}
+
+/// Where `EntityStore::fresh_euid` takes ids from, in place of the store's counter, see
+/// `EntityStore::with_id_source`. Ids that are already taken are skipped, so a source must go on
+/// to hand out others; `None` means it has run out.
+pub trait IdSource: std::fmt::Debug + Send + Sync {
+    fn next_id(&mut self, ty: EntityType) -> Option<EntityId>;
+}
+
+/// Hands out random UUIDs, so ids don't reveal how many entities came before, and never run out
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidIds;
+
+impl IdSource for UuidIds {
+    fn next_id(&mut self, _ty: EntityType) -> Option<EntityId> {
+        Some(Uuid::new_v4().to_string().parse().unwrap())
+    }
+}
+
+/// A user, team, or list, as held by the `EntityStore`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StoreEntity {
+    User(User),
+    Team(Team),
+    List(List),
+}
+
+impl StoreEntity {
+    pub fn euid(&self) -> &EntityUid {
+        match self {
+            StoreEntity::User(u) => u.uid().as_ref(),
+            StoreEntity::Team(t) => t.uid().as_ref(),
+            StoreEntity::List(l) => l.uid().as_ref(),
+        }
+    }
+
+    fn references(&self) -> Vec<(&'static str, EntityUid)> {
+        match self {
+            StoreEntity::User(u) => EntityRef::User(u),
+            StoreEntity::Team(t) => EntityRef::Team(t),
+            StoreEntity::List(l) => EntityRef::List(l),
+        }
+        .references()
+    }
+}
+
+impl EntityRef<'_> {
+    pub fn euid(&self) -> &EntityUid {
+        match self {
+            EntityRef::User(u) => u.uid().as_ref(),
+            EntityRef::Team(t) => t.uid().as_ref(),
+            EntityRef::List(l) => l.uid().as_ref(),
+        }
+    }
+
+    // The entities this one refers to, each with the field that refers to it
+    fn references(&self) -> Vec<(&'static str, EntityUid)> {
+        let parents = |entity: &dyn UserOrTeam| {
+            entity
+                .parent_teams()
+                .into_iter()
+                .map(|team| ("parents", team.into()))
+                .collect()
+        };
+        match self {
+            EntityRef::User(u) => parents(*u),
+            EntityRef::Team(t) => parents(*t),
+            EntityRef::List(l) => vec![
+                ("owner", l.owner().clone().into()),
+                #[cfg(not(feature = "use-templates"))]
+                ("readers", l.get_team(ShareRole::Reader).clone().into()),
+                #[cfg(not(feature = "use-templates"))]
+                ("editors", l.get_team(ShareRole::Editor).clone().into()),
+            ],
+        }
+    }
+}
+
+impl From<EntityRef<'_>> for StoreEntity {
+    fn from(entity: EntityRef<'_>) -> Self {
+        match entity {
+            EntityRef::User(u) => StoreEntity::User(u.clone()),
+            EntityRef::Team(t) => StoreEntity::Team(t.clone()),
+            EntityRef::List(l) => StoreEntity::List(l.clone()),
+        }
+    }
+}
+
+impl From<StoreEntity> for Entity {
+    fn from(entity: StoreEntity) -> Self {
+        match entity {
+            StoreEntity::User(u) => u.into(),
+            StoreEntity::Team(t) => t.into(),
+            StoreEntity::List(l) => l.into(),
+        }
+    }
+}
+
+/// A reference from an entity in a batch to one that is neither in the batch nor in the store,
+/// see `EntityStore::validate_batch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchError {
+    pub entity: EntityUid,
+    pub field: &'static str,
+    pub missing: EntityUid,
+}
+
+/// A reference from a stored entity to one that isn't in the store, see
+/// `EntityStore::validate_references`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DanglingRef {
+    pub entity: EntityUid,
+    pub field: &'static str,
+    pub missing: EntityUid,
+}
+
+/// A user, team, or list borrowed from the `EntityStore`, see `EntityStore::get`
+#[derive(Debug, Clone, Copy)]
+pub enum EntityRef<'a> {
+    User(&'a User),
+    Team(&'a Team),
+    List(&'a List),
+}
+
+#[cfg(feature = "undo")]
+const DEFAULT_UNDO_DEPTH: usize = 16;
+
+// Reverts the most recent mutation to a single entity
+#[cfg(feature = "undo")]
+#[derive(Debug, Clone)]
+enum UndoEntry {
+    // The entity did not exist before the mutation
+    Remove(EntityUid),
+    // The entity was in this state before the mutation
+    Restore(StoreEntity),
+    // The entity was live before `archive_entity` set it aside
+    Unarchive(EntityUid),
+    // The entity was archived before `restore_entity` brought it back
+    Rearchive(EntityUid),
+}
+
+#[cfg(feature = "undo")]
+#[derive(Debug)]
+struct UndoStack {
+    entries: std::collections::VecDeque<UndoEntry>,
+    depth: usize,
+}
+
+#[cfg(feature = "undo")]
+impl Default for UndoStack {
+    fn default() -> Self {
+        Self {
+            entries: Default::default(),
+            depth: DEFAULT_UNDO_DEPTH,
+        }
+    }
+}
+
+#[cfg(feature = "undo")]
+impl UndoStack {
+    fn push(&mut self, entry: UndoEntry) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+#[cfg(feature = "undo")]
+impl EntityStore {
+    /// Sets how many mutations can be undone, discarding the oldest entries beyond that
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo.depth = depth;
+        while self.undo.entries.len() > depth {
+            self.undo.entries.pop_front();
+        }
+    }
+
+    /// Reverts the most recent mutation made through the store's `insert_*`, `delete_entity`,
+    /// `archive_entity`, `restore_entity`, and `get_*_mut` methods. Each mutation covers a single
+    /// entity, so an operation that touched several entities takes several undos to revert.
+    pub fn undo(&mut self) -> Result<(), Error> {
+        self.touch();
+        match self.undo.entries.pop_back().ok_or(Error::NothingToUndo)? {
+            UndoEntry::Remove(euid) => {
+                self.take_live(&euid);
+            }
+            UndoEntry::Restore(entity) => self.put_live(entity),
+            UndoEntry::Unarchive(euid) => {
+                if let Some(entity) = self.archived.remove(&euid) {
+                    self.put_live(entity);
+                }
+            }
+            UndoEntry::Rearchive(euid) => {
+                if let Some(entity) = self.take_live(&euid) {
+                    self.archived.insert(euid, entity);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_undo(&mut self, euid: &EntityUid) {
+        let entry = if let Some(u) = self.users.get(euid) {
+            UndoEntry::Restore(StoreEntity::User(u.clone()))
+        } else if let Some(t) = self.teams.get(euid) {
+            UndoEntry::Restore(StoreEntity::Team(t.clone()))
+        } else if let Some(l) = self.lists.get(euid) {
+            UndoEntry::Restore(StoreEntity::List(l.clone()))
+        } else {
+            UndoEntry::Remove(euid.clone())
+        };
+        self.undo.push(entry);
+    }
+
+    fn record_undo_if_exists(&mut self, euid: &EntityUid) {
+        if self.exists(euid) {
+            self.record_undo(euid);
+        }
+    }
+}
+
+/// Lists bucketed by their owner
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListsByOwner {
+    pub owned: BTreeMap<UserUid, Vec<ListUid>>,
+    /// Lists whose owner is no longer in the store
+    pub ownerless: Vec<ListUid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EntityType {
     List,
     User,
@@ -161,6 +2261,41 @@ pub enum EntityType {
     Application,
 }
 
+impl EntityType {
+    /// The Cedar type name of entities of this type
+    pub fn type_name(&self) -> EntityTypeName {
+        self.to_string().parse().unwrap()
+    }
+}
+
+impl std::fmt::Display for EntityType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            EntityType::List => "List",
+            EntityType::User => "User",
+            EntityType::Team => "Team",
+            EntityType::Application => "Application",
+        })
+    }
+}
+
+impl std::str::FromStr for EntityType {
+    type Err = EntityDecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "List" => Ok(EntityType::List),
+            "User" => Ok(EntityType::User),
+            "Team" => Ok(EntityType::Team),
+            "Application" => Ok(EntityType::Application),
+            _ => Err(EntityDecodeError::BadEnum {
+                enumeration: "EntityType",
+                got: s.to_string(),
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Error)]
 pub enum EntityDecodeError {
     #[error("The following required attribute was missing: {0}")]
@@ -174,4 +2309,1023 @@ pub enum EntityDecodeError {
         enumeration: &'static str,
         got: String,
     },
+    #[error("Attribute {0} could not be read: {1}")]
+    BadAttr(&'static str, String),
+    #[error("{0}")]
+    EntityType(#[from] EntityTypeError),
+    #[error("Entity {0} is not of a type the store holds")]
+    UnexpectedType(EntityUid),
+    #[error("Entity {0} is of a type the store holds itself, so it can't be an extra entity")]
+    BuiltInType(EntityUid),
+    #[error("Could not read the schema's actions: {0}")]
+    SchemaActions(String),
+    #[error("Entity {0} does not conform to the schema: {1}")]
+    Nonconforming(EntityUid, String),
+    #[error("The store's entities could not be handed to Cedar: {0}")]
+    Bundle(String),
+    #[error("Could not read or write Cedar entity JSON: {0}")]
+    EntityJson(String),
+    #[error("The schema does not declare entity type {0}")]
+    UndeclaredType(EntityTypeName),
+    #[error("The schema does not declare action {0}")]
+    UndeclaredAction(EntityUid),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(store: &mut EntityStore, name: &str) -> UserUid {
+        let uid: UserUid = format!(r#"User::"{name}""#).parse().unwrap();
+        store.insert_user(User::new(uid.clone(), 5, "ABC17".to_string()));
+        uid
+    }
+
+    fn list(store: &mut EntityStore, owner: &UserUid, name: &str) -> ListUid {
+        let uid: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        let list = List::new(store, uid.clone(), owner.clone(), name.to_string()).unwrap();
+        store.insert_list(list).unwrap();
+        uid
+    }
+
+    #[test]
+    fn observed_attributes_counts_carriers_of_each_key() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        user(&mut store, "emina");
+        list(&mut store, &andrew, "Groceries");
+
+        let users = store.observed_attributes(EntityType::User);
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["joblevel"], 2);
+        assert_eq!(users["location"], 2);
+        let lists = store.observed_attributes(EntityType::List);
+        assert_eq!(lists["owner"], 1);
+        assert_eq!(lists["tasks"], 1);
+        assert!(store.observed_attributes(EntityType::Team).is_empty());
+    }
+
+    #[cfg(feature = "undo")]
+    #[test]
+    fn undo_reverts_the_latest_mutation_first() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let emina = user(&mut store, "emina");
+
+        store.undo().unwrap();
+        assert!(store.get_user(&andrew).is_ok());
+        assert!(store.get_user(&emina).is_err());
+        store.undo().unwrap();
+        assert!(store.get_user(&andrew).is_err());
+        assert!(matches!(store.undo(), Err(Error::NothingToUndo)));
+    }
+
+    #[cfg(feature = "undo")]
+    #[test]
+    fn set_undo_depth_drops_the_oldest_entries() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let emina = user(&mut store, "emina");
+
+        store.set_undo_depth(1);
+        store.undo().unwrap();
+        assert!(store.get_user(&emina).is_err());
+        assert!(matches!(store.undo(), Err(Error::NothingToUndo)));
+        assert!(store.get_user(&andrew).is_ok());
+    }
+
+    #[test]
+    fn fresh_euid_fails_once_the_counter_is_exhausted() {
+        let mut store = EntityStore::default();
+        store.uid = usize::MAX - 1;
+        let last: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        assert_eq!(last.as_ref().id().as_ref(), (usize::MAX - 1).to_string());
+        assert!(matches!(
+            store.fresh_euid::<ListUid>(EntityType::List),
+            Err(Error::IdSpaceExhausted)
+        ));
+    }
+
+    #[test]
+    fn import_acls_restores_exported_acls() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let team = store
+            .create_team_with_members(vec![andrew.clone().into()])
+            .unwrap();
+        #[cfg(not(feature = "use-templates"))]
+        let (emina, readers) = {
+            let emina = user(&mut store, "emina");
+            let groceries = list(&mut store, &andrew, "Groceries");
+            let readers = store
+                .get_list(&groceries)
+                .unwrap()
+                .get_team(ShareRole::Reader)
+                .clone();
+            store
+                .get_user_mut(&emina)
+                .unwrap()
+                .insert_parent(readers.clone());
+            (emina, readers)
+        };
+        let snapshot = serde_json::to_value(store.export_acls()).unwrap();
+
+        store.get_user_mut(&andrew).unwrap().delete_parent(&team);
+        #[cfg(not(feature = "use-templates"))]
+        store.get_user_mut(&emina).unwrap().delete_parent(&readers);
+        store
+            .import_acls(serde_json::from_value(snapshot).unwrap())
+            .unwrap();
+
+        assert!(store.get_user(&andrew).unwrap().has_parent(&team));
+        #[cfg(not(feature = "use-templates"))]
+        assert!(store.get_user(&emina).unwrap().has_parent(&readers));
+    }
+
+    fn schema_src() -> &'static str {
+        #[cfg(not(feature = "use-templates"))]
+        let src = include_str!("../tinytodo.cedarschema");
+        #[cfg(feature = "use-templates")]
+        let src = include_str!("../tinytodo-templates.cedarschema");
+        src
+    }
+
+    fn schema() -> Schema {
+        Schema::from_str_natural(schema_src()).unwrap().0
+    }
+
+    #[test]
+    fn from_entities_keeps_only_cedar_state() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let task_schema = serde_json::from_value(serde_json::json!({ "size": "Long" })).unwrap();
+        let list = store.get_list_mut(&groceries).unwrap();
+        list.create_task("milk".to_string());
+        list.set_task_schema(Some(task_schema));
+        list.set_template(true);
+        store
+            .get_user_mut(&andrew)
+            .unwrap()
+            .set_list_order(vec![groceries.clone()])
+            .unwrap();
+
+        let schema = schema();
+        let copy =
+            EntityStore::from_entities(&store.as_entities(&schema).unwrap(), &schema).unwrap();
+
+        let list = copy.get_list(&groceries).unwrap();
+        assert_eq!(list.owner(), &andrew);
+        assert_eq!(list.metadata().name, "Groceries");
+        assert_eq!(list.tasks().len(), 1);
+        assert_eq!(list.tasks()[0].name(), "milk");
+        assert_eq!(list.metadata().task_schema, None);
+        assert!(!list.is_template());
+        assert!(copy.get_user(&andrew).unwrap().list_order().is_empty());
+    }
+
+    #[test]
+    fn cedar_entity_json_round_trips() {
+        let schema = schema();
+        let store: EntityStore = serde_json::from_str(include_str!("../entities.json")).unwrap();
+        let json = store.export_entities_json(&schema).unwrap();
+        let copy = EntityStore::import_entities_json(&json, &schema).unwrap();
+        assert_eq!(copy.export_entities_json(&schema).unwrap(), json);
+
+        let kesha: UserUid = r#"User::"kesha""#.parse().unwrap();
+        let temp: TeamUid = r#"Team::"temp""#.parse().unwrap();
+        assert!(copy.get_user(&kesha).unwrap().has_parent(&temp));
+        assert!(matches!(
+            EntityStore::import_entities_json("{}", &schema),
+            Err(EntityDecodeError::EntityJson(_))
+        ));
+    }
+
+    #[test]
+    fn lists_belong_to_their_owners_application() {
+        let mut store = EntityStore::default();
+        let acme: EntityUid = r#"Application::"Acme""#.parse().unwrap();
+        store.insert_app(Application::new(acme.clone()));
+        let andrew = user(&mut store, "andrew");
+        let zoe: User = serde_json::from_value(serde_json::json!({
+            "euid": r#"User::"zoe""#,
+            "joblevel": 5,
+            "location": "XYZ1",
+            "parents": [acme.to_string()],
+        }))
+        .unwrap();
+        let zoe = {
+            let uid = zoe.uid().clone();
+            store.insert_user(zoe);
+            uid
+        };
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let chores = list(&mut store, &zoe, "Chores");
+
+        let tiny_todo = Application::default().euid().clone();
+        let in_app = |app: &EntityUid| {
+            store
+                .lists_in_app(app)
+                .map(|list| list.uid().clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(in_app(&tiny_todo), [groceries]);
+        assert_eq!(in_app(&acme), [chores]);
+        assert!(store.get_app(&acme).is_ok());
+        let missing: EntityUid = r#"Application::"Other""#.parse().unwrap();
+        assert!(matches!(
+            store.get_app(&missing),
+            Err(Error::NoSuchEntity(_))
+        ));
+    }
+
+    #[test]
+    fn bootstrap_checks_the_schema_declares_the_store() {
+        let store = EntityStore::bootstrap(&schema()).unwrap();
+        assert_eq!(store.euids().count(), 1);
+        assert!(store
+            .get_app(&Application::default().euid().clone())
+            .is_ok());
+
+        let no_lists = r#"
+            entity Application;
+            entity Team in [Team, Application];
+            entity User in [Team, Application];
+            action GetLists appliesTo { principal: [User], resource: [Application] };
+        "#;
+        let (no_lists, _) = Schema::from_str_natural(no_lists).unwrap();
+        assert!(matches!(
+            EntityStore::bootstrap(&no_lists),
+            Err(EntityDecodeError::UndeclaredType(ty)) if ty.to_string() == "List"
+        ));
+        let no_actions = r#"
+            entity Application;
+            entity Team in [Team, Application];
+            entity User in [Team, Application];
+            entity List in [Application];
+        "#;
+        let (no_actions, _) = Schema::from_str_natural(no_actions).unwrap();
+        assert!(matches!(
+            EntityStore::bootstrap(&no_actions),
+            Err(EntityDecodeError::UndeclaredAction(_))
+        ));
+    }
+
+    #[test]
+    fn get_many_reports_each_missing_euid() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let missing: EntityUid = r#"List::"99""#.parse().unwrap();
+
+        let found = store.get_many(&[
+            andrew.clone().into(),
+            missing.clone(),
+            groceries.clone().into(),
+        ]);
+        assert_eq!(found.len(), 3);
+        assert!(matches!(&found[0], Ok(EntityRef::User(u)) if u.uid() == &andrew));
+        assert!(matches!(&found[1], Err(Error::NoSuchEntity(e)) if e == &missing));
+        assert!(matches!(&found[2], Ok(EntityRef::List(l)) if l.uid() == &groceries));
+    }
+
+    #[test]
+    fn extra_entities_take_part_in_the_bundle_and_deletion() {
+        let src = format!("{}\nentity Project in [Team];\n", schema_src());
+        let (schema, _) = Schema::from_str_natural(&src).unwrap();
+        let json = r#"[{
+            "uid": {"type": "Project", "id": "apollo"},
+            "attrs": {},
+            "parents": [{"type": "Team", "id": "interns"}]
+        }]"#;
+        let mut store = EntityStore::default();
+        store.insert_team(Team::new(r#"Team::"interns""#.parse().unwrap()));
+        store.register_extra(JsonEntities::from_json_str(json, &schema).unwrap());
+
+        let apollo: EntityUid = r#"Project::"apollo""#.parse().unwrap();
+        assert!(store.euids().any(|euid| euid == &apollo));
+        assert!(store.contains(&apollo));
+        assert!(store.as_entities(&schema).unwrap().get(&apollo).is_some());
+
+        store.delete_entity(&apollo).unwrap();
+        assert!(!store.contains(&apollo));
+        assert!(store.as_entities(&schema).unwrap().get(&apollo).is_none());
+        assert!(matches!(
+            store.delete_entity(&apollo),
+            Err(Error::NoSuchEntity(e)) if e == apollo
+        ));
+    }
+
+    #[test]
+    fn extra_entities_may_not_be_of_a_built_in_type() {
+        let json = r#"[{"uid": {"type": "Team", "id": "interns"}, "attrs": {}, "parents": []}]"#;
+        assert!(matches!(
+            JsonEntities::from_json_str(json, &schema()),
+            Err(EntityDecodeError::BuiltInType(_))
+        ));
+    }
+
+    #[test]
+    fn find_by_attribute_matches_a_string_across_lists() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let kesha = user(&mut store, "kesha");
+        let first = list(&mut store, &andrew, "urgent");
+        let second = list(&mut store, &kesha, "urgent");
+        list(&mut store, &andrew, "someday");
+
+        let mut found = store.find_by_attribute(EntityType::List, "name", &"urgent".into());
+        found.sort();
+        let mut expected: Vec<EntityUid> = vec![first.into(), second.into()];
+        expected.sort();
+        assert_eq!(found, expected);
+        assert!(store
+            .find_by_attribute(EntityType::List, "name", &"never".into())
+            .is_empty());
+        assert!(store
+            .find_by_attribute(EntityType::User, "name", &"urgent".into())
+            .is_empty());
+    }
+
+    #[test]
+    fn concurrent_renames_conflict() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let read = store.version(groceries.as_ref());
+
+        let first = store
+            .update_list_name(&groceries, "Food".to_string(), "andrew", Some(&read))
+            .unwrap();
+        let conflict =
+            store.update_list_name(&groceries, "Shops".to_string(), "emina", Some(&read));
+        assert!(matches!(
+            conflict,
+            Err(Error::WriteConflict { base, current, .. }) if base == read && current == first
+        ));
+        assert_eq!(store.get_list(&groceries).unwrap().metadata().name, "Food");
+
+        let mut merged = read.clone();
+        merged.merge(&first);
+        let second = store
+            .update_list_name(&groceries, "Shops".to_string(), "emina", Some(&merged))
+            .unwrap();
+        assert!(second.descends_from(&first));
+        assert_eq!(store.get_list(&groceries).unwrap().metadata().name, "Shops");
+        let unconditional = store
+            .update_list_name(&groceries, "Errands".to_string(), "andrew", None)
+            .unwrap();
+        assert!(unconditional.descends_from(&second));
+    }
+
+    #[test]
+    fn archived_lists_are_hidden_until_restored() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let counter = store.uid;
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let euid: &EntityUid = groceries.as_ref();
+
+        store.archive_entity(euid).unwrap();
+        assert!(!store.euids().any(|e| e == euid));
+        assert!(store.as_entities(&schema()).unwrap().get(euid).is_none());
+        assert!(store.archived().any(|e| e == euid));
+        store.uid = counter;
+        let fresh: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        assert_ne!(fresh, groceries);
+
+        store.restore_entity(euid).unwrap();
+        assert_eq!(store.get_list(&groceries).unwrap().name(), "Groceries");
+        assert!(store.as_entities(&schema()).unwrap().get(euid).is_some());
+        assert_eq!(store.archived().count(), 0);
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    #[test]
+    fn validate_references_reports_a_team_deleted_out_from_under_a_list() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        assert!(store.validate_references().is_empty());
+
+        let readers = store
+            .get_list(&groceries)
+            .unwrap()
+            .get_team(ShareRole::Reader)
+            .clone();
+        store.delete_entity(&readers).unwrap();
+        assert_eq!(
+            store.validate_references(),
+            [DanglingRef {
+                entity: groceries.into(),
+                field: "readers",
+                missing: readers.into(),
+            }]
+        );
+    }
+
+    fn nested_teams(store: &mut EntityStore, names: &[&str], parents: &[(usize, usize)]) {
+        let uid = |name: &str| -> TeamUid { format!(r#"Team::"{name}""#).parse().unwrap() };
+        let mut teams: Vec<Team> = names.iter().map(|name| Team::new(uid(name))).collect();
+        for &(member, parent) in parents {
+            teams[member].insert_parent(uid(names[parent]));
+        }
+        for team in teams {
+            store.insert_team(team);
+        }
+    }
+
+    #[test]
+    fn team_ancestors_follows_nesting_all_the_way_up() {
+        let mut store = EntityStore::default();
+        nested_teams(&mut store, &["a", "b", "c"], &[(0, 1), (1, 2)]);
+        let a: TeamUid = r#"Team::"a""#.parse().unwrap();
+        let mut ancestors = store.team_ancestors(&a).unwrap();
+        ancestors.sort();
+        let expected: Vec<TeamUid> = vec![
+            r#"Team::"b""#.parse().unwrap(),
+            r#"Team::"c""#.parse().unwrap(),
+        ];
+        assert_eq!(ancestors, expected);
+    }
+
+    #[test]
+    fn team_ancestors_terminates_on_a_cycle() {
+        let mut store = EntityStore::default();
+        nested_teams(&mut store, &["a", "b"], &[(0, 1), (1, 0)]);
+        let a: TeamUid = r#"Team::"a""#.parse().unwrap();
+        let mut ancestors = store.team_ancestors(&a).unwrap();
+        ancestors.sort();
+        let expected: Vec<TeamUid> = vec![
+            r#"Team::"a""#.parse().unwrap(),
+            r#"Team::"b""#.parse().unwrap(),
+        ];
+        assert_eq!(ancestors, expected);
+    }
+
+    #[test]
+    fn insert_many_inserts_a_mixed_batch_that_refers_to_itself() {
+        let mut store = EntityStore::default();
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+        let kesha: UserUid = r#"User::"kesha""#.parse().unwrap();
+        let mut user = User::new(kesha.clone(), 5, "ABC17".to_string());
+        user.insert_parent(interns.clone());
+        let groceries: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        let name = "Groceries".to_string();
+        let list = List::new(&mut store, groceries.clone(), kesha.clone(), name).unwrap();
+        let batch = vec![
+            StoreEntity::List(list),
+            StoreEntity::User(user),
+            StoreEntity::Team(Team::new(interns.clone())),
+        ];
+
+        let euids = store.insert_many(batch, &schema()).unwrap();
+        let expected: Vec<EntityUid> = vec![
+            groceries.clone().into(),
+            kesha.clone().into(),
+            interns.into(),
+        ];
+        assert_eq!(euids, expected);
+        assert_eq!(store.get_list(&groceries).unwrap().owner(), &kesha);
+        assert!(store.get_user(&kesha).is_ok());
+    }
+
+    #[test]
+    fn insert_many_rejects_the_whole_batch_on_a_collision() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let taken: EntityUid = andrew.clone().into();
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+        let batch = vec![
+            StoreEntity::Team(Team::new(interns.clone())),
+            StoreEntity::User(User::new(andrew, 5, "ABC17".to_string())),
+        ];
+
+        assert!(matches!(
+            store.insert_many(batch, &schema()),
+            Err(Error::AlreadyExists(e)) if e == taken
+        ));
+        assert!(store.get_team(&interns).is_err());
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    #[test]
+    fn stats_count_a_share_with_a_user_and_a_team_as_two() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let kesha = user(&mut store, "kesha");
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+        store.insert_team(Team::new(interns.clone()));
+        let groceries = list(&mut store, &andrew, "Groceries");
+        list(&mut store, &kesha, "Chores");
+
+        let list = store.get_list(&groceries).unwrap();
+        let readers = list.get_team(ShareRole::Reader).clone();
+        let editors = list.get_team(ShareRole::Editor).clone();
+        store.get_user_mut(&kesha).unwrap().insert_parent(readers);
+        store.get_team_mut(&interns).unwrap().insert_parent(editors);
+        let stats = store.stats();
+        assert_eq!((stats.users, stats.teams, stats.lists), (2, 1, 2));
+        assert_eq!(stats.shares, 2);
+        assert_eq!(stats.next_uid, store.uid);
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    #[test]
+    fn unshare_list_returns_the_role_it_removed() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let kesha = user(&mut store, "kesha");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let list = store.get_list(&groceries).unwrap();
+        let readers = list.get_team(ShareRole::Reader).clone();
+        let editors = list.get_team(ShareRole::Editor).clone();
+        let who: UserOrTeamUid = kesha.clone().into();
+
+        store
+            .get_user_mut(&kesha)
+            .unwrap()
+            .insert_parent(readers.clone());
+        let removed = store.unshare_list(&groceries, &who).unwrap();
+        assert!(matches!(removed, Some(ShareRole::Reader)));
+        assert!(!store.get_user(&kesha).unwrap().has_parent(&readers));
+
+        let kesha_mut = store.get_user_mut(&kesha).unwrap();
+        kesha_mut.insert_parent(readers.clone());
+        kesha_mut.insert_parent(editors.clone());
+        let removed = store.unshare_list(&groceries, &who).unwrap();
+        assert!(matches!(removed, Some(ShareRole::Editor)));
+        let kesha_ref = store.get_user(&kesha).unwrap();
+        assert!(!kesha_ref.has_parent(&readers) && !kesha_ref.has_parent(&editors));
+
+        assert!(store.unshare_list(&groceries, &who).unwrap().is_none());
+    }
+
+    // Hands out its ids in order, then runs out
+    #[derive(Debug)]
+    struct ScriptedIds(Vec<&'static str>);
+
+    impl IdSource for ScriptedIds {
+        fn next_id(&mut self, _ty: EntityType) -> Option<EntityId> {
+            (!self.0.is_empty()).then(|| self.0.remove(0).parse().unwrap())
+        }
+    }
+
+    #[test]
+    fn fresh_euid_takes_ids_from_the_id_source_verbatim() {
+        let source = ScriptedIds(vec!["groceries", "interns", "chores"]);
+        let mut store = EntityStore::default().with_id_source(source);
+        store.insert_team(Team::new(r#"Team::"interns""#.parse().unwrap()));
+
+        let first: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        assert_eq!(first.as_ref().id().as_ref(), "groceries");
+        let second: TeamUid = store.fresh_euid(EntityType::Team).unwrap();
+        assert_eq!(second.as_ref().id().as_ref(), "chores");
+        assert!(matches!(
+            store.fresh_euid::<ListUid>(EntityType::List),
+            Err(Error::IdSpaceExhausted)
+        ));
+    }
+
+    #[test]
+    fn get_or_create_team_finds_the_team_it_created() {
+        let mut store = EntityStore::default();
+        let (team, created) = store.get_or_create_team("engineering").unwrap();
+        assert!(created);
+        assert_eq!(team.name(), Some("engineering"));
+        let first = team.uid().clone();
+
+        let (team, created) = store.get_or_create_team("engineering").unwrap();
+        assert!(!created);
+        assert_eq!(team.uid(), &first);
+        let (_, created) = store.get_or_create_team("design").unwrap();
+        assert!(created);
+
+        store.delete_entity(&first).unwrap();
+        let (team, created) = store.get_or_create_team("engineering").unwrap();
+        assert!(created);
+        assert_ne!(team.uid(), &first);
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    #[test]
+    fn offboard_user_hands_over_lists_and_revokes_teams_and_shares() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let kesha = user(&mut store, "kesha");
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+        store.insert_team(Team::new(interns.clone()));
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let chores = list(&mut store, &kesha, "Chores");
+        let readers = store
+            .get_list(&groceries)
+            .unwrap()
+            .get_team(ShareRole::Reader)
+            .clone();
+        let profile = store.get_user_mut(&kesha).unwrap();
+        profile.insert_parent(interns.clone());
+        profile.insert_parent(readers);
+
+        let report = store.offboard_user(&kesha, &andrew).unwrap();
+        assert_eq!(report.lists_transferred, [chores.clone()]);
+        assert_eq!(report.teams_left, [interns]);
+        assert_eq!(report.grants_revoked.len(), 1);
+        assert_eq!(report.grants_revoked[0].list, groceries);
+        assert_eq!(store.get_list(&chores).unwrap().owner(), &andrew);
+        assert!(store.get_user(&kesha).unwrap().parent_teams().is_empty());
+    }
+
+    #[test]
+    fn entities_to_json_gives_the_same_text_for_the_same_entities() {
+        let mut store = EntityStore::default();
+        user(&mut store, "emina");
+        user(&mut store, "andrew");
+
+        let entities = store.as_entities(&schema()).unwrap();
+        let expected = r#"[
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "Administer",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "CreateList",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "CreateTask",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "DeleteList",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "DeleteTask",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "EditShare",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "GetList",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "GetLists",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "TinyTodo",
+      "type": "Application"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "TransferList",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "UpdateList",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {},
+    "parents": [],
+    "uid": {
+      "id": "UpdateTask",
+      "type": "Action"
+    }
+  },
+  {
+    "attrs": {
+      "joblevel": 5,
+      "location": "ABC17"
+    },
+    "parents": [
+      {
+        "id": "TinyTodo",
+        "type": "Application"
+      }
+    ],
+    "uid": {
+      "id": "andrew",
+      "type": "User"
+    }
+  },
+  {
+    "attrs": {
+      "joblevel": 5,
+      "location": "ABC17"
+    },
+    "parents": [
+      {
+        "id": "TinyTodo",
+        "type": "Application"
+      }
+    ],
+    "uid": {
+      "id": "emina",
+      "type": "User"
+    }
+  }
+]"#;
+        assert_eq!(entities_to_json(&entities).unwrap(), expected);
+    }
+
+    #[test]
+    fn as_entities_accepts_a_well_formed_store() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+
+        let schema = schema();
+        assert!(store.validate(&schema).is_ok());
+        let entities = store.as_entities(&schema).unwrap();
+        let (andrew, groceries): (EntityUid, EntityUid) = (andrew.into(), groceries.into());
+        assert!(entities.get(&andrew).is_some());
+        assert!(entities.get(&groceries).is_some());
+    }
+
+    #[test]
+    fn as_entities_names_a_list_without_the_owner_the_schema_requires() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries: EntityUid = list(&mut store, &andrew, "Groceries").into();
+        // To a schema that calls the owner something else, the list has none
+        let src = schema_src().replace(r#""owner""#, r#""creator""#);
+        let schema = Schema::from_str_natural(&src).unwrap().0;
+
+        let Err(EntityDecodeError::Nonconforming(euid, _)) = store.as_entities(&schema) else {
+            panic!("the list should not conform");
+        };
+        assert_eq!(euid, groceries);
+        assert!(matches!(
+            store.validate(&schema),
+            Err(EntityDecodeError::Nonconforming(euid, _)) if euid == groceries
+        ));
+    }
+
+    #[cfg(feature = "undo")]
+    #[test]
+    fn undoing_an_archive_brings_the_entity_back_out_of_the_archive() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let euid: EntityUid = list(&mut store, &andrew, "Groceries").into();
+
+        store.archive_entity(&euid).unwrap();
+        store.undo().unwrap();
+        assert!(store.get(&euid).is_ok());
+        assert_eq!(store.archived().count(), 0);
+    }
+
+    #[cfg(feature = "undo")]
+    #[test]
+    fn undoing_a_restore_puts_the_entity_back_in_the_archive() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let euid: EntityUid = list(&mut store, &andrew, "Groceries").into();
+        store.archive_entity(&euid).unwrap();
+
+        store.restore_entity(&euid).unwrap();
+        store.undo().unwrap();
+        assert!(store.get(&euid).is_err());
+        assert_eq!(store.archived().collect::<Vec<_>>(), [&euid]);
+        store.restore_entity(&euid).unwrap();
+        assert!(store.get(&euid).is_ok());
+    }
+
+    #[test]
+    fn batch_applies_every_op_when_all_succeed() {
+        let mut store = EntityStore::default();
+        let andrew: UserUid = r#"User::"andrew""#.parse().unwrap();
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+
+        store
+            .batch(vec![
+                StoreOp::InsertUser(User::new(andrew.clone(), 5, "ABC17".to_string())),
+                StoreOp::InsertTeam(Team::new(interns.clone())),
+                StoreOp::AddMember {
+                    member: andrew.clone().into(),
+                    team: interns.clone(),
+                },
+            ])
+            .unwrap();
+        assert!(store.get_user(&andrew).unwrap().has_parent(&interns));
+    }
+
+    #[test]
+    fn batch_rolls_back_every_op_when_one_fails() {
+        let mut store = EntityStore::default();
+        let emina = user(&mut store, "emina");
+        let andrew: UserUid = r#"User::"andrew""#.parse().unwrap();
+        let interns: TeamUid = r#"Team::"interns""#.parse().unwrap();
+        let before = serde_json::to_value(&store).unwrap();
+
+        let failed = store.batch(vec![
+            StoreOp::InsertUser(User::new(andrew.clone(), 5, "ABC17".to_string())),
+            StoreOp::Delete(emina.clone().into()),
+            StoreOp::AddMember {
+                member: andrew.clone().into(),
+                team: interns,
+            },
+        ]);
+        assert!(matches!(failed, Err(Error::BatchFailed { index: 2, .. })));
+        assert_eq!(serde_json::to_value(&store).unwrap(), before);
+        assert!(store.get_user(&andrew).is_err());
+        assert!(store.get_user(&emina).is_ok());
+    }
+
+    #[test]
+    fn batch_leaves_extra_entities_alone() {
+        let src = format!("{}\nentity Project;\n", schema_src());
+        let (schema, _) = Schema::from_str_natural(&src).unwrap();
+        let json = r#"[{"uid": {"type": "Project", "id": "apollo"}, "attrs": {}, "parents": []}]"#;
+        let mut store = EntityStore::default();
+        store.register_extra(JsonEntities::from_json_str(json, &schema).unwrap());
+        let apollo: EntityUid = r#"Project::"apollo""#.parse().unwrap();
+
+        let failed = store.batch(vec![StoreOp::Delete(apollo.clone())]);
+        assert!(matches!(failed, Err(Error::BatchFailed { index: 0, .. })));
+        assert!(store.contains(&apollo));
+    }
+
+    #[test]
+    fn with_transaction_gives_back_the_ids_a_failed_closure_took() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let before = serde_json::to_value(&store).unwrap();
+
+        let mut taken = None;
+        let failed = store.with_transaction(|store| {
+            let uid: ListUid = store.fresh_euid(EntityType::List)?;
+            taken = Some(uid.clone());
+            let list = List::new(store, uid.clone(), andrew.clone(), "Groceries".to_string())?;
+            store.insert_list(list)?;
+            Err::<(), _>(Error::no_such_entity(uid))
+        });
+        assert!(failed.is_err());
+        assert_eq!(serde_json::to_value(&store).unwrap(), before);
+        assert_eq!(store.lists().count(), 0);
+        let next: ListUid = store.fresh_euid(EntityType::List).unwrap();
+        assert_eq!(Some(next), taken);
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    #[test]
+    fn delete_entity_cascade_leaves_no_list_referring_to_a_deleted_team() {
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let groceries = list(&mut store, &andrew, "Groceries");
+        let chores = list(&mut store, &andrew, "Chores");
+        let interns = store.create_team_with_members(vec![]).unwrap();
+        for (list, role) in [
+            (&groceries, ShareRole::Reader),
+            (&chores, ShareRole::Editor),
+        ] {
+            let share = store.get_list(list).unwrap().get_team(role).clone();
+            store.get_team_mut(&interns).unwrap().insert_parent(share);
+        }
+
+        store.delete_entity_cascade(&interns).unwrap();
+        assert!(store.get_team(&interns).is_err());
+        assert!(store.referrers(interns.as_ref()).is_empty());
+        // A team backing a share is replaced rather than left dangling
+        let readers = store
+            .get_list(&groceries)
+            .unwrap()
+            .get_team(ShareRole::Reader)
+            .clone();
+        store.delete_entity_cascade(&readers).unwrap();
+        let replacement = store
+            .get_list(&groceries)
+            .unwrap()
+            .get_team(ShareRole::Reader);
+        assert_ne!(replacement, &readers);
+        assert!(store.get_team(replacement).is_ok());
+        assert!(store.validate_references().is_empty());
+        assert!(store.as_entities(&schema()).is_ok());
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shared_store_readers_never_see_a_half_done_update() {
+        assert_send_sync::<SharedEntityStore>();
+        let mut store = EntityStore::default();
+        let andrew = user(&mut store, "andrew");
+        let shared = SharedEntityStore::new(store);
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let andrew = andrew.clone();
+                std::thread::spawn(move || {
+                    let mut seen = 0;
+                    for _ in 0..200 {
+                        let store = shared.read();
+                        // Each update adds a user and a team together
+                        assert_eq!(store.users.len(), store.teams.len() + 1);
+                        assert!(store.users.len() >= seen);
+                        seen = store.users.len();
+                        drop(store);
+                        assert!(shared.get_user(&andrew).is_ok());
+                    }
+                })
+            })
+            .collect();
+        let writer = {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    shared
+                        .update(|store| {
+                            user(store, &format!("user{i}"));
+                            store.create_team_with_members(vec![])?;
+                            Ok(())
+                        })
+                        .unwrap();
+                }
+            })
+        };
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let store = shared.read();
+        assert_eq!(store.users.len(), 51);
+        assert_eq!(store.teams.len(), 50);
+    }
+
+    #[test]
+    fn a_saved_store_reloads_with_the_next_fresh_id() {
+        let mut store = EntityStore::default();
+        user(&mut store, "andrew");
+        for _ in 0..=5 {
+            store.create_team_with_members(vec![]).unwrap();
+        }
+        assert_eq!(store.uid, 6);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let mut reloaded: EntityStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.teams.len(), 6);
+        let next: TeamUid = reloaded.fresh_euid(EntityType::Team).unwrap();
+        assert_eq!(next.as_ref().id().as_ref(), "6");
+    }
+
+    #[test]
+    fn repair_uid_counter_moves_past_the_largest_id() {
+        let mut store = EntityStore::default();
+        for id in [3, 50, 17] {
+            store.insert_team(Team::new(format!(r#"Team::"{id}""#).parse().unwrap()));
+        }
+        store.uid = 10;
+
+        assert_eq!(store.repair_uid_counter(), 51);
+        let next: TeamUid = store.fresh_euid(EntityType::Team).unwrap();
+        assert_eq!(next.as_ref().id().as_ref(), "51");
+    }
 }
@@ -14,14 +14,28 @@
  * limitations under the License.
  */
 
+mod activity;
 mod api;
+mod authcache;
+#[cfg(feature = "tower")]
+mod authlayer;
 mod context;
+mod decisionlog;
 mod entitystore;
 mod objects;
 mod policy_store;
+mod presence;
+mod ratelimit;
+mod schemagen;
+#[cfg(feature = "sealed-fields")]
+mod sealing;
+mod slicing;
+mod tokens;
 mod util;
+mod versionvector;
+mod webhooks;
 
-use context::AppContext;
+use context::{AppConfig, AppContext};
 use std::num::ParseIntError;
 use thiserror::Error;
 use tracing::error;
@@ -38,10 +52,14 @@ async fn main() {
     } else {
         ("./tinytodo.cedarschema", "./policies.cedar")
     };
-    let app = match AppContext::spawn("./entities.json", schema_path, policies_path) {
+    let entities_path =
+        std::env::var("TINYTODO_ENTITIES").unwrap_or_else(|_| "./entities.json".to_string());
+    let app = match AppConfig::from_env()
+        .and_then(|config| AppContext::spawn(entities_path, schema_path, policies_path, config))
+    {
         Ok(app) => app,
         Err(e) => {
-            error!("Failed to load entities, policies, or schema: {e}");
+            error!("Failed to load configuration, entities, policies, or schema: {e}");
             std::process::exit(1);
         }
     };
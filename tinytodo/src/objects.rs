@@ -14,19 +14,23 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 use cedar_policy::{Entity, EvalResult, RestrictedExpression};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
 
 use crate::{
-    context::APPLICATION_TINY_TODO,
+    api::ShareRole,
+    context::{Error, APPLICATION_TINY_TODO},
     entitystore::{EntityDecodeError, EntityStore},
-    util::{EntityUid, ListUid, TeamUid, UserUid},
+    util::{EntityTypeError, EntityUid, ListUid, TeamUid, UserUid, TYPE_APPLICATION},
 };
 
 #[cfg(not(feature = "use-templates"))]
-use crate::{api::ShareRole, util::TYPE_TEAM};
+use crate::entitystore::EntityType;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Application {
@@ -34,9 +38,20 @@ pub struct Application {
 }
 
 impl Application {
+    pub fn new(euid: EntityUid) -> Self {
+        Self { euid }
+    }
+
     pub fn euid(&self) -> &EntityUid {
         &self.euid
     }
+
+    /// The Cedar type of each attribute in `attrs`, for `schemagen`
+    pub const SCHEMA_ATTRS: &'static [(&'static str, &'static str)] = &[];
+
+    pub fn attrs(&self) -> HashMap<String, RestrictedExpression> {
+        HashMap::new()
+    }
 }
 
 impl Default for Application {
@@ -56,6 +71,11 @@ impl From<Application> for Entity {
 pub trait UserOrTeam {
     fn insert_parent(&mut self, parent: TeamUid);
     fn delete_parent(&mut self, parent: &TeamUid);
+    fn has_parent(&self, parent: &TeamUid) -> bool;
+    /// The teams this is a direct member of
+    fn parent_teams(&self) -> Vec<TeamUid>;
+    /// The applications this belongs to directly
+    fn parent_apps(&self) -> Vec<EntityUid>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +84,12 @@ pub struct User {
     joblevel: i64,
     location: String,
     parents: HashSet<EntityUid>,
+    // The order the user wants their lists in; not part of the user's Cedar attributes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    list_order: Vec<ListUid>,
+    // How many lists the user may own, if limited; not part of the user's Cedar attributes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    list_quota: Option<usize>,
 }
 
 impl User {
@@ -78,20 +104,64 @@ impl User {
             joblevel,
             location,
             parents: [parent].into_iter().collect(),
+            list_order: vec![],
+            list_quota: None,
         }
     }
-}
 
-impl From<User> for Entity {
-    fn from(value: User) -> Entity {
-        let attrs = [
-            ("joblevel", RestrictedExpression::new_long(value.joblevel)),
-            ("location", RestrictedExpression::new_string(value.location)),
+    /// How many lists the user may own, not counting templates. `None` is unlimited.
+    pub fn list_quota(&self) -> Option<usize> {
+        self.list_quota
+    }
+
+    pub fn list_order(&self) -> &[ListUid] {
+        &self.list_order
+    }
+
+    /// Replaces the user's list order, rejecting an order that names a list twice
+    pub fn set_list_order(&mut self, order: Vec<ListUid>) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        if let Some(dup) = order.iter().find(|list| !seen.insert(*list)) {
+            return Err(Error::DuplicateInOrder(dup.clone().into()));
+        }
+        self.list_order = order;
+        Ok(())
+    }
+
+    /// Sorts `lists` into this user's order. Lists the order doesn't mention come after
+    /// those it does, sorted by euid.
+    pub fn sort_lists(&self, lists: &mut [EntityUid]) {
+        let position = |euid: &EntityUid| {
+            self.list_order
+                .iter()
+                .position(|list| list.as_ref() == euid)
+                .unwrap_or(usize::MAX)
+        };
+        lists.sort_by_cached_key(|euid| (position(euid), euid.to_string()));
+    }
+
+    /// The Cedar type of each attribute in `attrs`, for `schemagen`
+    pub const SCHEMA_ATTRS: &'static [(&'static str, &'static str)] =
+        &[("joblevel", "Long"), ("location", "String")];
+
+    /// The attributes this user carries when handed to Cedar
+    pub fn attrs(&self) -> HashMap<String, RestrictedExpression> {
+        [
+            ("joblevel", RestrictedExpression::new_long(self.joblevel)),
+            (
+                "location",
+                RestrictedExpression::new_string(self.location.clone()),
+            ),
         ]
         .into_iter()
         .map(|(x, v)| (x.into(), v))
-        .collect();
+        .collect()
+    }
+}
 
+impl From<User> for Entity {
+    fn from(value: User) -> Entity {
+        let attrs = value.attrs();
         let euid: EntityUid = value.euid.into();
         Entity::new(
             euid.into(),
@@ -102,6 +172,21 @@ impl From<User> for Entity {
     }
 }
 
+impl TryFrom<&Entity> for User {
+    type Error = EntityDecodeError;
+
+    fn try_from(entity: &Entity) -> Result<Self, Self::Error> {
+        Ok(Self {
+            euid: decode_uid(entity)?,
+            joblevel: get_long(&get_attr(entity, "joblevel")?, "joblevel")?,
+            location: get_string(&get_attr(entity, "location")?, "location")?.clone(),
+            parents: direct_parents(entity),
+            list_order: vec![],
+            list_quota: None,
+        })
+    }
+}
+
 impl UserOrTeam for User {
     fn insert_parent(&mut self, parent: TeamUid) {
         self.parents.insert(parent.into());
@@ -110,12 +195,39 @@ impl UserOrTeam for User {
     fn delete_parent(&mut self, parent: &TeamUid) {
         self.parents.remove(parent.as_ref());
     }
+
+    fn has_parent(&self, parent: &TeamUid) -> bool {
+        self.parents.contains(parent.as_ref())
+    }
+
+    fn parent_teams(&self) -> Vec<TeamUid> {
+        self.parents
+            .iter()
+            .filter_map(|parent| TeamUid::try_from(parent.clone()).ok())
+            .collect()
+    }
+
+    fn parent_apps(&self) -> Vec<EntityUid> {
+        self.parents
+            .iter()
+            .filter(|parent| parent.type_name() == &*TYPE_APPLICATION)
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Team {
     uid: TeamUid,
     parents: HashSet<EntityUid>,
+    // How lists created by members are shared with the team when they ask for it;
+    // not part of the team's Cedar attributes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_share: Option<ShareRole>,
+    // The name of the external group this team mirrors, if any; not part of the team's Cedar
+    // attributes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 impl Team {
@@ -124,12 +236,36 @@ impl Team {
         Self {
             uid: euid,
             parents: [parent].into_iter().collect(),
+            default_share: None,
+            name: None,
+        }
+    }
+
+    pub fn with_name(self, name: impl Into<String>) -> Team {
+        Self {
+            name: Some(name.into()),
+            ..self
         }
     }
 
     pub fn uid(&self) -> &TeamUid {
         &self.uid
     }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn default_share(&self) -> Option<ShareRole> {
+        self.default_share
+    }
+
+    /// The Cedar type of each attribute in `attrs`, for `schemagen`
+    pub const SCHEMA_ATTRS: &'static [(&'static str, &'static str)] = &[];
+
+    pub fn attrs(&self) -> HashMap<String, RestrictedExpression> {
+        HashMap::new()
+    }
 }
 
 impl From<Team> for Entity {
@@ -142,6 +278,19 @@ impl From<Team> for Entity {
     }
 }
 
+impl TryFrom<&Entity> for Team {
+    type Error = EntityDecodeError;
+
+    fn try_from(entity: &Entity) -> Result<Self, Self::Error> {
+        Ok(Self {
+            uid: decode_uid(entity)?,
+            parents: direct_parents(entity),
+            default_share: None,
+            name: None,
+        })
+    }
+}
+
 impl UserOrTeam for Team {
     fn insert_parent(&mut self, parent: TeamUid) {
         self.parents.insert(parent.into());
@@ -150,6 +299,25 @@ impl UserOrTeam for Team {
     fn delete_parent(&mut self, parent: &TeamUid) {
         self.parents.remove(parent.as_ref());
     }
+
+    fn has_parent(&self, parent: &TeamUid) -> bool {
+        self.parents.contains(parent.as_ref())
+    }
+
+    fn parent_teams(&self) -> Vec<TeamUid> {
+        self.parents
+            .iter()
+            .filter_map(|parent| TeamUid::try_from(parent.clone()).ok())
+            .collect()
+    }
+
+    fn parent_apps(&self) -> Vec<EntityUid> {
+        self.parents
+            .iter()
+            .filter(|parent| parent.type_name() == &*TYPE_APPLICATION)
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -158,6 +326,12 @@ pub struct List {
     owner: UserUid,
     name: String,
     tasks: Vec<Task>, // Invariant, `tasks` must be sorted
+    // The typed custom fields tasks on this list may carry, if any
+    #[serde(default)]
+    task_schema: Option<TaskSchema>,
+    // Template lists are reusable skeletons, kept out of the normal listings
+    #[serde(default)]
+    is_template: bool,
     #[cfg(not(feature = "use-templates"))]
     readers: TeamUid,
     #[cfg(not(feature = "use-templates"))]
@@ -166,37 +340,54 @@ pub struct List {
 
 impl List {
     #![allow(unused_variables)]
-    pub fn new(store: &mut EntityStore, uid: ListUid, owner: UserUid, name: String) -> Self {
+    pub fn new(
+        store: &mut EntityStore,
+        uid: ListUid,
+        owner: UserUid,
+        name: String,
+    ) -> Result<Self, Error> {
         #[cfg(not(feature = "use-templates"))]
         {
-            let readers_uid = store.fresh_euid::<TeamUid>(TYPE_TEAM.clone()).unwrap();
+            let readers_uid = store.fresh_euid::<TeamUid>(EntityType::Team)?;
             let readers = Team::new(readers_uid.clone());
-            let writers_uid = store.fresh_euid::<TeamUid>(TYPE_TEAM.clone()).unwrap();
+            let writers_uid = store.fresh_euid::<TeamUid>(EntityType::Team)?;
             let writers = Team::new(writers_uid.clone());
             store.insert_team(readers);
             store.insert_team(writers);
-            Self {
+            Ok(Self {
                 uid,
                 owner,
                 name,
                 tasks: vec![],
+                task_schema: None,
+                is_template: false,
                 readers: readers_uid,
                 editors: writers_uid,
-            }
+            })
         }
         #[cfg(feature = "use-templates")]
-        Self {
+        Ok(Self {
             uid,
             owner,
             name,
             tasks: vec![],
-        }
+            task_schema: None,
+            is_template: false,
+        })
     }
 
     pub fn uid(&self) -> &ListUid {
         &self.uid
     }
 
+    pub fn owner(&self) -> &UserUid {
+        &self.owner
+    }
+
+    pub fn set_owner(&mut self, owner: UserUid) {
+        self.owner = owner;
+    }
+
     pub fn create_task(&mut self, description: String) -> i64 {
         let id = self.tasks.len() as i64;
         let task = Task::new(id, description);
@@ -204,10 +395,47 @@ impl List {
         id
     }
 
+    /// Creates a task carrying the custom `fields`, which must conform to this list's task schema
+    pub fn create_task_with_fields(
+        &mut self,
+        description: String,
+        fields: HashMap<String, Value>,
+    ) -> Result<i64, TaskFieldError> {
+        let schema = self.task_schema.as_ref().ok_or(TaskFieldError::NoSchema)?;
+        schema.validate(&fields)?;
+        let id = self.create_task(description);
+        self.tasks.last_mut().unwrap().fields = fields;
+        Ok(id)
+    }
+
+    pub fn set_task_schema(&mut self, schema: Option<TaskSchema>) {
+        self.task_schema = schema;
+    }
+
+    pub fn is_template(&self) -> bool {
+        self.is_template
+    }
+
+    pub fn set_template(&mut self, is_template: bool) {
+        self.is_template = is_template;
+    }
+
     pub fn get_task_mut(&mut self, id: i64) -> Option<&mut Task> {
         self.tasks.iter_mut().find(|task| task.id == id)
     }
 
+    /// Sets every task matching `pred` to `state`, returning how many tasks changed state
+    pub fn set_state_where(&mut self, pred: impl Fn(&Task) -> bool, state: TaskState) -> usize {
+        let mut changed = 0;
+        for task in self.tasks.iter_mut().filter(|task| pred(task)) {
+            if task.state != state {
+                task.state = state;
+                changed += 1;
+            }
+        }
+        changed
+    }
+
     pub fn delete_task(&mut self, id: i64) -> Option<()> {
         for (indx, task) in self.tasks.iter().enumerate() {
             if task.id == id {
@@ -222,6 +450,124 @@ impl List {
         self.name = name;
     }
 
+    /// Appends the tasks of `other` to this list, renumbering them to follow this list's tasks
+    pub fn append_tasks(&mut self, other: List) {
+        for mut task in other.tasks {
+            task.id = self.tasks.len() as i64;
+            self.tasks.push(task);
+        }
+    }
+
+    /// Everything about this list except its tasks
+    pub fn metadata(&self) -> ListMetadata<'_> {
+        ListMetadata {
+            owner: &self.owner,
+            name: &self.name,
+            task_schema: self.task_schema.as_ref(),
+            is_template: self.is_template,
+            #[cfg(not(feature = "use-templates"))]
+            readers: &self.readers,
+            #[cfg(not(feature = "use-templates"))]
+            editors: &self.editors,
+        }
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// Up to `limit` of the tasks after the one with id `after`, or from the first task if
+    /// `after` is `None`, along with the cursor for the next page if any tasks are left.
+    /// Cursors are task ids, so a page isn't thrown off by tasks added or deleted before it.
+    pub fn tasks_page(&self, after: Option<i64>, limit: usize) -> (&[Task], Option<i64>) {
+        let start = after.map_or(0, |id| self.tasks.partition_point(|task| task.id <= id));
+        let end = start.saturating_add(limit).min(self.tasks.len());
+        let page = &self.tasks[start..end];
+        let next = if end < self.tasks.len() {
+            page.last().map(|task| task.id).or(after)
+        } else {
+            None
+        };
+        (page, next)
+    }
+
+    /// The metadata fields that differ between this list and `other`
+    pub fn metadata_diff(&self, other: &List) -> Vec<MetadataField> {
+        let (a, b) = (self.metadata(), other.metadata());
+        let mut changed = vec![];
+        if a.owner != b.owner {
+            changed.push(MetadataField::Owner);
+        }
+        if a.name != b.name {
+            changed.push(MetadataField::Name);
+        }
+        if a.task_schema != b.task_schema {
+            changed.push(MetadataField::TaskSchema);
+        }
+        if a.is_template != b.is_template {
+            changed.push(MetadataField::IsTemplate);
+        }
+        #[cfg(not(feature = "use-templates"))]
+        if a.readers != b.readers {
+            changed.push(MetadataField::Readers);
+        }
+        #[cfg(not(feature = "use-templates"))]
+        if a.editors != b.editors {
+            changed.push(MetadataField::Editors);
+        }
+        changed
+    }
+
+    /// How the tasks of `other` differ from this list's, matching tasks up by id
+    pub fn tasks_diff(&self, other: &List) -> TasksDiff {
+        let mut diff = TasksDiff::default();
+        for task in &self.tasks {
+            match other.tasks.iter().find(|t| t.id == task.id) {
+                None => diff.removed.push(task.id),
+                Some(t) if t != task => diff.changed.push(task.id),
+                Some(_) => (),
+            }
+        }
+        for task in &other.tasks {
+            if !self.tasks.iter().any(|t| t.id == task.id) {
+                diff.added.push(task.id);
+            }
+        }
+        diff
+    }
+
+    /// A hash of this list's name and tasks, the same for any two lists with the same content
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        // Task fields are a `HashMap`, so they're hashed as a JSON value, whose maps are sorted
+        serde_json::to_value(&self.tasks)
+            .unwrap()
+            .to_string()
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// A lightweight view of this list, without its tasks
+    pub fn summary(&self) -> ListSummary {
+        let done = self
+            .tasks
+            .iter()
+            .filter(|task| task.state == TaskState::Checked)
+            .count();
+        ListSummary {
+            uid: self.uid.clone(),
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            task_count: self.tasks.len(),
+            completion: if self.tasks.is_empty() {
+                0.0
+            } else {
+                done as f64 / self.tasks.len() as f64
+            },
+        }
+    }
+
     #[cfg(not(feature = "use-templates"))]
     pub fn get_team(&self, role: ShareRole) -> &TeamUid {
         match role {
@@ -229,34 +575,114 @@ impl List {
             ShareRole::Editor => &self.editors,
         }
     }
-}
 
-impl From<List> for Entity {
-    fn from(value: List) -> Self {
-        let attrs = [
-            (
-                "owner",
-                format!("{}", value.owner.as_ref()).parse().unwrap(),
-            ),
-            ("name", RestrictedExpression::new_string(value.name)),
+    /// Makes `team` the one whose members have `role` on this list
+    #[cfg(not(feature = "use-templates"))]
+    pub fn set_team(&mut self, role: ShareRole, team: TeamUid) {
+        match role {
+            ShareRole::Reader => self.readers = team,
+            ShareRole::Editor => self.editors = team,
+        }
+    }
+
+    /// The Cedar type of each attribute in `attrs`, for `schemagen`
+    #[cfg(not(feature = "use-templates"))]
+    pub const SCHEMA_ATTRS: &'static [(&'static str, &'static str)] = &[
+        ("owner", "User"),
+        ("name", "String"),
+        ("tasks", "Tasks"),
+        ("readers", "Team"),
+        ("editors", "Team"),
+    ];
+    #[cfg(feature = "use-templates")]
+    pub const SCHEMA_ATTRS: &'static [(&'static str, &'static str)] =
+        &[("owner", "User"), ("name", "String"), ("tasks", "Tasks")];
+
+    /// The attributes this list carries when handed to Cedar
+    pub fn attrs(&self) -> HashMap<String, RestrictedExpression> {
+        [
+            ("owner", format!("{}", self.owner.as_ref()).parse().unwrap()),
+            ("name", RestrictedExpression::new_string(self.name.clone())),
             (
                 "tasks",
-                RestrictedExpression::new_set(value.tasks.into_iter().map(|t| t.into())),
+                RestrictedExpression::new_set(self.tasks.iter().cloned().map(|t| t.into())),
             ),
             #[cfg(not(feature = "use-templates"))]
             (
                 "readers",
-                format!("{}", value.readers.as_ref()).parse().unwrap(),
+                format!("{}", self.readers.as_ref()).parse().unwrap(),
             ),
             #[cfg(not(feature = "use-templates"))]
             (
                 "editors",
-                format!("{}", value.editors.as_ref()).parse().unwrap(),
+                format!("{}", self.editors.as_ref()).parse().unwrap(),
             ),
         ]
         .into_iter()
         .map(|(x, v)| (x.into(), v))
-        .collect();
+        .collect()
+    }
+}
+
+/// A borrowed view of a list's metadata, see `List::metadata`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListMetadata<'a> {
+    pub owner: &'a UserUid,
+    pub name: &'a str,
+    pub task_schema: Option<&'a TaskSchema>,
+    pub is_template: bool,
+    #[cfg(not(feature = "use-templates"))]
+    pub readers: &'a TeamUid,
+    #[cfg(not(feature = "use-templates"))]
+    pub editors: &'a TeamUid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MetadataField {
+    Owner,
+    Name,
+    TaskSchema,
+    IsTemplate,
+    #[cfg(not(feature = "use-templates"))]
+    Readers,
+    #[cfg(not(feature = "use-templates"))]
+    Editors,
+}
+
+/// The ids of the tasks added, removed, and changed between two versions of a list
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TasksDiff {
+    pub added: Vec<i64>,
+    pub removed: Vec<i64>,
+    pub changed: Vec<i64>,
+}
+
+impl TasksDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A page of a list's tasks, see `List::tasks_page`
+#[derive(Debug, Clone, Serialize)]
+pub struct TasksPage {
+    pub tasks: Vec<Task>,
+    pub next: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListSummary {
+    pub uid: ListUid,
+    pub name: String,
+    pub owner: UserUid,
+    pub task_count: usize,
+    // Fraction of the tasks that are checked, `0.0` for a list with no tasks
+    pub completion: f64,
+}
+
+impl From<List> for Entity {
+    fn from(value: List) -> Self {
+        let attrs = value.attrs();
 
         // We always have the single parent of the application, so we just hard code that here
         let parents = [APPLICATION_TINY_TODO.clone().into()]
@@ -268,11 +694,45 @@ impl From<List> for Entity {
     }
 }
 
+/// The task schema and template flag are not part of a list's Cedar attributes, so a decoded
+/// list has no task schema and isn't a template
+impl TryFrom<&Entity> for List {
+    type Error = EntityDecodeError;
+
+    fn try_from(entity: &Entity) -> Result<Self, Self::Error> {
+        let tasks = match get_attr(entity, "tasks")? {
+            EvalResult::Set(set) => {
+                let mut tasks = set
+                    .iter()
+                    .map(Task::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                tasks.sort();
+                tasks
+            }
+            _ => return Err(EntityDecodeError::WrongType("tasks", "Set")),
+        };
+        Ok(Self {
+            uid: decode_uid(entity)?,
+            owner: get_euid(&get_attr(entity, "owner")?, "owner")?,
+            name: get_string(&get_attr(entity, "name")?, "name")?.clone(),
+            tasks,
+            task_schema: None,
+            is_template: false,
+            #[cfg(not(feature = "use-templates"))]
+            readers: get_euid(&get_attr(entity, "readers")?, "readers")?,
+            #[cfg(not(feature = "use-templates"))]
+            editors: get_euid(&get_attr(entity, "editors")?, "editors")?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Task {
     id: i64,
     name: String,
     state: TaskState,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    fields: HashMap<String, Value>,
 }
 
 impl Task {
@@ -281,6 +741,7 @@ impl Task {
             id,
             name,
             state: TaskState::Unchecked,
+            fields: HashMap::new(),
         }
     }
 
@@ -291,6 +752,14 @@ impl Task {
     pub fn set_state(&mut self, new: TaskState) {
         self.state = new;
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> TaskState {
+        self.state
+    }
 }
 
 impl PartialOrd for Task {
@@ -312,6 +781,7 @@ impl TryFrom<&EvalResult> for Task {
         let id_field = "id";
         let name_field = "name";
         let state_field = "state";
+        let fields_field = "fields";
         match value {
             EvalResult::Record(rcd) => {
                 let id = get_long(
@@ -329,7 +799,20 @@ impl TryFrom<&EvalResult> for Task {
                     .get(state_field)
                     .ok_or(EntityDecodeError::MissingAttr(state_field))?
                     .try_into()?;
-                Ok(Self { id, name, state })
+                let fields = match rcd.get(fields_field) {
+                    Some(EvalResult::Set(set)) => set
+                        .iter()
+                        .map(decode_task_field)
+                        .collect::<Result<_, _>>()?,
+                    Some(_) => return Err(EntityDecodeError::WrongType(fields_field, "Set")),
+                    None => HashMap::new(),
+                };
+                Ok(Self {
+                    id,
+                    name,
+                    state,
+                    fields,
+                })
             }
             _ => Err(EntityDecodeError::WrongType("task", "record")),
         }
@@ -338,6 +821,17 @@ impl TryFrom<&EvalResult> for Task {
 
 impl From<Task> for RestrictedExpression {
     fn from(value: Task) -> Self {
+        let custom_fields = (!value.fields.is_empty()).then(|| {
+            (
+                "fields",
+                RestrictedExpression::new_set(
+                    value
+                        .fields
+                        .into_iter()
+                        .filter_map(|(key, v)| encode_task_field(key, v)),
+                ),
+            )
+        });
         let fields = [
             ("id", RestrictedExpression::new_long(value.id)),
             ("name", RestrictedExpression::new_string(value.name)),
@@ -347,11 +841,138 @@ impl From<Task> for RestrictedExpression {
             ),
         ]
         .into_iter()
+        .chain(custom_fields)
         .map(|(x, v)| (x.to_string(), v));
         RestrictedExpression::new_record(fields).expect("no duplicate keys!")
     }
 }
 
+// Custom task fields are handed to Cedar as a set of `TaskField` records (see the schema),
+// each holding the field's name under `key` and its value under the slot for its type.
+// Values that aren't a Long, String, or Bool can't have passed validation, so they are skipped.
+fn encode_task_field(key: String, value: Value) -> Option<RestrictedExpression> {
+    let (slot, v) = match value {
+        Value::Number(n) => ("long", RestrictedExpression::new_long(n.as_i64()?)),
+        Value::String(s) => ("string", RestrictedExpression::new_string(s)),
+        Value::Bool(b) => ("bool", RestrictedExpression::new_bool(b)),
+        _ => return None,
+    };
+    let fields = [
+        ("key".to_string(), RestrictedExpression::new_string(key)),
+        (slot.to_string(), v),
+    ];
+    Some(RestrictedExpression::new_record(fields).expect("no duplicate keys!"))
+}
+
+fn decode_task_field(e: &EvalResult) -> Result<(String, Value), EntityDecodeError> {
+    let key_field = "key";
+    match e {
+        EvalResult::Record(rcd) => {
+            let key = get_string(
+                rcd.get(key_field)
+                    .ok_or(EntityDecodeError::MissingAttr(key_field))?,
+                key_field,
+            )?
+            .clone();
+            let value = match (rcd.get("long"), rcd.get("string"), rcd.get("bool")) {
+                (Some(v), None, None) => Value::from(get_long(v, "long")?),
+                (None, Some(v), None) => Value::from(get_string(v, "string")?.clone()),
+                (None, None, Some(EvalResult::Bool(b))) => Value::from(*b),
+                (None, None, Some(_)) => return Err(EntityDecodeError::WrongType("bool", "Bool")),
+                _ => return Err(EntityDecodeError::MissingAttr("value")),
+            };
+            Ok((key, value))
+        }
+        _ => Err(EntityDecodeError::WrongType("field", "record")),
+    }
+}
+
+/// The type a custom task field must have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskFieldType {
+    Long,
+    String,
+    Bool,
+}
+
+impl TaskFieldType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            TaskFieldType::Long => value.as_i64().is_some(),
+            TaskFieldType::String => value.is_string(),
+            TaskFieldType::Bool => value.is_boolean(),
+        }
+    }
+}
+
+/// The custom fields tasks on a list may carry. Every field is optional,
+/// but fields not declared here are rejected.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TaskSchema(HashMap<String, TaskFieldType>);
+
+impl TaskSchema {
+    pub fn validate(&self, fields: &HashMap<String, Value>) -> Result<(), TaskFieldError> {
+        for (key, value) in fields {
+            let ty = self
+                .0
+                .get(key)
+                .ok_or_else(|| TaskFieldError::Undeclared(key.clone()))?;
+            if !ty.matches(value) {
+                return Err(TaskFieldError::WrongType {
+                    field: key.clone(),
+                    expected: *ty,
+                    got: value.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum TaskFieldError {
+    #[error("This list does not have a task schema, so tasks can't carry custom fields")]
+    NoSchema,
+    #[error("The task schema does not declare a field named {0}")]
+    Undeclared(String),
+    #[error("Task field {field} should be a {expected:?}, got: {got}")]
+    WrongType {
+        field: String,
+        expected: TaskFieldType,
+        got: Value,
+    },
+}
+
+fn get_attr(entity: &Entity, name: &'static str) -> Result<EvalResult, EntityDecodeError> {
+    entity
+        .attr(name)
+        .ok_or(EntityDecodeError::MissingAttr(name))?
+        .map_err(|e| EntityDecodeError::BadAttr(name, e.to_string()))
+}
+
+fn decode_uid<T: TryFrom<EntityUid, Error = EntityTypeError>>(
+    entity: &Entity,
+) -> Result<T, EntityDecodeError> {
+    Ok(EntityUid::from(entity.uid()).try_into()?)
+}
+
+// The parents `entity` was constructed with, as opposed to all of its ancestors
+fn direct_parents(entity: &Entity) -> HashSet<EntityUid> {
+    let (_, _, parents) = entity.clone().into_inner();
+    parents.into_iter().map(EntityUid::from).collect()
+}
+
+fn get_euid<T: TryFrom<EntityUid, Error = EntityTypeError>>(
+    e: &EvalResult,
+    name: &'static str,
+) -> Result<T, EntityDecodeError> {
+    match e {
+        EvalResult::EntityUid(euid) => Ok(EntityUid::from(euid.clone()).try_into()?),
+        _ => Err(EntityDecodeError::WrongType(name, "Entity")),
+    }
+}
+
 fn get_long(e: &EvalResult, name: &'static str) -> Result<i64, EntityDecodeError> {
     match e {
         EvalResult::Long(l) => Ok(*l),
@@ -398,3 +1019,55 @@ impl TryFrom<&EvalResult> for TaskState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groceries() -> List {
+        let mut store = EntityStore::default();
+        let owner: UserUid = r#"User::"andrew""#.parse().unwrap();
+        let uid: ListUid = r#"List::"100""#.parse().unwrap();
+        let mut list = List::new(&mut store, uid, owner, "Groceries".to_string()).unwrap();
+        for task in ["milk", "eggs", "bread"] {
+            list.create_task(task.to_string());
+        }
+        list
+    }
+
+    #[test]
+    fn lists_differing_only_in_metadata_have_no_task_diff() {
+        let before = groceries();
+        let mut after = before.clone();
+        after.update_name("Shopping".to_string());
+        after.set_template(true);
+
+        assert_eq!(
+            before.metadata_diff(&after),
+            vec![MetadataField::Name, MetadataField::IsTemplate]
+        );
+        assert!(before.tasks_diff(&after).is_empty());
+    }
+
+    #[test]
+    fn lists_differing_only_in_tasks_have_no_metadata_diff() {
+        let before = groceries();
+        let mut after = before.clone();
+        let added = after.create_task("butter".to_string());
+        after.delete_task(0).unwrap();
+        after
+            .get_task_mut(1)
+            .unwrap()
+            .set_name("oat milk".to_string());
+
+        assert!(before.metadata_diff(&after).is_empty());
+        assert_eq!(
+            before.tasks_diff(&after),
+            TasksDiff {
+                added: vec![added],
+                removed: vec![0],
+                changed: vec![1],
+            }
+        );
+    }
+}
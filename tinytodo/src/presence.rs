@@ -0,0 +1,61 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::util::{ListUid, UserUid};
+
+/// Who has recently been looking at each list. This is ephemeral: it lives only in the
+/// server's memory and is never written out with the entities.
+#[derive(Debug, Default)]
+pub struct Presence {
+    lists: HashMap<ListUid, HashMap<UserUid, Instant>>,
+}
+
+impl Presence {
+    /// Records that `user` was viewing `list` at `now`
+    pub fn touch(&mut self, list: ListUid, user: UserUid, now: Instant) {
+        self.lists.entry(list).or_default().insert(user, now);
+    }
+
+    /// The users seen on `list` within `ttl` of `now`.
+    /// Stale entries are only filtered out here; they're dropped the next time `prune` runs.
+    pub fn present_users(&self, list: &ListUid, ttl: Duration, now: Instant) -> Vec<UserUid> {
+        self.lists
+            .get(list)
+            .into_iter()
+            .flatten()
+            .filter(|(_, seen)| now.saturating_duration_since(**seen) <= ttl)
+            .map(|(user, _)| user.clone())
+            .collect()
+    }
+
+    /// Drops every entry older than `ttl`, so users who stop polling don't accumulate
+    pub fn prune(&mut self, ttl: Duration, now: Instant) {
+        for users in self.lists.values_mut() {
+            users.retain(|_, seen| now.saturating_duration_since(*seen) <= ttl);
+        }
+        self.lists.retain(|_, users| !users.is_empty());
+    }
+
+    /// Drops all presence on `list`, e.g. once it has been deleted
+    pub fn forget(&mut self, list: &ListUid) {
+        self.lists.remove(list);
+    }
+}
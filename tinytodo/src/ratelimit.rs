@@ -0,0 +1,141 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::util::EntityUid;
+
+// The fewest buckets the limiter holds before it first prunes them
+const MIN_PRUNE_AT: usize = 1024;
+
+/// How many requests of an action a principal may burst, and how quickly they earn more
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket for each (principal, action) pair.
+/// Buckets start full and are created on first use. A bucket that has refilled is the same as
+/// one that was never created, so full buckets are pruned whenever the number of buckets doubles,
+/// keeping it to about twice the number of principals that were recently limited.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: HashMap<(EntityUid, EntityUid), Bucket>,
+    prune_at: usize,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+            prune_at: MIN_PRUNE_AT,
+        }
+    }
+
+    /// Takes a token from the bucket for `principal` performing `action`, returning
+    /// whether there was one to take
+    pub fn try_acquire(&mut self, principal: &EntityUid, action: &EntityUid, now: Instant) -> bool {
+        if self.buckets.len() >= self.prune_at {
+            self.prune(now);
+            self.prune_at = (self.buckets.len() * 2).max(MIN_PRUNE_AT);
+        }
+        let limit = self.limit;
+        let bucket = self
+            .buckets
+            .entry((principal.clone(), action.clone()))
+            .or_insert(Bucket {
+                tokens: limit.capacity,
+                last_refill: now,
+            });
+        let elapsed: Duration = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * limit.refill_per_sec).min(limit.capacity);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops every bucket that has refilled by `now`
+    pub fn prune(&mut self, now: Instant) {
+        let limit = self.limit;
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_refill);
+            bucket.tokens + elapsed.as_secs_f64() * limit.refill_per_sec < limit.capacity
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn euid(s: &str) -> EntityUid {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn try_acquire_refills_over_time() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            capacity: 2.0,
+            refill_per_sec: 1.0,
+        });
+        let (user, action) = (euid(r#"User::"kesha""#), euid(r#"Action::"CreateList""#));
+        let start = Instant::now();
+        assert!(limiter.try_acquire(&user, &action, start));
+        assert!(limiter.try_acquire(&user, &action, start));
+        assert!(!limiter.try_acquire(&user, &action, start));
+        assert!(limiter.try_acquire(&user, &action, start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn prune_drops_only_refilled_buckets() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            capacity: 2.0,
+            refill_per_sec: 1.0,
+        });
+        let action = euid(r#"Action::"CreateList""#);
+        let start = Instant::now();
+        let busy = euid(r#"User::"busy""#);
+        assert!(limiter.try_acquire(&busy, &action, start));
+        assert!(limiter.try_acquire(&busy, &action, start));
+        for i in 1..MIN_PRUNE_AT {
+            limiter.try_acquire(&euid(&format!(r#"User::"{i}""#)), &action, start);
+        }
+
+        // Creating the next bucket prunes the others, which have refilled by then, but not `busy`'s
+        let later = start + Duration::from_secs(1);
+        limiter.try_acquire(&euid(r#"User::"new""#), &action, later);
+        assert_eq!(limiter.buckets.len(), 2);
+        assert!(limiter.try_acquire(&busy, &action, later));
+        assert!(!limiter.try_acquire(&busy, &action, later));
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::Write;
+
+use cedar_policy::SchemaFragment;
+
+use crate::{
+    context::ACTIONS,
+    objects::{Application, List, Team, User},
+};
+
+// The record types task attributes are built from, see `Task`'s conversion to a Cedar value,
+// and the context every action's requests may carry, see `ContextBuilder`
+const COMMON_TYPES: &str = r#"type TaskField = {
+    "key": String,
+    "long"?: Long,
+    "string"?: String,
+    "bool"?: Bool,
+};
+
+type Task = {
+    "id": Long,
+    "name": String,
+    "state": String,
+    "fields"?: Set<TaskField>,
+};
+
+type Tasks = Set<Task>;
+
+type RequestContext = {
+    "mfa"?: Bool,
+};
+"#;
+
+// Each entity type, the types its entities may be members of, and its attributes
+const ENTITY_TYPES: [(&str, &[&str], &[(&str, &str)]); 4] = [
+    ("List", &["Application"], List::SCHEMA_ATTRS),
+    ("Application", &[], Application::SCHEMA_ATTRS),
+    ("User", &["Team", "Application"], User::SCHEMA_ATTRS),
+    ("Team", &["Team", "Application"], Team::SCHEMA_ATTRS),
+];
+
+// The resource type each action in `ACTIONS` applies to; every action's principal is a `User`
+fn resource_type(action: &str) -> &'static str {
+    match action {
+        "CreateList" | "GetLists" | "Administer" => "Application",
+        _ => "List",
+    }
+}
+
+/// The schema the entities `EntityStore::as_entities` builds and the actions in `ACTIONS`
+/// conform to, in Cedar's human-readable syntax. The attributes come from each object's
+/// `SCHEMA_ATTRS`, which sits beside the `attrs` it describes, so the two change together.
+pub fn schema_source() -> String {
+    let mut src = String::from(COMMON_TYPES);
+    for (name, parents, attrs) in ENTITY_TYPES {
+        write!(src, "entity {name}").unwrap();
+        if !parents.is_empty() {
+            write!(src, " in [{}]", parents.join(", ")).unwrap();
+        }
+        if !attrs.is_empty() {
+            src.push_str(" = {\n");
+            for (attr, ty) in attrs {
+                writeln!(src, "  \"{attr}\": {ty},").unwrap();
+            }
+            src.push('}');
+        }
+        src.push_str(";\n");
+    }
+    src.push('\n');
+    for (action, _) in ACTIONS.iter() {
+        writeln!(
+            src,
+            "action {action} appliesTo {{\n  principal: [User],\n  resource: [{}],\n  context: RequestContext\n}};",
+            resource_type(action)
+        )
+        .unwrap();
+    }
+    src
+}
+
+/// Whether `src`, a schema in Cedar's human-readable syntax, declares the same types and
+/// actions as `schema_source`. They're compared in the JSON syntax, which doesn't keep comments,
+/// declaration order, or how actions are grouped.
+pub fn matches_schema_source(src: &str) -> bool {
+    let as_json = |src: &str| {
+        let (fragment, _) = SchemaFragment::from_str_natural(src).ok()?;
+        fragment.to_json_value().ok()
+    };
+    let generated = as_json(&schema_source()).expect("the generated schema parses");
+    as_json(src) == Some(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::load_policies, entitystore::EntityStore};
+    use cedar_policy::Schema;
+
+    #[cfg(not(feature = "use-templates"))]
+    const SCHEMA: &str = include_str!("../tinytodo.cedarschema");
+    #[cfg(feature = "use-templates")]
+    const SCHEMA: &str = include_str!("../tinytodo-templates.cedarschema");
+    #[cfg(not(feature = "use-templates"))]
+    const POLICIES: &str = include_str!("../policies.cedar");
+    #[cfg(feature = "use-templates")]
+    const POLICIES: &str = include_str!("../policies-templates.cedar");
+
+    #[test]
+    fn generated_schema_matches_the_hand_written_one() {
+        assert!(matches_schema_source(SCHEMA));
+        let without_tasks = SCHEMA.replace("\"tasks\": Tasks,", "");
+        assert!(!matches_schema_source(&without_tasks));
+    }
+
+    #[test]
+    fn generated_schema_accepts_the_example_policies_and_entities() {
+        let (schema, _) = Schema::from_str_natural(&schema_source()).unwrap();
+        load_policies(POLICIES, &schema).unwrap();
+        let store: EntityStore = serde_json::from_str(include_str!("../entities.json")).unwrap();
+        store.validate(&schema).unwrap();
+    }
+}
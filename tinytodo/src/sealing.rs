@@ -0,0 +1,209 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::str::FromStr;
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::{
+    entitystore::EntityStore,
+    util::{from_hex, to_hex},
+};
+
+// The fields encrypted in a sealed snapshot, as (section, type, field), where the type is what
+// an archived entity of the section is tagged with. Everything else stays plaintext, so a
+// sealed snapshot can still be searched by uid, membership, and so on.
+const SENSITIVE: &[(&str, &str, &str)] = &[("users", "User", "location")];
+
+// The key a sealed field's ciphertext is kept under, in place of its plaintext value
+const SEALED: &str = "sealed";
+
+const NONCE_LEN: usize = 12;
+
+/// A 256-bit key for sealing snapshot fields, written as 64 hex digits
+#[derive(Clone)]
+pub struct FieldKey([u8; 32]);
+
+impl std::fmt::Debug for FieldKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FieldKey(..)")
+    }
+}
+
+impl FromStr for FieldKey {
+    type Err = SealError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = from_hex(s.trim()).ok_or(SealError::BadKey)?;
+        Ok(Self(bytes.try_into().map_err(|_| SealError::BadKey)?))
+    }
+}
+
+impl FieldKey {
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SealError {
+    #[error("Field key must be 64 hex digits")]
+    BadKey,
+    #[error("Sealed field `{1}` of {0} is malformed")]
+    Malformed(String, &'static str),
+    #[error("Sealed field `{1}` of {0} could not be decrypted; the key is wrong or the field was altered")]
+    Decrypt(String, &'static str),
+    #[error("Error Deserializing Json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl EntityStore {
+    /// The store as it is saved, with each sensitive field encrypted under `key`.
+    /// Each ciphertext is bound to its entity and field, so it can't be moved to another.
+    pub fn to_sealed(&self, key: &FieldKey) -> Value {
+        let mut snapshot = serde_json::to_value(self).unwrap();
+        let cipher = key.cipher();
+        for_each_sensitive(&mut snapshot, |uid, field, value| {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let plaintext = value.to_string();
+            let aad = associated_data(uid, field);
+            let payload = Payload {
+                msg: plaintext.as_bytes(),
+                aad: aad.as_bytes(),
+            };
+            let mut sealed = nonce.to_vec();
+            sealed.extend(cipher.encrypt(&nonce, payload).unwrap());
+            *value = json!({ SEALED: to_hex(&sealed) });
+            Ok(())
+        })
+        .unwrap();
+        snapshot
+    }
+
+    /// The inverse of `to_sealed`. A sensitive field that isn't sealed is loaded as it is, so
+    /// a plaintext snapshot can be sealed by loading it and saving it again.
+    pub fn from_sealed(snapshot: Value, key: &FieldKey) -> Result<Self, SealError> {
+        Ok(serde_json::from_value(unseal(snapshot, key)?)?)
+    }
+}
+
+/// Decrypts each sealed field of `snapshot`, leaving the snapshot otherwise as it was
+pub fn unseal(mut snapshot: Value, key: &FieldKey) -> Result<Value, SealError> {
+    let cipher = key.cipher();
+    for_each_sensitive(&mut snapshot, |uid, field, value| {
+        let Some(sealed) = value.get(SEALED) else {
+            return Ok(());
+        };
+        let malformed = || SealError::Malformed(uid.to_string(), field);
+        let sealed = sealed.as_str().and_then(from_hex).ok_or_else(malformed)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(malformed());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = associated_data(uid, field);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: aad.as_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| SealError::Decrypt(uid.to_string(), field))?;
+        *value = serde_json::from_slice(&plaintext).map_err(|_| malformed())?;
+        Ok(())
+    })?;
+    Ok(snapshot)
+}
+
+// Calls `f` with the uid, field name, and value of every sensitive field in `snapshot`
+fn for_each_sensitive(
+    snapshot: &mut Value,
+    mut f: impl FnMut(&str, &'static str, &mut Value) -> Result<(), SealError>,
+) -> Result<(), SealError> {
+    for &(section, tag, field) in SENSITIVE {
+        if let Some(entities) = snapshot.get_mut(section).and_then(Value::as_object_mut) {
+            for (uid, entity) in entities {
+                if let Some(value) = entity.get_mut(field) {
+                    f(uid, field, value)?;
+                }
+            }
+        }
+        if let Some(archived) = snapshot.get_mut("archived").and_then(Value::as_object_mut) {
+            for (uid, entity) in archived {
+                if let Some(value) = entity.get_mut(tag).and_then(|e| e.get_mut(field)) {
+                    f(uid, field, value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn associated_data(uid: &str, field: &str) -> String {
+    format!("{uid}.{field}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> FieldKey {
+        to_hex(&[byte; 32]).parse().unwrap()
+    }
+
+    fn store() -> EntityStore {
+        serde_json::from_str(include_str!("../entities.json")).unwrap()
+    }
+
+    #[test]
+    fn sealed_snapshots_round_trip() {
+        let store = store();
+        let sealed = store.to_sealed(&key(1));
+        let kesha = &sealed["users"][r#"User::"kesha""#];
+        assert!(kesha["location"].get(SEALED).is_some());
+        assert_eq!(kesha["joblevel"], 5);
+
+        let unsealed = EntityStore::from_sealed(sealed, &key(1)).unwrap();
+        assert_eq!(
+            serde_json::to_value(unsealed).unwrap(),
+            serde_json::to_value(store).unwrap()
+        );
+    }
+
+    #[test]
+    fn unsealing_with_the_wrong_key_fails() {
+        let sealed = store().to_sealed(&key(1));
+        assert!(matches!(
+            EntityStore::from_sealed(sealed, &key(2)),
+            Err(SealError::Decrypt(_, "location"))
+        ));
+    }
+
+    #[test]
+    fn sealed_fields_cannot_be_moved_between_entities() {
+        let mut sealed = store().to_sealed(&key(1));
+        let users = &mut sealed["users"];
+        users[r#"User::"aaron""#]["location"] = users[r#"User::"kesha""#]["location"].clone();
+        assert!(matches!(
+            EntityStore::from_sealed(sealed, &key(1)),
+            Err(SealError::Decrypt(uid, "location")) if uid == r#"User::"aaron""#
+        ));
+    }
+}
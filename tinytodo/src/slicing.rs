@@ -0,0 +1,98 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use cedar_policy::{ActionConstraint, Entities, EntityTypeName, PolicySet, ResourceConstraint};
+
+use crate::util::EntityUid;
+
+/// The policies in `policies` that could apply to a request for `action` on a resource of type
+/// `resource_type`, judged by their scopes alone. `entities` supplies the action groups `action`
+/// is in, for `action in [...]` scopes. A policy is dropped only if its scope rules the request
+/// out, so the slice decides every such request the same way the full set does.
+pub fn relevant_policies(
+    action: &EntityUid,
+    resource_type: &EntityTypeName,
+    policies: &PolicySet,
+    entities: &Entities,
+) -> PolicySet {
+    let actions: HashSet<&cedar_policy::EntityUid> = std::iter::once(&**action)
+        .chain(entities.ancestors(action).into_iter().flatten())
+        .collect();
+    let mut slice = PolicySet::new();
+    // Templates are never evaluated themselves, but their links need them present
+    for t in policies.templates() {
+        slice
+            .add_template(t.clone())
+            .expect("template ids are unique in the set being sliced");
+    }
+    for p in policies.policies() {
+        let action_matches = match p.action_constraint() {
+            ActionConstraint::Any => true,
+            ActionConstraint::Eq(a) => actions.contains(&a),
+            ActionConstraint::In(groups) => groups.iter().any(|g| actions.contains(g)),
+        };
+        let resource_matches = match p.resource_constraint() {
+            ResourceConstraint::Eq(r) => r.type_name() == resource_type,
+            ResourceConstraint::Is(t) | ResourceConstraint::IsIn(t, _) => &t == resource_type,
+            // `resource in e` can hold for resources of types other than `e`'s
+            ResourceConstraint::Any | ResourceConstraint::In(_) => true,
+        };
+        if !(action_matches && resource_matches) {
+            continue;
+        }
+        let added = match (p.template_id(), p.template_links()) {
+            (Some(tid), Some(vals)) => slice.link(tid.clone(), p.id().clone(), vals),
+            _ => slice.add(p.clone()),
+        };
+        added.expect("policy ids are unique in the set being sliced");
+    }
+    slice
+}
+
+/// `relevant_policies` for each (action, resource type) seen so far. A slice is only as fresh
+/// as the policies it was taken from, so the owner must `clear` this whenever they change.
+#[derive(Debug, Default)]
+pub struct PolicySlices(Mutex<HashMap<(EntityUid, EntityTypeName), Arc<PolicySet>>>);
+
+impl PolicySlices {
+    pub fn get(
+        &self,
+        action: &EntityUid,
+        resource_type: &EntityTypeName,
+        policies: &PolicySet,
+        entities: &Entities,
+    ) -> Arc<PolicySet> {
+        let mut slices = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        slices
+            .entry((action.clone(), resource_type.clone()))
+            .or_insert_with(|| {
+                Arc::new(relevant_policies(action, resource_type, policies, entities))
+            })
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
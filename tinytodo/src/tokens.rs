@@ -0,0 +1,91 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    context::Error,
+    util::{ListUid, UserUid},
+};
+
+/// What a list-scoped token lets its bearer do to its list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenCapability {
+    /// Read the list and its tasks
+    Read,
+    /// Add tasks to the list
+    Append,
+}
+
+/// A token that acts for the user who issued it, but only on one list, only in the ways its
+/// capabilities allow, and only until it expires. The issuer must still be authorized for
+/// each use, so revoking their access to the list revokes the token's too.
+#[derive(Debug, Clone)]
+pub struct ScopedToken {
+    pub issuer: UserUid,
+    pub list: ListUid,
+    pub capabilities: HashSet<TokenCapability>,
+    pub expires: Instant,
+}
+
+/// The list-scoped tokens issued so far, by secret. Like presence, these live only in the
+/// server's memory, so every token is revoked when it restarts.
+#[derive(Debug, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<String, ScopedToken>,
+}
+
+impl TokenRegistry {
+    /// Stores `token`, returning the secret its bearer presents. Expired tokens are dropped.
+    pub fn issue(&mut self, token: ScopedToken, now: Instant) -> String {
+        self.tokens.retain(|_, t| t.expires > now);
+        let secret = Uuid::new_v4().simple().to_string();
+        self.tokens.insert(secret.clone(), token);
+        secret
+    }
+
+    /// The token whose secret is `secret`, failing with `Error::InvalidToken` if there is none
+    /// or it has expired, and with `Error::TokenNotAllowed` if it lacks `capability`
+    pub fn check(
+        &self,
+        secret: &str,
+        capability: TokenCapability,
+        now: Instant,
+    ) -> Result<&ScopedToken, Error> {
+        let token = self
+            .tokens
+            .get(secret)
+            .filter(|t| t.expires > now)
+            .ok_or(Error::InvalidToken)?;
+        if token.capabilities.contains(&capability) {
+            Ok(token)
+        } else {
+            Err(Error::TokenNotAllowed(capability))
+        }
+    }
+
+    /// Revokes every token on `list`, e.g. once it has been deleted
+    pub fn forget(&mut self, list: &ListUid) {
+        self.tokens.retain(|_, t| &t.list != list);
+    }
+}
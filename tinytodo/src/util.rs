@@ -103,6 +103,7 @@ lazy_static! {
     pub static ref TYPE_LIST: EntityTypeName = "List".parse().unwrap();
     pub static ref TYPE_USER: EntityTypeName = "User".parse().unwrap();
     pub static ref TYPE_TEAM: EntityTypeName = "Team".parse().unwrap();
+    pub static ref TYPE_APPLICATION: EntityTypeName = "Application".parse().unwrap();
 }
 
 // Here we defined a bunch of typed wrappers around `EntityUid`.
@@ -158,7 +159,7 @@ impl std::fmt::Display for EntityTypeError {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(try_from = "EntityUid")]
 #[serde(into = "EntityUid")]
 #[repr(transparent)]
@@ -183,7 +184,15 @@ impl AsRef<EntityUid> for UserUid {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+impl FromStr for UserUid {
+    type Err = UidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<EntityUid>()?.try_into()?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(try_from = "EntityUid")]
 #[serde(into = "EntityUid")]
 #[repr(transparent)]
@@ -208,7 +217,15 @@ impl AsRef<EntityUid> for ListUid {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+impl FromStr for ListUid {
+    type Err = UidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<EntityUid>()?.try_into()?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(try_from = "EntityUid")]
 #[serde(into = "EntityUid")]
 #[repr(transparent)]
@@ -237,6 +254,14 @@ impl AsRef<EntityUid> for UserOrTeamUid {
     }
 }
 
+impl FromStr for UserOrTeamUid {
+    type Err = UidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<EntityUid>()?.try_into()?)
+    }
+}
+
 impl From<UserUid> for UserOrTeamUid {
     fn from(value: UserUid) -> Self {
         Self(value.0)
@@ -255,7 +280,7 @@ impl From<UserOrTeamUid> for EntityUid {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(try_from = "EntityUid")]
 #[serde(into = "EntityUid")]
 #[repr(transparent)]
@@ -274,12 +299,29 @@ impl AsRef<EntityUid> for TeamUid {
     }
 }
 
+impl FromStr for TeamUid {
+    type Err = UidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<EntityUid>()?.try_into()?)
+    }
+}
+
 impl From<TeamUid> for EntityUid {
     fn from(value: TeamUid) -> Self {
         value.0
     }
 }
 
+/// Why a string isn't a uid of the type it was parsed as
+#[derive(Debug, Error)]
+pub enum UidParseError {
+    #[error("{0}")]
+    Parse(#[from] ParseErrors),
+    #[error("{0}")]
+    Type(#[from] EntityTypeError),
+}
+
 fn entity_type_check<T>(
     expected: &'static EntityTypeName,
     got: EntityUid,
@@ -307,6 +349,19 @@ impl AsRef<EntityUid> for EntityUid {
     }
 }
 
+// Ordered by their Cedar syntax, which is distinct for distinct uids
+impl PartialOrd for EntityUid {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EntityUid {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.to_string().cmp(&other.0.to_string())
+    }
+}
+
 impl FromStr for EntityUid {
     type Err = ParseErrors;
 
@@ -322,6 +377,46 @@ impl std::fmt::Display for EntityUid {
     }
 }
 
+/// A SHA-256 digest, such as `EntityStore::authz_fingerprint`, written as 64 hex digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Fingerprint(
+    #[serde(serialize_with = "serialize_digest")]
+    #[serde(deserialize_with = "deserialize_digest")]
+    pub [u8; 32],
+);
+
+fn serialize_digest<S>(digest: &[u8; 32], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(&to_hex(digest))
+}
+
+fn deserialize_digest<'de, D>(d: D) -> Result<[u8; 32], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    from_hex(&s)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| serde::de::Error::custom("expected 64 hex digits"))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[repr(transparent)]
 #[serde(transparent)]
@@ -409,3 +504,43 @@ where
 
     d.deserialize_str(Visitor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashSet};
+
+    #[test]
+    fn parsing_checks_the_entity_type() {
+        assert!(r#"User::"andrew""#.parse::<UserUid>().is_ok());
+        assert!(r#"List::"0""#.parse::<ListUid>().is_ok());
+        assert!(r#"Team::"1""#.parse::<TeamUid>().is_ok());
+        assert!(r#"Team::"1""#.parse::<UserOrTeamUid>().is_ok());
+        assert!(matches!(
+            r#"Team::"1""#.parse::<UserUid>(),
+            Err(UidParseError::Type(_))
+        ));
+        assert!(matches!(
+            r#"List::"0""#.parse::<UserOrTeamUid>(),
+            Err(UidParseError::Type(_))
+        ));
+        assert!(matches!(
+            "not a uid".parse::<ListUid>(),
+            Err(UidParseError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn equal_uids_are_one_set_member() {
+        let a: EntityUid = r#"User::"andrew""#.parse().unwrap();
+        let b: EntityUid = r#"User::"andrew""#.parse().unwrap();
+        let c: EntityUid = r#"User::"emina""#.parse().unwrap();
+        assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+        assert!(a < c);
+
+        let hashed: HashSet<_> = [a.clone(), b.clone(), c.clone()].into_iter().collect();
+        assert_eq!(hashed.len(), 2);
+        let ordered: BTreeSet<_> = [c.clone(), b, a.clone()].into_iter().collect();
+        assert_eq!(ordered.into_iter().collect::<Vec<_>>(), vec![a, c]);
+    }
+}
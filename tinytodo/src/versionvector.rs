@@ -0,0 +1,48 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How many writes each writer has made to an entity. One vector descends from another if it
+/// has seen every write the other has; if neither descends from the other, the writes they
+/// record were concurrent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    /// Whether this has seen every write `other` has
+    pub fn descends_from(&self, other: &VersionVector) -> bool {
+        other
+            .0
+            .iter()
+            .all(|(writer, count)| self.0.get(writer).map_or(false, |c| c >= count))
+    }
+
+    /// Takes in every write `other` has seen, e.g. after resolving a conflict with it
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (writer, count) in &other.0 {
+            let c = self.0.entry(writer.clone()).or_default();
+            *c = (*c).max(*count);
+        }
+    }
+
+    /// Records one more write by `writer`
+    pub fn bump(&mut self, writer: &str) {
+        *self.0.entry(writer.to_string()).or_default() += 1;
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use hyper::{client::HttpConnector, Uri};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError, Receiver, Sender},
+    Semaphore,
+};
+use tracing::{debug, error};
+
+use crate::{
+    objects::{MetadataField, TasksDiff},
+    util::{ListUid, UserUid},
+};
+
+// How many deliveries may be waiting before new changes are dropped
+const QUEUE_CAPACITY: usize = 1024;
+// How many deliveries may be in flight at once
+const MAX_IN_FLIGHT: usize = 32;
+// How long a webhook has to respond before its delivery is abandoned
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// The JSON payload POSTed to a webhook when a list changes
+#[derive(Debug, Clone, Serialize)]
+pub struct ListChange {
+    pub list: ListUid,
+    pub change: ChangeKind,
+    pub by: UserUid,
+    /// The list's fields that changed, left out if none did or they aren't known
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<MetadataField>,
+    /// The ids of the tasks that changed, left out if none did or they aren't known
+    #[serde(skip_serializing_if = "TasksDiff::is_empty")]
+    pub tasks: TasksDiff,
+}
+
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub url: String,
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum WebhookError {
+    #[error("`{0}` is not a valid url")]
+    InvalidUrl(String),
+    #[error("Webhooks must use http, not `{0}`")]
+    Scheme(String),
+    #[error("Webhooks may not be sent to `{0}`")]
+    HostNotAllowed(String),
+}
+
+/// Webhook URLs to notify when a list changes, either for a single list or for every list.
+/// Notifying only queues the deliveries; they are sent by a background task,
+/// so a slow or failing webhook never holds up the request that made the change.
+#[derive(Debug)]
+pub struct WebhookRegistry {
+    global: Vec<String>,
+    per_list: HashMap<ListUid, Vec<String>>,
+    // The hosts per-list webhooks may be sent to. Global webhooks come from the operator, so
+    // they aren't checked; per-list ones come from users, and would otherwise let them make the
+    // server POST to anything it can reach.
+    allowed_hosts: Vec<String>,
+    queue: Sender<Delivery>,
+}
+
+impl WebhookRegistry {
+    /// Creates a registry whose deliveries are left on the returned queue,
+    /// for callers that want to send them themselves
+    pub fn with_queue(capacity: usize) -> (Self, Receiver<Delivery>) {
+        let (queue, recv) = mpsc::channel(capacity);
+        let registry = Self {
+            global: vec![],
+            per_list: HashMap::new(),
+            allowed_hosts: vec![],
+            queue,
+        };
+        (registry, recv)
+    }
+
+    /// Creates a registry and spawns the task that POSTs its deliveries
+    pub fn spawn() -> Self {
+        let (registry, recv) = Self::with_queue(QUEUE_CAPACITY);
+        tokio::spawn(deliver_all(recv));
+        registry
+    }
+
+    pub fn register_global(&mut self, url: String) {
+        self.global.push(url);
+    }
+
+    /// Lets per-list webhooks be sent to `host`, compared case-insensitively
+    pub fn allow_host(&mut self, host: String) {
+        self.allowed_hosts.push(host.to_ascii_lowercase());
+    }
+
+    /// Registers `url` on `list`, if it is an `http` url on an allowed host.
+    /// Registering the same url on a list again does nothing, so each change is delivered once.
+    pub fn register(&mut self, list: ListUid, url: String) -> Result<(), WebhookError> {
+        self.check_url(&url)?;
+        let urls = self.per_list.entry(list).or_default();
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+        Ok(())
+    }
+
+    // The client only speaks plain HTTP, so other schemes could never be delivered
+    fn check_url(&self, url: &str) -> Result<(), WebhookError> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|_| WebhookError::InvalidUrl(url.to_string()))?;
+        match uri.scheme_str() {
+            Some("http") => (),
+            Some(scheme) => return Err(WebhookError::Scheme(scheme.to_string())),
+            None => return Err(WebhookError::InvalidUrl(url.to_string())),
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| WebhookError::InvalidUrl(url.to_string()))?
+            .to_ascii_lowercase();
+        if self.allowed_hosts.contains(&host) {
+            Ok(())
+        } else {
+            Err(WebhookError::HostNotAllowed(host))
+        }
+    }
+
+    /// Drops the webhooks registered on `list`, e.g. once it has been deleted
+    pub fn unregister_all(&mut self, list: &ListUid) {
+        self.per_list.remove(list);
+    }
+
+    /// Queues a delivery of `change` to every webhook registered on its list, and every global webhook
+    pub fn notify(&self, change: ListChange) {
+        let urls = self
+            .per_list
+            .get(&change.list)
+            .into_iter()
+            .flatten()
+            .chain(self.global.iter());
+        let payload = serde_json::to_string(&change).unwrap();
+        for url in urls {
+            let delivery = Delivery {
+                url: url.clone(),
+                payload: payload.clone(),
+            };
+            match self.queue.try_send(delivery) {
+                Ok(()) => (),
+                Err(TrySendError::Full(d)) => {
+                    error!("Webhook queue full, dropping delivery to {}", d.url)
+                }
+                Err(TrySendError::Closed(d)) => {
+                    error!("Webhook queue closed, dropping delivery to {}", d.url)
+                }
+            }
+        }
+    }
+}
+
+// Sends each delivery on its own task, so one slow webhook doesn't hold up the others.
+// Waiting for a free slot before spawning keeps the backlog in the bounded queue.
+async fn deliver_all(mut recv: Receiver<Delivery>) {
+    let client = hyper::Client::new();
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    while let Some(delivery) = recv.recv().await {
+        let permit = in_flight.clone().acquire_owned().await.unwrap();
+        let client = client.clone();
+        tokio::spawn(async move {
+            deliver(&client, delivery).await;
+            drop(permit);
+        });
+    }
+}
+
+async fn deliver(client: &hyper::Client<HttpConnector>, delivery: Delivery) {
+    let request = hyper::Request::post(delivery.url.as_str())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(delivery.payload));
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            error!("Invalid webhook url {}: {e}", delivery.url);
+            return;
+        }
+    };
+    match tokio::time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+        Ok(Ok(resp)) => debug!("Webhook {} responded with {}", delivery.url, resp.status()),
+        Ok(Err(e)) => error!("Error delivering webhook to {}: {e}", delivery.url),
+        Err(_) => error!(
+            "Webhook {} didn't respond within {DELIVERY_TIMEOUT:?}",
+            delivery.url
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> WebhookRegistry {
+        let (mut registry, _) = WebhookRegistry::with_queue(1);
+        registry.allow_host("hooks.example.com".to_string());
+        registry
+    }
+
+    fn list() -> ListUid {
+        r#"List::"0""#.parse().unwrap()
+    }
+
+    #[test]
+    fn register_checks_scheme_and_host() {
+        let mut registry = registry();
+        assert!(registry
+            .register(list(), "http://HOOKS.example.com:8080/hook".to_string())
+            .is_ok());
+        assert!(matches!(
+            registry.register(list(), "https://hooks.example.com/hook".to_string()),
+            Err(WebhookError::Scheme(_))
+        ));
+        assert!(matches!(
+            registry.register(list(), "http://169.254.169.254/latest".to_string()),
+            Err(WebhookError::HostNotAllowed(_))
+        ));
+        assert!(matches!(
+            registry.register(list(), "/hook".to_string()),
+            Err(WebhookError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn register_ignores_repeats() {
+        let mut registry = registry();
+        let url = "http://hooks.example.com/hook".to_string();
+        registry.register(list(), url.clone()).unwrap();
+        registry.register(list(), url).unwrap();
+        assert_eq!(registry.per_list[&list()].len(), 1);
+    }
+}
@@ -2,8 +2,26 @@ from tinytodo import *
 import time
 import unittest
 import io
+import json
+import os
+import tempfile
+import threading
+from http.server import BaseHTTPRequestHandler, HTTPServer
 from contextlib import redirect_stdout
 
+# Collects the JSON payloads POSTed to it, standing in for a webhook receiver
+class WebhookSink(BaseHTTPRequestHandler):
+    payloads = []
+
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers['Content-Length']))
+        WebhookSink.payloads.append(json.loads(body))
+        self.send_response(200)
+        self.end_headers()
+
+    def log_message(self, format, *args):
+        pass
+
 # Tests are flakey if the delay after start and stop is too short, 0.1 seconds
 # seems to be fine. Tests will fail if there's an instance of the TinyTodo
 # server running already. E.g., if you start one in the repl and don't kill it
@@ -24,6 +42,15 @@ class TinyTodoTest(unittest.TestCase):
             f()
         self.assertIn(s, out.getvalue())
 
+    # List IDs depend on whether the server allocates reader/editor teams per list,
+    # so tests creating more than one list read the ID back from the output
+    def create_list_id(self, name, apply_team_defaults = False):
+        out = io.StringIO()
+        with redirect_stdout(out):
+            create_list(name, apply_team_defaults)
+        self.assertIn("Created list ID", out.getvalue())
+        return int(out.getvalue().split()[-1])
+
     def test_owner_get_list(self):
         self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
         self.assert_in_stdout("=== foo ===", lambda : get_list(0))
@@ -69,3 +96,803 @@ class TinyTodoTest(unittest.TestCase):
         set_user(emina)
         self.assert_in_stdout("Created task", lambda : create_task(0, "bar"))
         self.assert_in_stdout("1: [ ] bar", lambda : get_list(0))
+
+    def test_merge_lists(self):
+        into_id = self.create_list_id("foo")
+        from_id = self.create_list_id("bar")
+        self.assert_in_stdout("Created task", lambda : create_task(from_id, "baz"))
+        self.assert_in_stdout("Shared list ID %d with emina" % from_id, lambda : share_list(from_id, emina, True))
+        self.assert_in_stdout("Merged list ID %d into list ID %d" % (from_id, into_id), lambda : merge_lists(into_id, from_id))
+        self.assert_in_stdout("1: [ ] baz", lambda : get_list(into_id))
+        self.assert_in_stdout("Access denied", lambda : get_list(from_id))
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list(into_id))
+        self.assert_in_stdout("Access denied", lambda : merge_lists(into_id, from_id))
+
+    def test_list_summaries(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Created task", lambda : create_task(list_id, "bar"))
+        self.assert_in_stdout("Created task", lambda : create_task(list_id, "baz"))
+        self.assert_in_stdout("Toggled task", lambda : toggle_task(list_id, 1))
+        lst = get_list_data(andrew, List(list_id))
+        self.assert_in_stdout("%d: %s (%d tasks, 50%% done)" % (list_id, lst['name'], len(lst['tasks'])), lambda : get_list_summaries())
+        set_user(emina)
+        self.assert_in_stdout("No lists for emina", lambda : get_list_summaries())
+
+    def test_get_many_lists(self):
+        foo_id = self.create_list_id("foo")
+        bar_id = self.create_list_id("bar")
+        self.assert_in_stdout("%d: foo\n99: Authorization Denied\n%d: bar" % (foo_id, bar_id), lambda : get_many_lists([foo_id, 99, bar_id]))
+        set_user(emina)
+        self.assert_in_stdout("%d: Authorization Denied" % foo_id, lambda : get_many_lists([foo_id]))
+
+    def test_templates(self):
+        template_id = self.create_list_id("skeleton")
+        list_id = self.create_list_id("real")
+        self.assert_in_stdout("Marked list ID %d as a template" % template_id, lambda : set_template(template_id))
+        self.assert_in_stdout("Lists: %d\n" % list_id, lambda : get_lists())
+        self.assert_in_stdout("Lists: %d\n" % template_id, lambda : get_templates())
+        out = io.StringIO()
+        with redirect_stdout(out):
+            get_list_summaries()
+        self.assertNotIn("%d: skeleton" % template_id, out.getvalue())
+        self.assert_in_stdout("Unmarked list ID %d" % template_id, lambda : set_template(template_id, False))
+        self.assert_in_stdout("No lists for andrew", lambda : get_templates())
+
+    def test_duplicate_lists(self):
+        first_id = self.create_list_id("groceries")
+        second_id = self.create_list_id("groceries")
+        other_id = self.create_list_id("chores")
+        for list_id in [first_id, second_id, other_id]:
+            self.assert_in_stdout("Created task", lambda : create_task(list_id, "milk"))
+        self.assert_in_stdout("Duplicates: %d,%d\n" % (first_id, second_id), lambda : find_duplicate_lists())
+        self.assert_in_stdout("Created task", lambda : create_task(second_id, "eggs"))
+        self.assert_in_stdout("No duplicate lists", lambda : find_duplicate_lists())
+
+    def test_lists_by_owner(self):
+        first_id = self.create_list_id("foo")
+        second_id = self.create_list_id("bar")
+        set_user(emina)
+        third_id = self.create_list_id("baz")
+        self.assert_in_stdout('User::"emina": %d\n' % third_id, lambda : get_lists_by_owner())
+        set_user(andrew)
+        self.assert_in_stdout("Shared list ID %d with emina" % first_id, lambda : share_list(first_id, emina, True))
+        set_user(emina)
+        self.assert_in_stdout('User::"andrew": %d\nUser::"emina": %d\n' % (first_id, third_id), lambda : get_lists_by_owner())
+        set_user(andrew)
+        self.assert_in_stdout('User::"andrew": %d,%d\n' % (first_id, second_id), lambda : get_lists_by_owner())
+
+    def test_activity(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Created task", lambda : create_task(list_id, "bar"))
+        self.assert_in_stdout("Toggled task", lambda : toggle_task(list_id, 0))
+        self.assert_in_stdout("Shared list ID %d with emina" % list_id, lambda : share_list(list_id, emina, True))
+        self.assert_in_stdout("Shared by andrew\nTaskUpdated by andrew\nTaskAdded by andrew\nCreated by andrew\n", lambda : get_activity(list_id))
+        self.assert_in_stdout("Shared by andrew\nTaskUpdated by andrew\n", lambda : get_activity(list_id, 2))
+        set_user(emina)
+        self.assert_in_stdout("Shared by andrew", lambda : get_activity(list_id))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_activity(list_id))
+
+    def test_tasks_page(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("End of list", lambda : get_tasks_page(list_id))
+        for name in ["a", "b", "c", "d", "e"]:
+            self.assert_in_stdout("Created task", lambda : create_task(list_id, name))
+        self.assert_in_stdout("0: a\n1: b\nNext: 1\n", lambda : get_tasks_page(list_id, None, 2))
+        self.assert_in_stdout("2: c\n3: d\nNext: 3\n", lambda : get_tasks_page(list_id, 1, 2))
+        self.assert_in_stdout("4: e\nEnd of list\n", lambda : get_tasks_page(list_id, 3, 2))
+        self.assert_in_stdout("4: e\nEnd of list\n", lambda : get_tasks_page(list_id, 3, 100))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_tasks_page(list_id))
+
+    def test_presence(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Nobody on list ID %d" % list_id, lambda : get_presence(list_id))
+        self.assert_in_stdout("Touched presence on list ID %d" % list_id, lambda : touch_presence(list_id))
+        self.assert_in_stdout("Shared list ID %d with emina" % list_id, lambda : share_list(list_id, emina, True))
+        set_user(emina)
+        self.assert_in_stdout("Touched presence on list ID %d" % list_id, lambda : touch_presence(list_id))
+        self.assert_in_stdout("On list ID %d: andrew,emina" % list_id, lambda : get_presence(list_id))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : touch_presence(list_id))
+        self.assert_in_stdout("Access denied", lambda : get_presence(list_id))
+
+    def test_presence_expires(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_PRESENCE_TTL_SECS'] = '1'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_PRESENCE_TTL_SECS']
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Touched presence on list ID %d" % list_id, lambda : touch_presence(list_id))
+        self.assert_in_stdout("On list ID %d: andrew" % list_id, lambda : get_presence(list_id))
+        time.sleep(1.5)
+        self.assert_in_stdout("Nobody on list ID %d" % list_id, lambda : get_presence(list_id))
+
+    def test_generic_denial_message(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        body = json.loads(get_list_inner(emina, List(0)).text)
+        self.assertEqual("Authorization Denied", body['error'])
+
+    def test_detailed_denial_message(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_DENIAL_VERBOSITY'] = 'detailed'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DENIAL_VERBOSITY']
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        body = json.loads(get_list_inner(emina, List(0)).text)
+        self.assertEqual("Authorization Denied: no policy permitted the request", body['error'])
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(0))
+
+    def test_denied_for_missing_list(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Access denied", lambda : get_list(99))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(0))
+
+    def test_not_found_for_forbidden_list(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_NOT_FOUND_POLICY'] = 'not-found'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_NOT_FOUND_POLICY']
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout('Error: No Such Entity: List::"99"', lambda : get_list(99))
+        set_user(emina)
+        self.assert_in_stdout('Error: No Such Entity: List::"0"', lambda : get_list(0))
+
+    def test_distinct_missing_list(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_NOT_FOUND_POLICY'] = 'distinct'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_NOT_FOUND_POLICY']
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout('Error: Resource List::"99" does not exist', lambda : get_list(99))
+        self.assert_in_stdout('Error: Resource List::"99" does not exist', lambda : delete_list(99))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(0))
+
+    def test_rate_limit(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_RATE_LIMIT'] = '2/1'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_RATE_LIMIT']
+        self.assert_in_stdout("Created list ID", lambda : create_list("foo"))
+        self.assert_in_stdout("Created list ID", lambda : create_list("bar"))
+        self.assert_in_stdout("Error: Rate limited", lambda : create_list("baz"))
+        set_user(emina)
+        self.assert_in_stdout("Created list ID", lambda : create_list("baz"))
+        set_user(andrew)
+        time.sleep(1.1)
+        self.assert_in_stdout("Created list ID", lambda : create_list("baz"))
+
+    def test_auth_cache(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_AUTH_CACHE'] = '2'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_AUTH_CACHE']
+        list_id = self.create_list_id("foo")
+        other_id = self.create_list_id("bar")
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        self.assert_in_stdout("Access denied", lambda : get_list(other_id))
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        set_user(andrew)
+        self.assert_in_stdout("Shared list ID %d with emina" % list_id, lambda : share_list(list_id, emina, True))
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+        set_user(andrew)
+        self.assert_in_stdout("Unshared", lambda : unshare_list(list_id, emina))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+
+    def test_policy_slicing(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_POLICY_SLICING'] = 'true'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_POLICY_SLICING']
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("=== foo ===", lambda : get_list(0))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(0))
+        self.assert_in_stdout("Access denied", lambda : create_task(0, "bar"))
+        set_user(andrew)
+        self.assert_in_stdout("Shared list ID 0 with emina", lambda : share_list(0, emina, True))
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list(0))
+        self.assert_in_stdout("Access denied", lambda : create_task(0, "bar"))
+
+    def test_decision_log(self):
+        stop_server()
+        time.sleep(0.1)
+        with tempfile.NamedTemporaryFile(suffix='.jsonl', delete=False) as f:
+            pass
+        os.environ['TINYTODO_DECISION_LOG'] = f.name
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DECISION_LOG']
+        try:
+            list_id = self.create_list_id("foo")
+            self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+            set_user(emina)
+            self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+            time.sleep(0.1)
+            with open(f.name) as log:
+                records = [json.loads(line) for line in log]
+        finally:
+            os.remove(f.name)
+        reads = [r for r in records if r['action'] == 'Action::"GetList"' and r['resource'] == 'List::"%d"' % list_id]
+        self.assertEqual(['User::"andrew"', 'User::"emina"'], [r['principal'] for r in reads])
+        self.assertEqual(['Allow', 'Deny'], [r['decision'] for r in reads])
+        self.assertEqual([['policy1'], []], [r['policies'] for r in reads])
+        self.assertEqual([None, 'no policy permitted the request'], [r['reason'] for r in reads])
+        self.assertTrue(all(r['at'] > 0 for r in reads))
+
+    def test_rename_list(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Renamed list ID 0 to bar", lambda : rename_list(0, "bar"))
+        self.assert_in_stdout("=== bar ===", lambda : get_list(0))
+        self.assert_in_stdout("List names must be between 1 and 256 characters", lambda : rename_list(0, ""))
+        self.assert_in_stdout("=== bar ===", lambda : get_list(0))
+
+    def test_concurrent_renames(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID", lambda : share_list(list_id, emina, read_only=False))
+        self.assert_in_stdout('now at version {"User::\\"andrew\\"": 1}', lambda : rename_list(list_id, "bar", {}))
+        set_user(emina)
+        self.assert_in_stdout("Write to List::\"%d\" conflicts with a concurrent write" % list_id, lambda : rename_list(list_id, "baz", {}))
+        self.assert_in_stdout("=== bar ===", lambda : get_list(list_id))
+        self.assert_in_stdout('now at version {"User::\\"andrew\\"": 1, "User::\\"emina\\"": 1}', lambda : rename_list(list_id, "baz", {'User::"andrew"' : 1}))
+        self.assert_in_stdout("=== baz ===", lambda : get_list(list_id))
+
+    def test_transfer_list(self):
+        list_id = self.create_list_id("foo")
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : transfer_list(list_id, emina))
+        set_user(andrew)
+        self.assert_in_stdout("Transferred list ID %d to emina" % list_id, lambda : transfer_list(list_id, emina))
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+        self.assert_in_stdout("Transferred list ID %d to andrew" % list_id, lambda : transfer_list(list_id, andrew))
+
+    def test_complete_tasks(self):
+        list_id = self.create_list_id("foo")
+        for name in ["buy milk", "buy eggs", "call mom"]:
+            self.assert_in_stdout("Created task", lambda : create_task(list_id, name))
+        self.assert_in_stdout("Marked 0 tasks complete on list ID %d" % list_id, lambda : complete_tasks(list_id, True, "walk"))
+        self.assert_in_stdout("Marked 2 tasks complete on list ID %d" % list_id, lambda : complete_tasks(list_id, True, "buy"))
+        self.assert_in_stdout("2: [X] buy eggs", lambda : get_list(list_id))
+        self.assert_in_stdout("3: [ ] call mom", lambda : get_list(list_id))
+        self.assert_in_stdout("Marked 1 tasks complete on list ID %d" % list_id, lambda : complete_tasks(list_id))
+        self.assert_in_stdout("3: [X] call mom", lambda : get_list(list_id))
+        self.assert_in_stdout("Marked 3 tasks incomplete on list ID %d" % list_id, lambda : complete_tasks(list_id, False))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : complete_tasks(list_id))
+
+    def test_capabilities(self):
+        owned_id = self.create_list_id("foo")
+        set_user(emina)
+        shared_id = self.create_list_id("bar")
+        self.assert_in_stdout("Shared list ID %d with andrew" % shared_id, lambda : share_list(shared_id, andrew, True))
+        set_user(andrew)
+        self.assert_in_stdout('Application::"TinyTodo": Administer,CreateList,GetLists', lambda : get_capabilities())
+        self.assert_in_stdout('List::"%d": CreateTask,DeleteList,DeleteTask,EditShare,GetList,TransferList,UpdateList,UpdateTask' % owned_id, lambda : get_capabilities())
+        self.assert_in_stdout('List::"%d": GetList\n' % shared_id, lambda : get_capabilities())
+
+    def test_list_actions(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Actions: CreateTask,DeleteList,DeleteTask,EditShare,GetList,TransferList,UpdateList,UpdateTask", lambda : get_list_actions(list_id))
+        self.assert_in_stdout("Shared list ID %d with kesha" % list_id, lambda : share_list(list_id, kesha, True))
+        set_user(kesha)
+        self.assert_in_stdout("Actions: GetList\n", lambda : get_list_actions(list_id))
+        set_user(aaron)
+        self.assert_in_stdout("Actions: none", lambda : get_list_actions(list_id))
+
+    def test_memberships(self):
+        interns_id = self.create_list_id("foo")
+        temp_id = self.create_list_id("bar")
+        self.assert_in_stdout("Shared list ID %d with interns" % interns_id, lambda : share_list(interns_id, interns, True))
+        self.assert_in_stdout("Shared list ID %d with temp" % temp_id, lambda : share_list(temp_id, temp, False))
+        set_user(aaron)
+        self.assert_in_stdout("Teams: interns, temp\nShared lists: %d, %d" % (interns_id, temp_id), lambda : get_memberships())
+        set_user(kesha)
+        self.assert_in_stdout("Teams: temp\nShared lists: %d" % temp_id, lambda : get_memberships())
+        set_user(emina)
+        self.assert_in_stdout("Teams: admin\nShared lists: none", lambda : get_memberships())
+
+    def test_create_team(self):
+        self.assert_in_stdout("Created team 0", lambda : create_team([kesha, interns]))
+        set_user(kesha)
+        self.assert_in_stdout("Teams: 0, temp\n", lambda : get_memberships())
+        set_user(aaron)
+        self.assert_in_stdout("Teams: 0, interns, temp\n", lambda : get_memberships())
+        self.assert_in_stdout("Access denied", lambda : create_team([aaron]))
+        set_user(andrew)
+        self.assert_in_stdout('Error: No Such Entity: User::"nobody"', lambda : create_team([User("nobody")]))
+
+    def test_offboard_user(self):
+        shared_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID", lambda : share_list(shared_id, kesha))
+        set_user(kesha)
+        owned_id = self.create_list_id("bar")
+        self.assert_in_stdout("Access denied", lambda : offboard_user(kesha, andrew))
+        set_user(andrew)
+        self.assert_in_stdout("Lists transferred: %d\nTeams left: temp\nShares revoked: %d (Reader)" % (owned_id, shared_id), lambda : offboard_user(kesha, andrew))
+        self.assert_in_stdout("=== bar ===", lambda : get_list(owned_id))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_list(shared_id))
+
+    def test_sync_group(self):
+        self.assert_in_stdout("Group engineering is team 0", lambda : sync_group("engineering"))
+        self.assert_in_stdout("Group engineering is team 0", lambda : sync_group("engineering"))
+        self.assert_in_stdout("Group design is team 1", lambda : sync_group("design"))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : sync_group("engineering"))
+
+    def test_clone_team(self):
+        self.assert_in_stdout("Created team 0", lambda : create_team([kesha]))
+        self.assert_in_stdout("Created team 1", lambda : clone_team(Team("0")))
+        set_user(kesha)
+        self.assert_in_stdout("Teams: 0, 1, temp\n", lambda : get_memberships())
+        self.assert_in_stdout("Access denied", lambda : clone_team(Team("0")))
+        set_user(andrew)
+        self.assert_in_stdout('Error: No Such Entity: Team::"nobody"', lambda : clone_team(Team("nobody")))
+
+    def test_team_ancestors(self):
+        self.assert_in_stdout("Ancestors: temp\n", lambda : get_team_ancestors(interns))
+        self.assert_in_stdout("Created team 0", lambda : create_team([interns]))
+        self.assert_in_stdout("Ancestors: 0, temp\n", lambda : get_team_ancestors(interns))
+        self.assert_in_stdout("Ancestors: none", lambda : get_team_ancestors(Team("temp")))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_team_ancestors(interns))
+
+    def test_seed_entities(self):
+        app = 'Application::"TinyTodo"'
+        ops = {'Team' : {'uid' : Team("ops").euid(), 'parents' : [app]}}
+        sam = {'User' : {'euid' : User("sam").euid(), 'joblevel' : 3, 'location' : 'GHI99', 'parents' : [app, Team("ops").euid()]}}
+        self.assert_in_stdout('Seeded User::"sam", Team::"ops"', lambda : seed_entities([sam, ops]))
+        self.assert_in_stdout("sam", lambda : find_entities('User', 'location', 'GHI99'))
+        qa = {'Team' : {'uid' : Team("qa").euid(), 'parents' : [app]}}
+        self.assert_in_stdout('Error: Entity User::"sam" already exists', lambda : seed_entities([qa, sam]))
+        self.assert_in_stdout('Error: No Such Entity: Team::"qa"', lambda : get_team_ancestors(Team("qa")))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : seed_entities([qa]))
+
+    def test_find_entities(self):
+        self.assert_in_stdout("aaron, kesha", lambda : find_entities('User', 'location', 'ABC17'))
+        self.assert_in_stdout("emina", lambda : find_entities('User', 'joblevel', 8))
+        self.assert_in_stdout("None found", lambda : find_entities('User', 'location', 'nowhere'))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : find_entities('User', 'location', 'ABC17'))
+
+    def test_deletion_impact(self):
+        self.assert_in_stdout('Entities: User::"andrew" (parents), User::"emina" (parents)\nPolicies: admin-administer, admin-mfa-read', lambda : get_deletion_impact(admin))
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout('Entities: List::"%d" (owner)\nPolicies: none' % list_id, lambda : get_deletion_impact(andrew))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_deletion_impact(admin))
+
+    def test_apps(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout('Lists in TinyTodo: List::"%d"' % list_id, lambda : get_app_lists('TinyTodo'))
+        self.assert_in_stdout("No Such Entity", lambda : get_app_lists('Acme'))
+        self.assert_in_stdout("Created application Acme", lambda : create_app('Acme'))
+        self.assert_in_stdout("Lists in Acme: none", lambda : get_app_lists('Acme'))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : create_app('Other'))
+        self.assert_in_stdout("Access denied", lambda : get_app_lists('TinyTodo'))
+
+    def test_export_entities(self):
+        self.assert_in_stdout('"id": "kesha"', lambda : export_entities())
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout('"id": "0"', lambda : export_entities())
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : export_entities())
+
+    def test_archive_entity(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Archived", lambda : archive_entity(List(list_id)))
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : restore_entity(List(list_id)))
+        set_user(andrew)
+        self.assert_in_stdout("Restored", lambda : restore_entity(List(list_id)))
+        self.assert_in_stdout("foo", lambda : get_list(list_id))
+
+    def test_repro_case(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Denied with context {}\nPolicies: none", lambda : get_repro_case(emina, 'GetList', list_id))
+        self.assert_in_stdout("Allowed with context {}\nPolicies: policy1", lambda : get_repro_case(andrew, 'GetList', list_id))
+        try:
+            set_mfa(True)
+            self.assert_in_stdout('Allowed with context {"mfa": true}\nPolicies: admin-mfa-read', lambda : get_repro_case(emina, 'GetList', list_id))
+        finally:
+            set_mfa(None)
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_repro_case(kesha, 'GetList', list_id))
+
+    def get_fingerprint(self):
+        out = io.StringIO()
+        with redirect_stdout(out):
+            get_fingerprint()
+        self.assertIn("Fingerprint: ", out.getvalue())
+        return out.getvalue().split()[-1]
+
+    def test_verify_sync(self):
+        fingerprint = self.get_fingerprint()
+        self.assert_in_stdout("In sync", lambda : verify_sync(fingerprint))
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Replica has diverged from the primary and needs a full resync", lambda : verify_sync(fingerprint))
+        self.assert_in_stdout("In sync", lambda : verify_sync(self.get_fingerprint()))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_fingerprint())
+
+    def test_stats(self):
+        self.assert_in_stdout("Entities: 4 users, 3 teams, 0 lists, 0 shares\nAuthorization cache: off", lambda : get_stats())
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID", lambda : share_list(list_id, emina))
+        self.assert_in_stdout("Shared list ID", lambda : share_list(list_id, Team("temp"), read_only=False))
+        self.assert_in_stdout("Entities: 4 users, 3 teams, 1 lists, 2 shares", lambda : get_stats())
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_stats())
+        set_user(andrew)
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_AUTH_CACHE'] = '16'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_AUTH_CACHE']
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+        self.assert_in_stdout("Authorization cache: 1 hits, 3 misses, 0 evictions, 2 entries", lambda : get_stats())
+
+    def test_export_user_data(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Owned lists: foo\nTeams: admin, temp\n", lambda : export_user_data())
+        self.assert_in_stdout("Access denied", lambda : export_user_data(emina))
+        set_user(emina)
+        self.assert_in_stdout("Owned lists: none\nTeams: admin\n", lambda : export_user_data())
+
+    def test_unshare_removes_both_roles(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID", lambda : share_list(list_id, emina, read_only=False))
+        self.assert_in_stdout("Unshared", lambda : unshare_list(list_id, emina))
+        self.assert_in_stdout("Unshared", lambda : unshare_list(list_id, emina))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+
+    def test_acls_round_trip(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID %d with emina" % list_id, lambda : share_list(list_id, emina, True))
+        snapshot = json.loads(export_acls_inner(andrew).text)
+        self.assert_in_stdout("Unshared read permissions on list ID %d with emina" % list_id, lambda : unshare_list(list_id, emina))
+        self.assert_in_stdout("Imported ACLs", lambda : import_acls(snapshot))
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : export_acls())
+        self.assert_in_stdout("Access denied", lambda : import_acls(snapshot))
+
+    def test_granting_teams(self):
+        list_id = self.create_list_id("foo")
+        share_list(list_id, interns, True)
+        share_list(list_id, admin, False)
+        share_list(list_id, kesha, True)
+        self.assert_in_stdout("Readers: interns\nEditors: admin", lambda : get_granting_teams(list_id))
+        unshare_list(list_id, interns)
+        self.assert_in_stdout("Readers: none\nEditors: admin", lambda : get_granting_teams(list_id))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_granting_teams(list_id))
+
+    def issue_token(self, list_id, capabilities, ttl_secs = None):
+        out = io.StringIO()
+        with redirect_stdout(out):
+            issue_list_token(list_id, capabilities, ttl_secs)
+        self.assertIn("Issued token", out.getvalue())
+        return out.getvalue().split()[-1]
+
+    def test_list_token(self):
+        list_id = self.create_list_id("foo")
+        reader = self.issue_token(list_id, ['read'])
+        appender = self.issue_token(list_id, ['read', 'append'])
+        set_user(emina)
+        self.assert_in_stdout("=== foo ===", lambda : get_list_with_token(reader))
+        self.assert_in_stdout("Error: Token does not allow Append on its list", lambda : create_task_with_token(reader, "bar"))
+        self.assert_in_stdout("Created task 0 with token", lambda : create_task_with_token(appender, "bar"))
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        self.assert_in_stdout("Access denied", lambda : issue_list_token(list_id, ['read']))
+        set_user(andrew)
+        self.assert_in_stdout("bar", lambda : get_list(list_id))
+
+    def test_expired_list_token(self):
+        list_id = self.create_list_id("foo")
+        token = self.issue_token(list_id, ['read'], 0)
+        self.assert_in_stdout("Error: Token is invalid or has expired", lambda : get_list_with_token(token))
+        self.assert_in_stdout("Error: Token is invalid or has expired", lambda : get_list_with_token("nonsense"))
+
+    def test_uuid_ids(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_UUID_IDS'] = 'true'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_UUID_IDS']
+        out = io.StringIO()
+        with redirect_stdout(out):
+            create_list("foo")
+        list_id = out.getvalue().split()[-1]
+        self.assertRegex(list_id, '^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$')
+        self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+
+    def test_lenient_load_quarantines_bad_entity(self):
+        stop_server()
+        time.sleep(0.1)
+        with open('entities.json') as f:
+            snapshot = json.load(f)
+        snapshot['users']['User::"kesha"']['joblevel'] = 'five'
+        with tempfile.NamedTemporaryFile('w', suffix='.json', delete=False) as f:
+            json.dump(snapshot, f)
+        os.environ['TINYTODO_ENTITIES'] = f.name
+        os.environ['TINYTODO_LENIENT_LOAD'] = 'true'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_ENTITIES']
+            del os.environ['TINYTODO_LENIENT_LOAD']
+            os.remove(f.name)
+        self.assert_in_stdout("Created list ID", lambda : create_list("foo"))
+        set_user(emina)
+        self.assert_in_stdout("Created list ID", lambda : create_list("bar"))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : create_list("baz"))
+
+    def test_missing_entities_file_starts_empty(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_ENTITIES'] = os.path.join(tempfile.mkdtemp(), 'none.json')
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_ENTITIES']
+        self.assert_in_stdout("No lists for", lambda : get_lists())
+        self.assert_in_stdout("No Such Entity", lambda : create_list("foo"))
+
+    def test_list_quota(self):
+        stop_server()
+        time.sleep(0.1)
+        with open('entities.json') as f:
+            snapshot = json.load(f)
+        snapshot['users']['User::"andrew"']['list_quota'] = 2
+        with tempfile.NamedTemporaryFile('w', suffix='.json', delete=False) as f:
+            json.dump(snapshot, f)
+        os.environ['TINYTODO_ENTITIES'] = f.name
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_ENTITIES']
+            os.remove(f.name)
+        self.assert_in_stdout("Created list ID", lambda : create_list("foo"))
+        template_id = self.create_list_id("bar")
+        self.assert_in_stdout('Error: User User::"andrew" may not own more than 2 lists', lambda : create_list("baz"))
+        self.assert_in_stdout("Marked list ID %d as a template" % template_id, lambda : set_template(template_id))
+        self.assert_in_stdout("Created list ID", lambda : create_list("baz"))
+        set_user(emina)
+        self.assert_in_stdout("Created list ID", lambda : create_list("qux"))
+
+    def test_team_default_share(self):
+        stop_server()
+        time.sleep(0.1)
+        with open('entities.json') as f:
+            snapshot = json.load(f)
+        snapshot['teams']['Team::"admin"']['default_share'] = 'Editor'
+        with tempfile.NamedTemporaryFile('w', suffix='.json', delete=False) as f:
+            json.dump(snapshot, f)
+        os.environ['TINYTODO_ENTITIES'] = f.name
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_ENTITIES']
+            os.remove(f.name)
+        plain_id = self.create_list_id("foo")
+        shared_id = self.create_list_id("bar", True)
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(plain_id))
+        self.assert_in_stdout("Created task", lambda : create_task(shared_id, "buy milk"))
+        set_user(kesha)
+        self.assert_in_stdout("Access denied", lambda : get_list(shared_id))
+
+    def test_dynamic_groups(self):
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Shared list ID %d with interns" % list_id, lambda : share_list(list_id, interns, True))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : check_access('GetList', list_id))
+        self.assert_in_stdout("Access allowed", lambda : check_access('GetList', list_id, [interns]))
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        self.assert_in_stdout("No Such Entity", lambda : check_access('GetList', list_id, [Team('nobody')]))
+
+    def test_authorization_requirements(self):
+        self.assert_in_stdout("Error: Authorization diagnostics are only available", lambda : get_requirements('GetList', 0))
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_DENIAL_VERBOSITY'] = 'detailed'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DENIAL_VERBOSITY']
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("No context would allow this", lambda : get_requirements('GetList', list_id))
+        set_user(emina)
+        self.assert_in_stdout("context.mfa (admin-mfa-read)", lambda : get_requirements('GetList', list_id))
+        set_user(kesha)
+        self.assert_in_stdout("No context would allow this", lambda : get_requirements('GetList', list_id))
+
+    def test_granting_policies(self):
+        self.assert_in_stdout("Error: Authorization diagnostics are only available", lambda : get_granting_policies('GetList', 0))
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_DENIAL_VERBOSITY'] = 'detailed'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DENIAL_VERBOSITY']
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Granted by policy1", lambda : get_granting_policies('GetList', list_id))
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_granting_policies('GetList', list_id))
+        try:
+            set_mfa(True)
+            self.assert_in_stdout("Granted by admin-mfa-read", lambda : get_granting_policies('GetList', list_id))
+        finally:
+            set_mfa(None)
+
+    def test_denied_actions(self):
+        self.assert_in_stdout("Error: Authorization diagnostics are only available", lambda : get_denied_actions(0))
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_DENIAL_VERBOSITY'] = 'detailed'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DENIAL_VERBOSITY']
+        list_id = self.create_list_id("foo")
+        self.assert_in_stdout("Nothing is denied", lambda : get_denied_actions(list_id))
+        self.assert_in_stdout("Shared list ID", lambda : share_list(list_id, emina, read_only=False))
+        set_user(emina)
+        self.assert_in_stdout("DeleteList: no policy permits it\nEditShare: no policy permits it\nTransferList: no policy permits it\n", lambda : get_denied_actions(list_id))
+
+    def test_mfa_admin_read(self):
+        list_id = self.create_list_id("foo")
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        try:
+            set_mfa(True)
+            self.assert_in_stdout("=== foo ===", lambda : get_list(list_id))
+            set_user(kesha)
+            self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+            set_user(emina)
+            set_mfa(False)
+            self.assert_in_stdout("Access denied", lambda : get_list(list_id))
+        finally:
+            set_mfa(None)
+
+    def test_authorization_trace(self):
+        self.assert_in_stdout("Error: Authorization diagnostics are only available", lambda : trace_authorization('GetList', 0))
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_DENIAL_VERBOSITY'] = 'detailed'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_DENIAL_VERBOSITY']
+        list_id = self.create_list_id("foo")
+        out = io.StringIO()
+        with redirect_stdout(out):
+            trace_authorization('GetList', list_id)
+        trace = json.loads(out.getvalue())
+        self.assertEqual('User::"andrew"', trace['request']['principal'])
+        self.assertEqual('Action::"GetList"', trace['request']['action'])
+        self.assertEqual('List::"%d"' % list_id, trace['request']['resource'])
+        self.assertEqual('Allow', trace['decision'])
+        self.assertNotEqual([], trace['diagnostics']['reason'])
+        set_user(emina)
+        out = io.StringIO()
+        with redirect_stdout(out):
+            trace_authorization('GetList', list_id)
+        trace = json.loads(out.getvalue())
+        self.assertEqual('Deny', trace['decision'])
+        self.assertEqual([], trace['diagnostics']['reason'])
+
+    def test_reorder_lists(self):
+        first = self.create_list_id("foo")
+        second = self.create_list_id("bar")
+        third = self.create_list_id("baz")
+        self.assert_in_stdout("Lists: %d,%d,%d" % (first, second, third), lambda : get_lists())
+        self.assert_in_stdout("Reordered lists", lambda : reorder_lists([third, first]))
+        self.assert_in_stdout("Lists: %d,%d,%d" % (third, first, second), lambda : get_lists())
+        self.assert_in_stdout("Error: List", lambda : reorder_lists([second, second]))
+        self.assert_in_stdout("Lists: %d,%d,%d" % (third, first, second), lambda : get_lists())
+        set_user(emina)
+        self.assert_in_stdout("Access denied", lambda : reorder_lists([first]))
+
+    def test_task_fields(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Error: Invalid task fields", lambda : create_task(0, "bar", {'points': 3}))
+        self.assert_in_stdout("Set task schema on list ID 0", lambda : set_task_schema(0, {'points': 'Long'}))
+        self.assert_in_stdout("Created task", lambda : create_task(0, "bar", {'points': 3}))
+        self.assert_in_stdout("Error: Invalid task fields", lambda : create_task(0, "baz", {'points': 'three'}))
+        self.assert_in_stdout("Error: Invalid task fields", lambda : create_task(0, "baz", {'size': 3}))
+        self.assert_in_stdout("1: [ ] bar", lambda : get_list(0))
+
+    def test_webhook_on_list_update(self):
+        stop_server()
+        time.sleep(0.1)
+        os.environ['TINYTODO_WEBHOOK_HOSTS'] = '127.0.0.1'
+        try:
+            start_server()
+            time.sleep(0.1)
+        finally:
+            del os.environ['TINYTODO_WEBHOOK_HOSTS']
+        sink = HTTPServer(('127.0.0.1', 8099), WebhookSink)
+        threading.Thread(target=sink.serve_forever, daemon=True).start()
+        try:
+            self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+            self.assert_in_stdout("Registered webhook on list ID 0", lambda : register_webhook(0, 'http://127.0.0.1:8099/hook'))
+            self.assert_in_stdout("Registered webhook on list ID 0", lambda : register_webhook(0, 'http://127.0.0.1:8099/hook'))
+            self.assert_in_stdout("Created task", lambda : create_task(0, "bar"))
+            time.sleep(0.5)
+            self.assert_in_stdout("Renamed list ID 0 to groceries", lambda : rename_list(0, "groceries"))
+            time.sleep(0.5)
+            self.assertEqual(1, WebhookSink.payloads.count({'list': 'List::"0"', 'change': 'Update', 'by': 'User::"andrew"', 'tasks': {'added': [0], 'removed': [], 'changed': []}}))
+            self.assertIn({'list': 'List::"0"', 'change': 'Update', 'by': 'User::"andrew"', 'fields': ['Name']}, WebhookSink.payloads)
+        finally:
+            sink.shutdown()
+            sink.server_close()
+
+    def test_webhook_host_not_allowed(self):
+        self.assert_in_stdout("Created list ID 0", lambda : create_list("foo"))
+        self.assert_in_stdout("Error: Invalid webhook: Webhooks may not be sent to `169.254.169.254`", lambda : register_webhook(0, 'http://169.254.169.254/latest/meta-data'))
+        self.assert_in_stdout("Error: Invalid webhook: Webhooks must use http, not `ftp`", lambda : register_webhook(0, 'ftp://127.0.0.1/hook'))
@@ -97,13 +97,13 @@ class Server:
         return 'http://localhost:%s' % self.port
 
     def get(self, param):
-        return requests.get('%s%s' % (self.url(), param))
+        return requests.get('%s%s' % (self.url(), param), headers = context_headers())
 
     def post(self, param, data):
-        return requests.post('%s%s' % (self.url(), param), json = data)
+        return requests.post('%s%s' % (self.url(), param), json = data, headers = context_headers())
 
     def delete(self, param, data):
-        return requests.delete('%s%s' % (self.url(), param), json = data)
+        return requests.delete('%s%s' % (self.url(), param), json = data, headers = context_headers())
 
     def stopped(self):
         return False
@@ -126,6 +126,7 @@ users = ['emina', 'aaron', 'andrew', 'kesha']
 teams = ['admin', 'interns', 'temp']
 [admin, interns, temp] = [Team(team) for team in teams]
 current_user = None
+mfa = None
 
 
 
@@ -139,6 +140,19 @@ def set_user(user):
     current_user = user
     print('User is now %s' % user)
 
+# Set whether the current user has passed MFA, or None to not say
+def set_mfa(passed):
+    global mfa
+    mfa = passed
+    print('MFA is now %s' % passed)
+
+# The headers telling the server the context of each request
+def context_headers():
+    if mfa is None:
+        return {}
+    else:
+        return {'X-TinyTodo-MFA': 'true' if mfa else 'false'}
+
 # Start the TinyTodo server
 def start_server(port = 8080):
     global server
@@ -218,7 +232,7 @@ def is_error(body):
 
 
 def is_authz_denied(body):
-    return 'error' in body and body['error'] == 'Authorization Denied'
+    return 'error' in body and body['error'].startswith('Authorization Denied')
         
 
 
@@ -230,6 +244,11 @@ def get_lists(user):
     req = server.get('/api/lists/get?uid=%s' % user.euid())
     return req, get_lists_printer(user)
 
+@web_req("Get Templates")
+def get_templates(user):
+    req = server.get('/api/lists/get?uid=%s&templates=true' % user.euid())
+    return req, get_lists_printer(user)
+
 def get_lists_printer(user):
     def inner(list_of_lists):
         if len(list_of_lists) == 0:
@@ -239,11 +258,297 @@ def get_lists_printer(user):
 
     return inner
 
+@web_req("Reorder Lists")
+def reorder_lists(user, list_ids):
+    data = {
+            'uid' : user.euid(),
+            'order' : [List(list_id).euid() for list_id in list_ids]
+            }
+    return server.post('/api/lists/order', data), lambda _ : 'Reordered lists'
+
+@web_req("Get List Summaries")
+def get_list_summaries(user):
+    req = server.get('/api/lists/summaries?uid=%s' % user.euid())
+    return req, get_list_summaries_printer(user)
+
+@web_req("Get Many Lists")
+def get_many_lists(user, list_ids):
+    data = {
+            'uid' : user.euid(),
+            'lists' : [List(l).euid() for l in list_ids],
+            }
+    return server.post('/api/lists/many', data), get_many_lists_printer(list_ids)
+
+def get_many_lists_printer(list_ids):
+    def display_lookup(list_id, lookup):
+        if 'list' in lookup:
+            return '%s: %s' % (List(list_id), lookup['list']['name'])
+        else:
+            return '%s: %s' % (List(list_id), lookup['error'])
+
+    return lambda lookups : '\n'.join([display_lookup(l, x) for (l, x) in zip(list_ids, lookups)])
+
+def get_list_summaries_printer(user):
+    def inner(summaries):
+        if len(summaries) == 0:
+            return 'No lists for %s' % user
+        else:
+            return '\n'.join([display_summary(s) for s in summaries])
+
+    return inner
+
+def display_summary(s):
+    return '%s: %s (%d tasks, %d%% done)' % (List(s['uid']), s['name'], s['task_count'], round(s['completion'] * 100))
+
+@web_req("Find Duplicate Lists")
+def find_duplicate_lists(user):
+    req = server.get('/api/lists/duplicates?uid=%s' % user.euid())
+    return req, lambda groups : '\n'.join(['Duplicates: %s' % ','.join([str(List(l)) for l in group]) for group in groups]) or 'No duplicate lists'
+
+@web_req("Get Lists By Owner")
+def get_lists_by_owner(user):
+    req = server.get('/api/lists/by_owner?uid=%s' % user.euid())
+    return req, get_lists_by_owner_printer
+
+def get_lists_by_owner_printer(grouped):
+    lines = ['%s: %s' % (owner, ','.join([str(List(l)) for l in lists])) for owner, lists in grouped['owned'].items()]
+    if len(grouped['ownerless']) > 0:
+        lines.append('Ownerless: %s' % ','.join([str(List(l)) for l in grouped['ownerless']]))
+    return '\n'.join(lines) or 'No lists'
+
+@web_req("Create Team")
+def create_team(user, members):
+    data = {
+            'uid' : user.euid(),
+            'members' : [m.euid() for m in members],
+            }
+    return server.post('/api/admin/team/create', data), lambda t : 'Created team %s' % parse_euid(t, 'Team')
+
+@web_req("Offboard User")
+def offboard_user(user, departing, successor):
+    data = {
+            'uid' : user.euid(),
+            'user' : departing.euid(),
+            'successor' : successor.euid(),
+            }
+    return server.post('/api/admin/offboard', data), display_offboard_report
+
+def display_offboard_report(report):
+    lists = ', '.join(sorted([parse_euid(l, 'List') for l in report['lists_transferred']])) or 'none'
+    teams = ', '.join(sorted([parse_euid(t, 'Team') for t in report['teams_left']])) or 'none'
+    shares = ', '.join(sorted(['%s (%s)' % (parse_euid(g['list'], 'List'), g['role']) for g in report['grants_revoked']])) or 'none'
+    return 'Lists transferred: %s\nTeams left: %s\nShares revoked: %s' % (lists, teams, shares)
+
+@web_req("Sync Group")
+def sync_group(user, name):
+    data = {
+            'uid' : user.euid(),
+            'name' : name,
+            }
+    return server.post('/api/admin/team/group', data), lambda t : 'Group %s is team %s' % (name, parse_euid(t, 'Team'))
+
+@web_req("Clone Team")
+def clone_team(user, team):
+    data = {
+            'uid' : user.euid(),
+            'team' : team.euid(),
+            }
+    return server.post('/api/admin/team/clone', data), lambda t : 'Created team %s' % parse_euid(t, 'Team')
+
+# Each entity is one of `users`, `teams`, or `lists` as in entities.json, tagged with its kind,
+# e.g. `{'Team' : {'uid' : 'Team::"ops"', 'parents' : ['Application::"TinyTodo"']}}`
+@web_req("Seed Entities")
+def seed_entities(user, entities):
+    data = {
+            'uid' : user.euid(),
+            'entities' : entities,
+            }
+    return server.post('/api/admin/seed', data), lambda euids : 'Seeded %s' % ', '.join(euids)
+
+@web_req("Get Team Ancestors")
+def get_team_ancestors(user, team):
+    req = server.get('/api/admin/team/ancestors?uid=%s&team=%s' % (user.euid(), team.euid()))
+    return req, lambda teams : 'Ancestors: %s' % (', '.join(parse_euid(t, 'Team') for t in teams) or 'none')
+
+@web_req("Create App")
+def create_app(user, name):
+    data = {
+            'uid' : user.euid(),
+            'app' : 'Application::"%s"' % name,
+            }
+    return server.post('/api/admin/app/create', data), lambda a : 'Created application %s' % name
+
+@web_req("Get App Lists")
+def get_app_lists(user, name):
+    req = server.get('/api/admin/app/lists?uid=%s&app=%s' % (user.euid(), 'Application::"%s"' % name))
+    return req, lambda lists : 'Lists in %s: %s' % (name, ', '.join(lists) or 'none')
+
+@web_req("Export ACLs")
+def export_acls(user):
+    return export_acls_inner(user), lambda snapshot : json.dumps(snapshot, indent = 2)
+
+def export_acls_inner(user):
+    return server.get('/api/admin/acls?uid=%s' % user.euid())
+
+@web_req("Find Entities")
+def find_entities(user, ty, attr, value):
+    data = {
+            'uid' : user.euid(),
+            'type' : ty,
+            'attr' : attr,
+            'value' : value,
+            }
+    names = lambda euids : ', '.join(sorted([parse_euid(e, ty) for e in euids])) or 'None found'
+    return server.post('/api/admin/find', data), names
+
+@web_req("Get Deletion Impact")
+def get_deletion_impact(user, entity):
+    req = server.get('/api/admin/impact?uid=%s&entity=%s' % (user.euid(), entity.euid()))
+    return req, display_deletion_impact
+
+def display_deletion_impact(impact):
+    entities = ', '.join(sorted(['%s (%s)' % (euid, field) for [euid, field] in impact['entities']])) or 'none'
+    policies = ', '.join(sorted(impact['policies'])) or 'none'
+    return 'Entities: %s\nPolicies: %s' % (entities, policies)
+
+@web_req("Get Fingerprint")
+def get_fingerprint(user):
+    return server.get('/api/admin/sync?uid=%s' % user.euid()), lambda f : 'Fingerprint: %s' % f
+
+@web_req("Verify Sync")
+def verify_sync(user, fingerprint):
+    data = {
+            'uid' : user.euid(),
+            'fingerprint' : fingerprint,
+            }
+    return server.post('/api/admin/sync', data), lambda _ : 'In sync'
+
+@web_req("Export Entities")
+def export_entities(user):
+    req = server.get('/api/admin/entities?uid=%s' % user.euid())
+    return req, lambda entities : 'Exported %d entities\n%s' % (len(entities), json.dumps(entities, indent=2))
+
+@web_req("Archive Entity")
+def archive_entity(user, entity):
+    data = {
+            'uid' : user.euid(),
+            'entity' : entity.euid(),
+            }
+    return server.post('/api/admin/archive', data), lambda _ : 'Archived %s' % entity.euid()
+
+@web_req("Restore Entity")
+def restore_entity(user, entity):
+    data = {
+            'uid' : user.euid(),
+            'entity' : entity.euid(),
+            }
+    return server.post('/api/admin/restore', data), lambda _ : 'Restored %s' % entity.euid()
+
+@web_req("Get Repro Case")
+def get_repro_case(user, principal, action, list_id):
+    req = server.get('/api/admin/repro?uid=%s&principal=%s&action=%s&resource=%s' % (user.euid(), principal.euid(), action, List(list_id).euid()))
+    return req, display_repro_case
+
+def display_repro_case(case):
+    decision = 'Allowed' if case['allowed'] else 'Denied'
+    policies = ', '.join(sorted(case['policies'])) or 'none'
+    return '%s with context %s\nPolicies: %s\n%s' % (decision, json.dumps(case['context']), policies, json.dumps(case, indent=2))
+
+@web_req("Get Stats")
+def get_stats(user):
+    return server.get('/api/admin/stats?uid=%s' % user.euid()), display_stats
+
+def display_stats(stats):
+    store = stats['store']
+    # Stores built with `--features use-templates` don't count shares
+    shares = ', %d shares' % store['shares'] if 'shares' in store else ''
+    entities = 'Entities: %d users, %d teams, %d lists%s' % (store['users'], store['teams'], store['lists'], shares)
+    cache = stats['cache']
+    if cache is None:
+        return '%s\nAuthorization cache: off' % entities
+    else:
+        return '%s\nAuthorization cache: %d hits, %d misses, %d evictions, %d entries' % (entities, cache['hits'], cache['misses'], cache['evictions'], cache['entries'])
+
+@web_req("Import ACLs")
+def import_acls(user, snapshot):
+    data = {
+            'uid' : user.euid(),
+            'snapshot' : snapshot,
+            }
+    return server.post('/api/admin/acls', data), lambda _ : 'Imported ACLs'
+
+# Needs a server built with `--features undo`
+@web_req("Undo")
+def undo(user):
+    req = server.post('/api/admin/undo', { 'uid' : user.euid() })
+    return req, lambda _ : 'Undid the latest change'
+
+# Needs a server built with `--features partial-eval`
+@web_req("Get Partial Decision")
+def get_partial_decision(user, action, list_id):
+    req = server.get('/api/partial?uid=%s&action=%s&resource=%s' % (user.euid(), action, List(list_id).euid()))
+    return req, lambda d : d if isinstance(d, str) else 'Depends on the context through %s' % ', '.join(d['residual'])
+
+# Needs a server built with `--features sealed-fields`
+@web_req("Export Sealed")
+def export_sealed(user):
+    req = server.get('/api/admin/sealed?uid=%s' % user.euid())
+    return req, lambda snapshot : json.dumps(snapshot, indent=2)
+
+@web_req("Get Capabilities")
+def get_capabilities(user):
+    req = server.get('/api/capabilities?uid=%s' % user.euid())
+    return req, lambda caps : '\n'.join(['%s: %s' % (c['resource'], ','.join(c['actions'])) for c in caps])
+
+@web_req("Get List Actions")
+def get_list_actions(user, list_id):
+    req = server.get('/api/list/actions?uid=%s&list=%s' % (user.euid(), List(list_id).euid()))
+    return req, lambda actions : 'Actions: %s' % (','.join(actions) or 'none')
+
+@web_req("Trace Authorization")
+def trace_authorization(user, action, list_id):
+    req = server.get('/api/trace?uid=%s&action=%s&resource=%s' % (user.euid(), action, List(list_id).euid()))
+    return req, lambda trace : json.dumps(trace, indent = 2)
+
+@web_req("Check Access")
+def check_access(user, action, list_id, groups = []):
+    data = {
+            'uid' : user.euid(),
+            'action' : action,
+            'resource' : List(list_id).euid(),
+            'groups' : [g.euid() for g in groups]
+            }
+    return server.post('/api/authorize', data), lambda _ : 'Access allowed'
+
+@web_req("Get Authorization Requirements")
+def get_requirements(user, action, list_id):
+    req = server.get('/api/requirements?uid=%s&action=%s&resource=%s' % (user.euid(), action, List(list_id).euid()))
+    return req, lambda reqs : '\n'.join(['context.%s (%s)' % (r['attribute'], r['policy']) for r in reqs]) or 'No context would allow this'
+
+@web_req("Get Granting Policies")
+def get_granting_policies(user, action, list_id):
+    req = server.get('/api/granted?uid=%s&action=%s&resource=%s' % (user.euid(), action, List(list_id).euid()))
+    return req, lambda info : 'Granted by %s' % ', '.join(sorted(info['determining_policies']))
+
+@web_req("Get Denied Actions")
+def get_denied_actions(user, list_id):
+    req = server.get('/api/denied?uid=%s&resource=%s' % (user.euid(), List(list_id).euid()))
+    return req, display_denied_actions
+
+def display_denied_actions(denied):
+    def reason(d):
+        if d['forbidden_by']:
+            return 'forbidden by %s' % ', '.join(d['forbidden_by'])
+        else:
+            return 'no policy permits it'
+    return '\n'.join(sorted(['%s: %s' % (d['action'], reason(d)) for d in denied])) or 'Nothing is denied'
+
 @web_req("Create List")
-def create_list(user, name):
+def create_list(user, name, apply_team_defaults = False):
     data = {
             'uid' : user.euid(),
-            'name' : name
+            'name' : name,
+            'apply_team_defaults' : apply_team_defaults
             }
     f = lambda x: 'Created list ID %s' % List(x)
     return server.post('/api/list/create', data), f
@@ -278,6 +583,19 @@ def display_list(l):
     return inner
 
 
+@web_req("Get Tasks Page")
+def get_tasks_page(user, list_id, after = None, limit = None):
+    url = '/api/list/tasks?uid=%s&list=%s' % (user.euid(), List(list_id).euid())
+    if after is not None:
+        url += '&after=%d' % after
+    if limit is not None:
+        url += '&limit=%d' % limit
+    def inner(page):
+        lines = ['%d: %s' % (task['id'], task['name']) for task in page['tasks']]
+        lines.append('Next: %s' % page['next'] if page['next'] is not None else 'End of list')
+        return '\n'.join(lines)
+    return server.get(url), inner
+
 def display_task(index, task):
     return '%d: %s %s' % (index, '[ ]' if task['state'] == 'Unchecked' else '[X]', task['name'])
 
@@ -285,15 +603,37 @@ def display_task(index, task):
             
 
 @web_req("Create Task")
-def create_task(user, list_id, name):
+def create_task(user, list_id, name, fields = None):
     url = '/api/task/create'
     data = { 
             'uid' : user.euid(),
             'list' : List(list_id).euid(),
             'name' : name
             }
+    if fields is not None:
+        data['fields'] = fields
     return server.post(url, data), lambda _ : 'Created task on list ID %d' % list_id
 
+@web_req("Set Template")
+def set_template(user, list_id, is_template = True):
+    url = '/api/list/template'
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid(),
+            'is_template' : is_template
+            }
+    return server.post(url, data), lambda _ : '%s list ID %d as a template' % ('Marked' if is_template else 'Unmarked', list_id)
+
+@web_req("Set Task Schema")
+def set_task_schema(user, list_id, schema):
+    url = '/api/list/task_schema'
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid(),
+            'schema' : schema
+            }
+    return server.post(url, data), lambda _ : 'Set task schema on list ID %d' % list_id
+
 
 
 @web_req("Toggle Task")
@@ -319,6 +659,17 @@ def find_task(user, lst, task_id):
     else:
         raise NoSuchTaskException(lst, task_id + 1)
 
+@web_req("Complete Tasks")
+def complete_tasks(user, list_id, completed = True, name_contains = None):
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid(),
+            'completed' : completed,
+            'name_contains' : name_contains
+            }
+    state = 'complete' if completed else 'incomplete'
+    return server.post('/api/task/complete', data), lambda n : 'Marked %d tasks %s on list ID %d' % (n, state, list_id)
+
 @web_req("Change Task Description")
 def change_task_description(user, list_id, task_id, desc):
     lst = List(list_id)
@@ -353,6 +704,77 @@ def delete_list(user, list_id):
             }
     return server.delete(url, data), lambda _: 'List Deleted'
 
+@web_req("merge lists")
+def merge_lists(user, into_id, from_id):
+    url = '/api/list/merge'
+    data = {
+            'uid' : user.euid(),
+            'into' : List(into_id).euid(),
+            'from' : List(from_id).euid(),
+            }
+    return server.post(url, data), lambda _: 'Merged list ID %s into list ID %s' % (from_id, into_id)
+
+@web_req("rename list")
+def rename_list(user, list_id, name, version = None):
+    l = List(list_id)
+    url = '/api/list/update'
+    data = {
+            'uid' : user.euid(),
+            'list' : l.euid(),
+            'name' : name,
+            'version' : version,
+            }
+    return server.post(url, data), lambda v: 'Renamed list ID %s to %s, now at version %s' % (list_id, name, json.dumps(v))
+
+@web_req("transfer list")
+def transfer_list(user, list_id, new_owner):
+    l = List(list_id)
+    url = '/api/list/transfer'
+    data = {
+            'uid' : user.euid(),
+            'list' : l.euid(),
+            'new_owner' : new_owner.euid(),
+            }
+    return server.post(url, data), lambda _: 'Transferred list ID %s to %s' % (l, new_owner)
+
+@web_req("register webhook")
+def register_webhook(user, list_id, url):
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid(),
+            'url' : url,
+            }
+    return server.post('/api/list/webhook', data), lambda _: 'Registered webhook on list ID %s' % list_id
+
+
+@web_req("touch presence")
+def touch_presence(user, list_id):
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid()
+            }
+    return server.post('/api/list/presence', data), lambda _ : 'Touched presence on list ID %d' % list_id
+
+@web_req("get presence")
+def get_presence(user, list_id):
+    req = server.get('/api/list/presence?uid=%s&list=%s' % (user.euid(), List(list_id).euid()))
+    return req, get_presence_printer(list_id)
+
+@web_req("Get Activity")
+def get_activity(user, list_id, limit = None):
+    url = '/api/list/activity?uid=%s&list=%s' % (user.euid(), List(list_id).euid())
+    if limit is not None:
+        url += '&limit=%d' % limit
+    return server.get(url), lambda events : '\n'.join(['%s by %s' % (e['activity'], parse_euid(e['by'], 'User')) for e in events]) or 'No activity on list ID %d' % list_id
+
+def get_presence_printer(list_id):
+    def inner(users):
+        if len(users) == 0:
+            return 'Nobody on list ID %d' % list_id
+        else:
+            return 'On list ID %d: %s' % (list_id, ','.join(sorted(parse_euid(u, 'User') for u in users)))
+
+    return inner
 
 @web_req("share list")
 def share_list(user, list_id, share_with, read_only = True):
@@ -366,6 +788,52 @@ def share_list(user, list_id, share_with, read_only = True):
             }
     return server.post(url, data), lambda _: 'Shared list ID %s with %s as %s' % (l, share_with, 'reader' if read_only else 'editor')
 
+@web_req("get granting teams")
+def get_granting_teams(user, list_id):
+    l = List(list_id)
+    req = server.get('/api/list/granting_teams?uid=%s&list=%s' % (user.euid(), l.euid()))
+    names = lambda teams : ', '.join([parse_euid(t, 'Team') for t in teams]) or 'none'
+    return req, lambda g : 'Readers: %s\nEditors: %s' % (names(g['readers']), names(g['editors']))
+
+@web_req("issue list token")
+def issue_list_token(user, list_id, capabilities, ttl_secs = None):
+    data = {
+            'uid' : user.euid(),
+            'list' : List(list_id).euid(),
+            'capabilities' : capabilities
+            }
+    if ttl_secs is not None:
+        data['ttl_secs'] = ttl_secs
+    return server.post('/api/list/token', data), lambda t : 'Issued token %s' % t
+
+@web_req("get list with token")
+def get_list_with_token(user, token):
+    req = server.get('/api/token/list?token=%s' % token)
+    return req, lambda obj : display_list(List(obj['uid']))(obj)
+
+@web_req("create task with token")
+def create_task_with_token(user, token, name):
+    data = {
+            'token' : token,
+            'name' : name
+            }
+    return server.post('/api/token/task', data), lambda i : 'Created task %d with token' % i
+
+@web_req("get memberships")
+def get_memberships(user):
+    req = server.get('/api/memberships?uid=%s' % user.euid())
+    teams = lambda m : ', '.join([parse_euid(t, 'Team') for t in m['teams']]) or 'none'
+    lists = lambda m : ', '.join([parse_euid(l, 'List') for l in m['lists']]) or 'none'
+    return req, lambda m : 'Teams: %s\nShared lists: %s' % (teams(m), lists(m))
+
+@web_req("export user data")
+def export_user_data(user, target = None):
+    target = target or user
+    req = server.get('/api/user/export?uid=%s&user=%s' % (user.euid(), target.euid()))
+    lists = lambda e : ', '.join([l['name'] for l in e['owned_lists']]) or 'none'
+    teams = lambda e : ', '.join(sorted([parse_euid(t, 'Team') for t in e['teams']])) or 'none'
+    return req, lambda e : 'Owned lists: %s\nTeams: %s' % (lists(e), teams(e))
+
 @web_req("unshare list")
 def unshare_list(user, list_id, unshare_with, read_only = True):
     l = List(list_id)